@@ -0,0 +1,92 @@
+//! 批注附注：从单独的批注文件按章节序号读入评注，在写出前并入对应章节的正文，
+//! 重新抓取、重新生成输出文件时批注随之保留
+//!
+//! 批注文件为 TOML，键为章节序号（从1开始，同日志中的序号），值为批注文本，多行文本每行成为一段：
+//!
+//! ```toml
+//! 12 = "这里埋下了第三卷的伏笔"
+//! 30 = """
+//! 读书会讨论记录：
+//! 主角的选择与第一章呼应
+//! """
+//! ```
+
+use crate::pipeline::Transform;
+use crate::{AnnotationStyle, ChapterResult};
+use std::collections::BTreeMap;
+use tracing::info;
+
+/// 批注第一段的前缀，与正文区分开
+const ANNOTATION_LABEL: &str = "【批注】";
+
+pub(crate) struct Annotations {
+    /// 章节序号（从0开始）-> 批注段落
+    notes: BTreeMap<usize, Vec<String>>,
+    style: AnnotationStyle,
+    applied: usize,
+}
+
+impl Annotations {
+    /// path 为空时返回 None
+    pub(crate) fn load(path: &str, style: AnnotationStyle) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if path.is_empty() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(path).map_err(|e| format!("无法读取批注文件 {}: {}", path, e))?;
+        let table: BTreeMap<String, String> = toml::from_str(&text).map_err(|e| format!("批注文件解析失败 {}: {}", path, e))?;
+        let mut notes = BTreeMap::new();
+        for (key, text) in table {
+            let index = key.trim().parse::<usize>().ok().filter(|&index| index > 0)
+                .ok_or_else(|| format!("批注文件 {} 中的键必须是从1开始的章节序号: {}", path, key))?;
+            let mut paragraphs: Vec<String> = text.lines().map(str::trim).filter(|line| !line.is_empty()).map(str::to_string).collect();
+            if let Some(first) = paragraphs.first_mut() {
+                first.insert_str(0, ANNOTATION_LABEL);
+                notes.insert(index - 1, paragraphs);
+            }
+        }
+        info!("已加载批注文件 {}: {} 章有批注", path, notes.len());
+        Ok(Some(Annotations { notes, style, applied: 0 }))
+    }
+}
+
+impl Transform for Annotations {
+    fn summary(&self) -> Option<String> {
+        // 同一序号的章节可能出现不止一次（如目录中的重复章节未移除），applied 可能超过批注条数
+        let missing = self.notes.len().saturating_sub(self.applied);
+        Some(if missing > 0 {
+            format!("批注已并入 {} 章，{} 条批注对应的章节未抓到或不在本次输出中", self.applied, missing)
+        } else {
+            format!("批注已并入 {} 章", self.applied)
+        })
+    }
+
+    fn apply(&mut self, result: &mut ChapterResult) {
+        let Some(paragraphs) = self.notes.get(&result.index) else {
+            return;
+        };
+        // 批注以前缀区分，不借用 note_separator：那是站点作者附言的标记
+        match self.style {
+            AnnotationStyle::Callout => {
+                result.content.splice(0..0, paragraphs.iter().cloned());
+            }
+            AnnotationStyle::Footnote => result.content.extend(paragraphs.iter().cloned()),
+        }
+        self.applied += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_when_a_chapter_is_annotated_twice() {
+        let mut annotations = Annotations { notes: BTreeMap::from([(0, vec!["【批注】伏笔".to_string()])]), style: AnnotationStyle::Footnote, applied: 0 };
+        for _ in 0..2 {
+            let mut result = ChapterResult::success(0, "第1章".into(), "https://example.com/1.html".into(), vec!["正文".into()], 0, chrono::Utc::now());
+            annotations.apply(&mut result);
+            assert_eq!(result.content, ["正文", "【批注】伏笔"]);
+        }
+        assert_eq!(annotations.summary().unwrap(), "批注已并入 2 章");
+    }
+}
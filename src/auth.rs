@@ -0,0 +1,180 @@
+//! 登录会话：需要登录才能阅读后续章节的站点，可以预置浏览器中复制的 Cookie、导入 cookies.txt，
+//! 或在抓取前提交一次登录表单。所有请求共用同一个 Cookie 容器，登录后站点下发的会话 Cookie 对之后的请求都有效
+
+use crate::{AuthConfig, HttpConfig, Identity, PresetCookies, read_html};
+use reqwest::cookie::Jar;
+use std::error::Error;
+use std::sync::Arc;
+use tracing::info;
+
+/// 配置了 [auth] 时创建共享的 Cookie 容器，预置 [http.cookies]、cookie 字符串和 cookies.txt 中的 Cookie
+pub(crate) fn session_jar(auth: &AuthConfig, http: &HttpConfig, catalog_url: &str) -> Result<Option<Arc<Jar>>, Box<dyn Error>> {
+    if !auth.enabled() {
+        return Ok(None);
+    }
+    let jar = PresetCookies(http.cookies.clone()).seeded_jar();
+    if !auth.cookie.is_empty() {
+        let url = reqwest::Url::parse(catalog_url)?;
+        let host = url.host_str().ok_or("目录页地址缺少域名")?;
+        for pair in auth.cookie.split(';').map(str::trim).filter(|pair| !pair.is_empty()) {
+            jar.add_cookie_str(&format!("{}; Domain={}; Path=/", pair, host), &url);
+        }
+    }
+    if !auth.cookies_file.is_empty() {
+        let text = std::fs::read_to_string(&auth.cookies_file).map_err(|e| format!("无法读取 Cookie 文件 {}: {}", auth.cookies_file, e))?;
+        let count = load_cookies_txt(&text, &jar).map_err(|e| format!("Cookie 文件 {} {}", auth.cookies_file, e))?;
+        info!("已从 {} 导入 {} 个 Cookie", auth.cookies_file, count);
+    }
+    Ok(Some(Arc::new(jar)))
+}
+
+/// 解析浏览器扩展导出的 Netscape 格式 cookies.txt：
+/// 域名、是否含子域名、路径、仅 HTTPS、过期时间、名称、值，以制表符分隔；已过期的跳过，格式有误的行报错
+fn load_cookies_txt(text: &str, jar: &Jar) -> Result<usize, String> {
+    let now = chrono::Utc::now().timestamp();
+    let mut count = 0;
+    for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+        // curl 等工具用 #HttpOnly_ 前缀标记 HttpOnly Cookie，其余 # 开头的是注释
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+        let [domain, subdomains, path, secure, expires, name, value] = fields[..] else {
+            return Err(format!("第 {} 行应有 7 个以制表符分隔的字段，实际为 {} 个", number, fields.len()));
+        };
+        let expires: i64 = expires.parse().map_err(|_| format!("第 {} 行的过期时间不是数字: {}", number, expires))?;
+        if expires != 0 && expires < now {
+            continue;
+        }
+        let host = domain.trim_start_matches('.');
+        let secure = secure.eq_ignore_ascii_case("TRUE");
+        let url = reqwest::Url::parse(&format!("{}://{}{}", if secure { "https" } else { "http" }, host, path))
+            .map_err(|e| format!("第 {} 行的域名或路径无效 ({}{}): {}", number, domain, path, e))?;
+        let mut cookie = format!("{}={}; Path={}", name, value, path);
+        if subdomains.eq_ignore_ascii_case("TRUE") {
+            cookie.push_str(&format!("; Domain={}", host));
+        }
+        if secure {
+            cookie.push_str("; Secure");
+        }
+        jar.add_cookie_str(&cookie, &url);
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// 登录页中包含密码输入框的表单：提交地址和其中的隐藏字段（常见的 CSRF 令牌）
+fn login_form(html: &str, page_url: &reqwest::Url, password_field: &str) -> Option<(reqwest::Url, Vec<(String, String)>)> {
+    let document = scraper::Html::parse_document(html);
+    let form_sel = scraper::Selector::parse("form").unwrap();
+    let input_sel = scraper::Selector::parse("input[name]").unwrap();
+    let form = document.select(&form_sel)
+        .find(|form| form.select(&input_sel).any(|input| input.value().attr("name") == Some(password_field)))?;
+    let action = form.value().attr("action").filter(|action| !action.is_empty()).and_then(|action| page_url.join(action).ok());
+    let hidden = form.select(&input_sel)
+        .filter(|input| input.value().attr("type").is_some_and(|t| t.eq_ignore_ascii_case("hidden")))
+        .filter_map(|input| Some((input.value().attr("name")?.to_string(), input.value().attr("value").unwrap_or_default().to_string())))
+        .collect();
+    Some((action.unwrap_or_else(|| page_url.clone()), hidden))
+}
+
+/// 抓取前提交登录表单：先打开登录页取得表单中的隐藏字段，再连同用户名、密码和 extra_fields 一起提交
+pub(crate) async fn login(auth: &AuthConfig, identity: &Identity) -> Result<(), Box<dyn Error>> {
+    let password = if auth.password_env.is_empty() {
+        auth.password.clone()
+    } else {
+        std::env::var(&auth.password_env).map_err(|_| format!("未设置环境变量 {}，无法读取登录密码", auth.password_env))?
+    };
+    info!("正在登录: {}", auth.login_url);
+    identity.throttle(&auth.login_url).await;
+    let resp = identity.get(&auth.login_url).send().await?;
+    let page_url = resp.url().clone();
+    let html = read_html(resp).await?;
+    let (action, mut fields) = match login_form(&html, &page_url, &auth.password_field) {
+        Some(form) => form,
+        None => {
+            info!("登录页中未找到含 {} 输入框的表单，直接提交到 {}", auth.password_field, page_url);
+            (page_url.clone(), Vec::new())
+        }
+    };
+    fields.retain(|(name, _)| *name != auth.username_field && *name != auth.password_field && !auth.extra_fields.contains_key(name));
+    fields.push((auth.username_field.clone(), auth.username.clone()));
+    fields.push((auth.password_field.clone(), password));
+    fields.extend(auth.extra_fields.iter().map(|(name, value)| (name.clone(), value.clone())));
+
+    identity.throttle(action.as_str()).await;
+    let resp = identity.post_form(action.as_str(), &fields).header("Referer", page_url.as_str()).send().await?;
+    let status = resp.status();
+    let landed = resp.url().clone();
+    let body = read_html(resp).await?;
+    if !status.is_success() {
+        return Err(format!("登录失败: {} 返回 HTTP {}", action, status).into());
+    }
+    if !auth.failure_marker.is_empty() && body.contains(&auth.failure_marker) {
+        return Err(format!("登录失败: 响应中出现 \"{}\"，请检查用户名和密码", auth.failure_marker).into());
+    }
+    info!("登录完成 (HTTP {})，跳转到 {}", status.as_u16(), landed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::cookie::CookieStore;
+
+    /// 容器中 Cookie 的顺序不固定，排序后比较
+    fn cookies(jar: &Jar, url: &str) -> String {
+        let header = jar.cookies(&reqwest::Url::parse(url).unwrap()).map(|v| v.to_str().unwrap().to_string()).unwrap_or_default();
+        let mut pairs: Vec<&str> = header.split("; ").filter(|pair| !pair.is_empty()).collect();
+        pairs.sort_unstable();
+        pairs.join("; ")
+    }
+
+    #[test]
+    fn cookies_txt_lines() {
+        let text = "\
+# Netscape HTTP Cookie File
+# 注释行
+
+.example.com\tTRUE\t/\tFALSE\t0\tsession\tabc
+#HttpOnly_www.example.com\tFALSE\t/\tTRUE\t4102444800\ttoken\txyz\r
+www.example.com\tFALSE\t/\tFALSE\t1000000000\texpired\told
+";
+        let jar = Jar::default();
+        assert_eq!(load_cookies_txt(text, &jar), Ok(2));
+        assert_eq!(cookies(&jar, "https://www.example.com/book/"), "session=abc; token=xyz");
+        assert_eq!(cookies(&jar, "http://m.example.com/"), "session=abc");
+        // 仅 HTTPS 的 Cookie 不随 http 请求发送
+        assert_eq!(cookies(&jar, "http://www.example.com/"), "session=abc");
+    }
+
+    #[test]
+    fn malformed_cookies_txt_lines_are_errors() {
+        let jar = Jar::default();
+        let error = load_cookies_txt("# ok\n.example.com TRUE / FALSE 0 a b\n", &jar).unwrap_err();
+        assert!(error.contains("第 2 行") && error.contains("实际为 1 个"), "{}", error);
+        let error = load_cookies_txt(".example.com\tTRUE\t/\tFALSE\tnever\ta\tb", &jar).unwrap_err();
+        assert!(error.contains("过期时间"), "{}", error);
+        assert!(load_cookies_txt("exa mple.com\tTRUE\t/\tFALSE\t0\ta\tb", &jar).is_err());
+    }
+
+    #[test]
+    fn login_form_finds_password_form_and_hidden_fields() {
+        let page = reqwest::Url::parse("https://example.com/user/login").unwrap();
+        let html = r#"<form action="/search"><input name="q"></form>
+            <form action="/user/do_login" method="post">
+              <input type="hidden" name="csrf" value="t0k">
+              <input type="HIDDEN" name="from">
+              <input name="username"><input type="password" name="password">
+            </form>"#;
+        let (action, hidden) = login_form(html, &page, "password").unwrap();
+        assert_eq!(action.as_str(), "https://example.com/user/do_login");
+        assert_eq!(hidden, [("csrf".to_string(), "t0k".to_string()), ("from".to_string(), String::new())]);
+
+        let (action, hidden) = login_form(r#"<form><input name="pwd"></form>"#, &page, "pwd").unwrap();
+        assert_eq!(action, page);
+        assert!(hidden.is_empty());
+        assert!(login_form(html, &page, "pwd").is_none());
+    }
+}
@@ -0,0 +1,154 @@
+//! 章节页的 HTTP 响应缓存：按地址保存页面和 ETag / Last-Modified，下次抓取时带上 If-None-Match / If-Modified-Since，
+//! 站点返回 304 时直接使用缓存的页面，重新抓取整本书时只有更新过的章节才真正下载
+//!
+//! 只缓存带有校验头的响应，没有校验头就无法判断页面是否变化。缓存按地址的 md5 命名、gzip 压缩保存，不会被自动清理。
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use reqwest::header::{ETAG, HeaderMap, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// 为临时文件编号
+static PART_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CachedPage {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// 保存时所用 HTML 预过滤规则的标识，未过滤时为空；与本次不同则缓存的页面可能缺少需要的元素，不再使用
+    filter: String,
+    pub(crate) html: String,
+}
+
+pub(crate) struct ResponseCache {
+    dir: PathBuf,
+    /// 站点返回 304、使用了缓存的页面数
+    revalidated: AtomicUsize,
+    stored: AtomicUsize,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        ResponseCache { dir, revalidated: AtomicUsize::new(0), stored: AtomicUsize::new(0) }
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        let hash = format!("{:x}", md5::compute(url));
+        self.dir.join(&hash[..2]).join(format!("{}.json.gz", hash))
+    }
+
+    /// 取出地址对应的缓存；读取失败、地址或过滤规则不一致时视为没有缓存
+    pub(crate) fn get(&self, url: &str, filter: &str) -> Option<CachedPage> {
+        let mut json = Vec::new();
+        GzDecoder::new(std::fs::File::open(self.path(url)).ok()?).read_to_end(&mut json).ok()?;
+        let page: CachedPage = serde_json::from_slice(&json).ok()?;
+        (page.url == url && page.filter == filter).then_some(page)
+    }
+
+    /// 按缓存的校验头给请求加上条件
+    pub(crate) fn conditional(page: &CachedPage, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(etag) = &page.etag {
+            request = request.header(IF_NONE_MATCH, etag.as_str());
+        }
+        if let Some(last_modified) = &page.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+        }
+        request
+    }
+
+    pub(crate) fn record_hit(&self) {
+        self.revalidated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 保存页面，响应没有 ETag 和 Last-Modified 时不保存
+    pub(crate) fn store(&self, url: &str, headers: &HeaderMap, filter: &str, html: &str) -> io::Result<()> {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+        let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+        if etag.is_none() && last_modified.is_none() {
+            return Ok(());
+        }
+        let page = CachedPage { url: url.to_string(), etag, last_modified, filter: filter.to_string(), html: html.to_string() };
+        let path = self.path(url);
+        std::fs::create_dir_all(path.parent().unwrap_or(&self.dir))?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&serde_json::to_vec(&page)?)?;
+        // 先写临时文件再改名，中途退出不会留下半个文件；临时文件名各不相同，
+        // 同一地址被并发保存或两个进程共用缓存目录时不会互相覆盖写了一半的文件
+        let part = path.with_extension(format!("{}.{}.part", std::process::id(), PART_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        std::fs::write(&part, encoder.finish()?)?;
+        std::fs::rename(&part, &path)?;
+        self.stored.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// (使用缓存的页面数, 新保存或更新的页面数)
+    pub(crate) fn stats(&self) -> (usize, usize) {
+        (self.revalidated.load(Ordering::Relaxed), self.stored.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(etag: Option<&'static str>, last_modified: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = etag {
+            headers.insert(ETAG, HeaderValue::from_static(etag));
+        }
+        if let Some(last_modified) = last_modified {
+            headers.insert(LAST_MODIFIED, HeaderValue::from_static(last_modified));
+        }
+        headers
+    }
+
+    #[test]
+    fn store_and_get_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rust_crawler_cache_{}", std::process::id()));
+        let cache = ResponseCache::new(dir.clone());
+        let url = "https://example.com/1.html";
+        assert!(cache.get(url, "").is_none());
+
+        cache.store(url, &headers(Some("\"v1\""), None), "", "<p>旧</p>").unwrap();
+        let page = cache.get(url, "").unwrap();
+        assert_eq!((page.html.as_str(), page.etag.as_deref(), page.last_modified.as_deref()), ("<p>旧</p>", Some("\"v1\""), None));
+        // 其他地址恰好落在同一文件、或预过滤规则变了时不使用
+        assert!(cache.get("https://example.com/2.html", "").is_none());
+        assert!(cache.get(url, "filtered").is_none());
+
+        let request = ResponseCache::conditional(&page, reqwest::Client::new().get(url)).build().unwrap();
+        assert_eq!(request.headers()[IF_NONE_MATCH], "\"v1\"");
+        assert!(!request.headers().contains_key(IF_MODIFIED_SINCE));
+
+        // 页面更新后的响应替换掉过期的缓存
+        cache.store(url, &headers(None, Some("Wed, 01 Jan 2026 00:00:00 GMT")), "", "<p>新</p>").unwrap();
+        let page = cache.get(url, "").unwrap();
+        assert_eq!((page.html.as_str(), page.etag.as_deref()), ("<p>新</p>", None));
+        let request = ResponseCache::conditional(&page, reqwest::Client::new().get(url)).build().unwrap();
+        assert_eq!(request.headers()[IF_MODIFIED_SINCE], "Wed, 01 Jan 2026 00:00:00 GMT");
+
+        // 没有校验头的响应不保存
+        cache.store("https://example.com/3.html", &HeaderMap::new(), "", "<p></p>").unwrap();
+        assert!(cache.get("https://example.com/3.html", "").is_none());
+        assert_eq!(cache.stats(), (0, 2));
+
+        let leftovers: Vec<_> = walk(&dir).into_iter().filter(|path| path.extension().is_some_and(|ext| ext == "part")).collect();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn walk(dir: &std::path::Path) -> Vec<PathBuf> {
+        std::fs::read_dir(dir).unwrap()
+            .flat_map(|entry| {
+                let path = entry.unwrap().path();
+                if path.is_dir() { walk(&path) } else { vec![path] }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,291 @@
+//! 断点文件：抓取过程中随结果到达记录每章状态与内容，中断后可用 `--resume` 只补抓缺失的章节
+//!
+//! 文件按行存储：第一行是打开时的完整状态，之后每抓完一章在末尾追加一行该章的记录，写盘量与章节数成正比。
+//! 读取时后面的记录覆盖前面的，再把合并后的状态重写为一行，文件不会随续抓次数越来越长。
+//! 被杀掉时末尾可能留下半行，读取时忽略。
+
+use crate::{ChapterResult, output_path, result_status};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// 追加的记录先在内存中缓冲，至多隔这么久落盘一次；crawl.state_save_every 另外限制缓冲的章节数
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize)]
+struct ChapterState {
+    url: String,
+    status: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    content: Vec<String>,
+    /// 正文在转存目录中，这里不重复保存
+    #[serde(default)]
+    spilled: bool,
+    /// 连续几次运行都抓取失败，成功后清零
+    #[serde(default, skip_serializing_if = "is_zero")]
+    failed_runs: u32,
+    /// 退避结束的时间（RFC 3339），续抓时在此之前不再重试
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_at: Option<String>,
+}
+
+/// 追加在文件末尾的一章记录
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    index: usize,
+    #[serde(flatten)]
+    chapter: ChapterState,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CrawlState {
+    catalog_url: String,
+    /// 章节序号（从 0 开始）-> 状态
+    chapters: BTreeMap<usize, ChapterState>,
+}
+
+pub(crate) struct Checkpoint {
+    state: CrawlState,
+    /// 文件打开失败时为 None，只在内存中记录
+    log: Option<BufWriter<File>>,
+    last_saved: Instant,
+    /// 缓冲中的记录满这么多章就落盘，为 0 时只按时间间隔落盘
+    save_every: usize,
+    unsaved: usize,
+    /// 失败章节的退避基数和上限（秒），基数为 0 时不退避
+    backoff: (u64, u64),
+}
+
+impl Checkpoint {
+    /// resume 为 false 时从空状态开始，否则读取已有断点文件；目录地址不一致的断点文件不会被使用
+    pub(crate) fn open(path: &str, catalog_url: &str, resume: bool) -> Self {
+        let fresh = CrawlState { catalog_url: catalog_url.to_string(), chapters: BTreeMap::new() };
+        let state = if !resume {
+            fresh
+        } else {
+            match std::fs::read_to_string(output_path(path)) {
+                Ok(text) => match parse(&text) {
+                    Ok(state) if state.catalog_url == catalog_url => state,
+                    Ok(_) => {
+                        info!("断点文件属于另一个目录页，忽略并重新抓取: {}", path);
+                        fresh
+                    }
+                    Err(e) => {
+                        warn!("断点文件解析失败，重新抓取: {}", e);
+                        fresh
+                    }
+                },
+                Err(e) => {
+                    info!("无法读取断点文件，重新抓取: {} ({})", path, e);
+                    fresh
+                }
+            }
+        };
+        let log = compact(path, &state)
+            .inspect_err(|e| warn!("断点文件写入失败，本次不保存进度: {} ({})", path, e))
+            .ok();
+        Checkpoint { state, log, last_saved: Instant::now(), save_every: 0, unsaved: 0, backoff: (0, 0) }
+    }
+
+    /// 每记录 chapters 章落盘一次，进程被强行杀掉时最多丢失这么多章的进度
+    pub(crate) fn with_save_every(mut self, chapters: usize) -> Self {
+        self.save_every = chapters;
+        self
+    }
+
+    /// 章节每多一次运行抓取失败，下次续抓前的等待时间加倍：base、2×base、4×base……不超过 max
+    pub(crate) fn with_backoff(mut self, base_secs: u64, max_secs: u64) -> Self {
+        self.backoff = (base_secs, max_secs);
+        self
+    }
+
+    /// 该章上次抓取时是软 404（站点表示章节不存在）
+    pub(crate) fn not_found(&self, index: usize, url: &str) -> bool {
+        self.state.chapters.get(&index).is_some_and(|chapter| chapter.url == url && chapter.status == "not_found")
+    }
+
+    /// 该章上次失败且仍在退避期内时，返回连续失败次数和退避结束的时间
+    pub(crate) fn backing_off(&self, index: usize, url: &str) -> Option<(u32, DateTime<Utc>)> {
+        let chapter = self.state.chapters.get(&index)?;
+        if chapter.url != url || chapter.status != "failed" {
+            return None;
+        }
+        let retry_at = DateTime::parse_from_rfc3339(chapter.retry_at.as_deref()?).ok()?.with_timezone(&Utc);
+        (retry_at > Utc::now()).then_some((chapter.failed_runs, retry_at))
+    }
+
+    /// 该章在断点文件中已抓取成功且地址未变时，还原出抓取结果
+    pub(crate) fn restore(&self, index: usize, url: &str) -> Option<ChapterResult> {
+        let chapter = self.state.chapters.get(&index)?;
+        if chapter.url != url || !matches!(chapter.status.as_str(), "success" | "partial") {
+            return None;
+        }
+        let mut result = ChapterResult::success(index, chapter.title.clone(), chapter.url.clone(), chapter.content.clone(), 0, chrono::Utc::now());
+        result.partial = chapter.status == "partial";
+        result.resumed = true;
+        result.spilled = chapter.spilled;
+        Some(result)
+    }
+
+    /// 记录一章的结果，缓冲的章节数达到 save_every 或距上次写盘超过间隔时顺带落盘
+    pub(crate) fn record(&mut self, result: &ChapterResult) {
+        let status = result_status(result);
+        let (title, content) = if result.success { (result.title.clone(), result.content.clone()) } else { (String::new(), Vec::new()) };
+        let (failed_runs, retry_at) = if status == "failed" && self.backoff.0 > 0 {
+            let previous = self.state.chapters.get(&result.index)
+                .filter(|chapter| chapter.url == result.url)
+                .map_or(0, |chapter| chapter.failed_runs);
+            let failed_runs = previous + 1;
+            let (base, max) = self.backoff;
+            let delay = base.saturating_mul(1u64 << (failed_runs - 1).min(32)).min(max.max(base));
+            let retry_at = i64::try_from(delay).ok()
+                .and_then(chrono::Duration::try_seconds)
+                .and_then(|delay| Utc::now().checked_add_signed(delay))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC);
+            (failed_runs, Some(retry_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)))
+        } else {
+            (0, None)
+        };
+        let entry = Entry {
+            index: result.index,
+            chapter: ChapterState {
+                url: result.url.clone(),
+                status: status.to_string(),
+                title,
+                content,
+                spilled: result.spilled,
+                failed_runs,
+                retry_at,
+            },
+        };
+        if let Some(log) = self.log.as_mut() {
+            let appended = serde_json::to_writer(&mut *log, &entry)
+                .map_err(std::io::Error::other)
+                .and_then(|_| log.write_all(b"\n"));
+            if let Err(e) = appended {
+                warn!("断点文件写入失败: {}", e);
+            }
+        }
+        self.state.chapters.insert(entry.index, entry.chapter);
+        self.unsaved += 1;
+        if (self.save_every > 0 && self.unsaved >= self.save_every) || self.last_saved.elapsed() >= SAVE_INTERVAL {
+            self.save();
+        }
+    }
+
+    /// 把缓冲中的记录写入文件
+    pub(crate) fn save(&mut self) {
+        if let Some(log) = self.log.as_mut() && let Err(e) = log.flush() {
+            warn!("断点文件写入失败: {}", e);
+        }
+        self.last_saved = Instant::now();
+        self.unsaved = 0;
+    }
+}
+
+/// 第一行为完整状态，之后每行一章记录，后出现的覆盖先出现的；不完整的行忽略
+fn parse(text: &str) -> Result<CrawlState, serde_json::Error> {
+    let mut lines = text.lines();
+    let mut state: CrawlState = serde_json::from_str(lines.next().unwrap_or_default())?;
+    let mut skipped = 0;
+    for line in lines.filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str::<Entry>(line) {
+            Ok(entry) => {
+                state.chapters.insert(entry.index, entry.chapter);
+            }
+            Err(_) => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        warn!("断点文件中有 {} 行记录不完整，已忽略", skipped);
+    }
+    Ok(state)
+}
+
+/// 把状态重写为一行（先写临时文件再重命名，中途被杀掉也不会留下半截的断点文件），返回追加记录用的文件
+fn compact(path: &str, state: &CrawlState) -> std::io::Result<BufWriter<File>> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut json = serde_json::to_vec(state).map_err(std::io::Error::other)?;
+    json.push(b'\n');
+    std::fs::write(output_path(&tmp_path), json)?;
+    std::fs::rename(output_path(&tmp_path), output_path(path))?;
+    let file = std::fs::OpenOptions::new().append(true).open(output_path(path))?;
+    Ok(BufWriter::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("rust_crawler_checkpoint_{}_{}.json", name, std::process::id())).to_string_lossy().into_owned()
+    }
+
+    fn chapter(index: usize, content: &str) -> ChapterResult {
+        let url = format!("https://example.com/{}.html", index);
+        ChapterResult::success(index, format!("第{}章", index + 1), url, vec![content.to_string()], 0, Utc::now())
+    }
+
+    #[test]
+    fn records_are_appended_and_merged_on_resume() {
+        let path = temp_path("append");
+        let catalog = "https://example.com/book/";
+        let mut checkpoint = Checkpoint::open(&path, catalog, false);
+        checkpoint.record(&chapter(0, "旧正文"));
+        checkpoint.record(&chapter(1, "第二章"));
+        checkpoint.record(&chapter(0, "新正文"));
+        checkpoint.save();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 4);
+        // 每章的正文只随它自己的记录写入一次
+        assert_eq!(text.matches("第二章\"").count(), 1);
+
+        // 被杀掉时留下的半行
+        std::fs::write(&path, format!("{}{{\"index\":2,\"url\":", text)).unwrap();
+        let checkpoint = Checkpoint::open(&path, catalog, true);
+        assert_eq!(checkpoint.restore(0, "https://example.com/0.html").unwrap().content, ["新正文"]);
+        assert_eq!(checkpoint.restore(1, "https://example.com/1.html").unwrap().content, ["第二章"]);
+        assert!(checkpoint.restore(2, "https://example.com/2.html").is_none());
+        drop(checkpoint);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn saves_after_every_n_chapters() {
+        let path = temp_path("save_every");
+        let mut checkpoint = Checkpoint::open(&path, "https://example.com/book/", false).with_save_every(2);
+        let lines = || std::fs::read_to_string(&path).unwrap().lines().count();
+        checkpoint.record(&chapter(0, "一"));
+        assert_eq!(lines(), 1);
+        checkpoint.record(&chapter(1, "二"));
+        assert_eq!(lines(), 3);
+        checkpoint.record(&chapter(2, "三"));
+        assert_eq!(lines(), 3);
+        checkpoint.record(&chapter(3, "四"));
+        assert_eq!(lines(), 5);
+        drop(checkpoint);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_single_object_state_file() {
+        let path = temp_path("legacy");
+        let legacy = r#"{"catalog_url":"https://example.com/book/","chapters":{"0":{"url":"https://example.com/0.html","status":"success","title":"第1章","content":["正文"]}}}"#;
+        std::fs::write(&path, legacy).unwrap();
+        let checkpoint = Checkpoint::open(&path, "https://example.com/book/", true);
+        assert_eq!(checkpoint.restore(0, "https://example.com/0.html").unwrap().content, ["正文"]);
+        assert!(Checkpoint::open(&path, "https://example.com/other/", true).restore(0, "https://example.com/0.html").is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1498 @@
+//! 配置：config.toml 各段对应的结构和默认值，加载时合并命令行覆盖项、站点配置和批量模式的各本书
+
+use crate::{CHROME_USER_AGENTS, FIREFOX_USER_AGENTS, SAFARI_USER_AGENTS, host_matches, init_log_clock, library, logging};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+const DEFAULT_CONCURRENT_LIMIT: usize = 15;
+const DEFAULT_RAMP_UP_INITIAL: usize = 2;
+const DEFAULT_RAMP_UP_SECS: u64 = 60;
+const DEFAULT_MAX_RETRIES: usize = 2;
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 1000;
+const DEFAULT_INJECTED_MIN_CHAPTERS: usize = 5;
+const DEFAULT_ROTATE_EVERY: usize = 50;
+const DEFAULT_POOL_SIZE: usize = 4;
+const DEFAULT_FORUM_MAX_PAGES: usize = 500;
+const DEFAULT_CATALOG_MAX_PAGES: usize = 100;
+const DEFAULT_DWELL_MIN_MS: u64 = 8000;
+const DEFAULT_DWELL_MAX_MS: u64 = 30000;
+const DEFAULT_CATALOG_REVISIT_CHANCE: f64 = 0.05;
+const DEFAULT_MAX_ASSETS: usize = 3;
+const DEFAULT_PROXY_MAX_FAILURES: usize = 3;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_BASE_URL: &str = "https://www.alicesw.com/";
+const DEFAULT_CATALOG_URL: &str = "https://www.alicesw.com/other/chapters/id/47686.html";
+pub(crate) const DEFAULT_TIME_FORMAT: &str = "[%H:%M:%S]";
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_OUTPUT_FILE: &str = "output.txt";
+const DEFAULT_STATE_FILE: &str = ".crawl_state.json";
+const DEFAULT_STATE_SAVE_EVERY: usize = 10;
+const DEFAULT_FAILURES_FILE: &str = "failures.json";
+const DEFAULT_RESUME_BACKOFF_MAX_SECS: u64 = 7 * 24 * 3600;
+const DEFAULT_BOOK_LANGUAGE: &str = "zh-CN";
+const DEFAULT_NOTE_SEPARATOR: &str = "【作者的话】";
+const DEFAULT_USERNAME_FIELD: &str = "username";
+const DEFAULT_PASSWORD_FIELD: &str = "password";
+const DEFAULT_TITLE_SELECTOR: &str = ".j_chapterName";
+const DEFAULT_CONTENT_SELECTOR: &str = ".read-content p";
+const DEFAULT_CHAPTER_LINK_SELECTOR: &str = ".mulu_list li a";
+
+/// 完整配置，字段与 config.toml 中的各段一一对应，可用 `toml::from_str` 从配置文本得到
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub(crate) crawl: CrawlConfig,
+    #[serde(default)]
+    pub(crate) urls: UrlsConfig,
+    #[serde(default)]
+    pub(crate) selectors: SelectorsConfig,
+    #[serde(default)]
+    pub(crate) output: OutputConfig,
+    #[serde(default)]
+    pub(crate) clean: CleanConfig,
+    #[serde(default)]
+    pub(crate) identity: IdentityConfig,
+    #[serde(default)]
+    pub(crate) forum: ForumConfig,
+    #[serde(default)]
+    pub(crate) http: HttpConfig,
+    #[serde(default)]
+    pub(crate) human: HumanConfig,
+    #[serde(default)]
+    pub(crate) quality: QualityConfig,
+    #[serde(default)]
+    pub(crate) log: LogConfig,
+    #[serde(default)]
+    pub(crate) signing: SigningConfig,
+    /// 域名 -> 只作用于该域名（及其子域名）请求的覆盖项
+    #[serde(default)]
+    pub(crate) hosts: BTreeMap<String, HostConfig>,
+    #[serde(default)]
+    pub(crate) auth: AuthConfig,
+    #[serde(default)]
+    pub(crate) batch: BatchConfig,
+    /// 批量模式下每本书的完整配置，由 [[books]] 中的各项分别合并到根配置上得到
+    #[serde(skip)]
+    pub(crate) books: Vec<Config>,
+}
+
+impl UrlsConfig {
+    /// 用户给出的入口地址：配置了书籍主页时为书籍主页，否则为目录页
+    pub(crate) fn entry_url(&self) -> &str {
+        if self.book_url.is_empty() { &self.catalog_url } else { &self.book_url }
+    }
+}
+
+impl Config {
+    /// 实际使用的并发数，拟人模式下固定为1以便按阅读顺序逐章访问
+    pub(crate) fn concurrent_limit(&self) -> usize {
+        if self.human.enabled { 1 } else { self.crawl.concurrent_limit }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CrawlConfig {
+    #[serde(default = "default_concurrent_limit")]
+    pub(crate) concurrent_limit: usize,
+    #[serde(default = "default_ramp_up_initial")]
+    pub(crate) ramp_up_initial: usize,
+    #[serde(default = "default_ramp_up_secs")]
+    pub(crate) ramp_up_secs: u64,
+    #[serde(default)]
+    pub(crate) paywall_markers: Vec<String>,
+    /// 站点对不存在的章节返回 200 和提示页（软 404）时，页面中的提示语
+    #[serde(default = "default_soft_404_markers")]
+    pub(crate) soft_404_markers: Vec<String>,
+    #[serde(default)]
+    pub(crate) min_paragraphs: usize,
+    #[serde(default)]
+    pub(crate) accept_partial: bool,
+    #[serde(default = "default_max_retries")]
+    pub(crate) max_retries: usize,
+    #[serde(default = "default_retry_backoff_ms")]
+    pub(crate) retry_backoff_ms: u64,
+    #[serde(default)]
+    pub(crate) stdin_control: bool,
+    #[serde(default)]
+    pub(crate) abort_after_consecutive_failures: usize,
+    #[serde(default)]
+    pub(crate) min_delay_ms: u64,
+    #[serde(default)]
+    pub(crate) max_delay_ms: u64,
+    #[serde(default = "default_state_file")]
+    pub(crate) state_file: String,
+    /// 断点文件每记录这么多章落盘一次，为 0 时只按时间间隔（2 秒）落盘
+    #[serde(default = "default_state_save_every")]
+    pub(crate) state_save_every: usize,
+    #[serde(default)]
+    pub(crate) resume: bool,
+    /// 失败章节列表文件，非空时从断点文件恢复已抓章节，只重新抓取列表中的地址
+    #[serde(default)]
+    pub(crate) retry_failures: String,
+    /// 断点续抓时连续失败章节的退避基数（秒），每多失败一次加倍，为 0 时每次都重试
+    #[serde(default)]
+    pub(crate) resume_backoff_secs: u64,
+    /// 退避时间的上限（秒）
+    #[serde(default = "default_resume_backoff_max_secs")]
+    pub(crate) resume_backoff_max_secs: u64,
+    /// 读取 robots.txt，跳过禁止抓取的章节并遵守 Crawl-delay
+    #[serde(default)]
+    pub(crate) respect_robots_txt: bool,
+    /// 只抓取目录中的第 start_chapter 至 end_chapter 章（从1开始，含两端），0 表示不限
+    #[serde(default)]
+    pub(crate) start_chapter: usize,
+    #[serde(default)]
+    pub(crate) end_chapter: usize,
+    /// 读取章节页时边下载边丢弃正文容器以外的标记，只对简单选择器生效
+    #[serde(default)]
+    pub(crate) prefilter_html: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct UrlsConfig {
+    #[serde(default = "default_base_url")]
+    pub(crate) base_url: String,
+    #[serde(default = "default_catalog_url")]
+    pub(crate) catalog_url: String,
+    /// 书籍主页，非空时从中找到目录页链接，代替 catalog_url
+    #[serde(default)]
+    pub(crate) book_url: String,
+    #[serde(default)]
+    pub(crate) book_id_pattern: String,
+    /// 目录分页地址模板，{page} 替换为页码，从第 2 页开始
+    #[serde(default)]
+    pub(crate) catalog_page_template: String,
+    #[serde(default = "default_catalog_max_pages")]
+    pub(crate) catalog_max_pages: usize,
+    /// 从章节地址中去掉的查询参数，以 * 结尾时按前缀匹配
+    #[serde(default)]
+    pub(crate) strip_query_params: Vec<String>,
+    /// 按地址中的数字排序章节，第一个捕获组为序号
+    #[serde(default)]
+    pub(crate) sort_key_pattern: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct SelectorsConfig {
+    #[serde(default = "default_title_selector")]
+    pub(crate) title_selector: String,
+    #[serde(default = "default_content_selector")]
+    pub(crate) content_selector: String,
+    #[serde(default = "default_chapter_link_selector")]
+    pub(crate) chapter_link_selector: String,
+    #[serde(default)]
+    pub(crate) note_selector: String,
+    #[serde(default)]
+    pub(crate) catalog_next_page_selector: String,
+    /// 书籍主页上指向目录页的链接，配置了 urls.book_url 时使用
+    #[serde(default)]
+    pub(crate) catalog_link_selector: String,
+    /// 目录页中的分卷标题，其后的章节链接归入该卷
+    #[serde(default)]
+    pub(crate) volume_selector: String,
+    /// 章节正文分成多页时的下一页链接
+    #[serde(default)]
+    pub(crate) content_next_page_selector: String,
+    /// 定位页面内嵌 JSON 状态的正则，第一个捕获组为 JSON 文本；非空时改用 JSON 指针提取标题和正文
+    #[serde(default)]
+    pub(crate) json_state_pattern: String,
+    #[serde(default)]
+    pub(crate) json_title_pointer: String,
+    #[serde(default)]
+    pub(crate) json_content_pointer: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct OutputConfig {
+    #[serde(default)]
+    pub(crate) format: OutputFormat,
+    #[serde(default = "default_output_file")]
+    pub(crate) file: String,
+    #[serde(default)]
+    pub(crate) fsync: FsyncPolicy,
+    #[serde(default)]
+    pub(crate) report_file: String,
+    /// 章节索引（每章的字节偏移和长度），只用于 txt 和 markdown 输出
+    #[serde(default)]
+    pub(crate) index_file: String,
+    /// 内容摘要文件，记录每章的哈希，下次运行时据此报告站点修订过的章节
+    #[serde(default)]
+    pub(crate) digest_file: String,
+    #[serde(default)]
+    pub(crate) digest_granularity: DigestGranularity,
+    /// 失败章节列表（序号、地址、原因），没有失败章节时不生成
+    #[serde(default = "default_failures_file")]
+    pub(crate) failures_file: String,
+    #[serde(default)]
+    pub(crate) timeline_file: String,
+    #[serde(default = "default_note_separator")]
+    pub(crate) note_separator: String,
+    #[serde(default)]
+    pub(crate) placeholders: bool,
+    /// 批注文件，按章节序号把评注并入输出
+    #[serde(default)]
+    pub(crate) annotations_file: String,
+    #[serde(default)]
+    pub(crate) annotation_style: AnnotationStyle,
+    /// 抓到的正文先转存到 <file>.chapters 目录，写出时再逐章读回
+    #[serde(default)]
+    pub(crate) spill: bool,
+    /// 按内容寻址的正文仓库目录，非空时同时开启转存，正文压缩后按哈希保存，内容相同的章节只存一份
+    #[serde(default)]
+    pub(crate) store_dir: String,
+    /// 以下为 EPUB 元数据（书名也用于 Markdown / HTML），书名为空时使用输出文件名
+    #[serde(default)]
+    pub(crate) title: String,
+    #[serde(default)]
+    pub(crate) author: String,
+    /// 题材、状态等标签，写入 EPUB 的 dc:subject
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+    #[serde(default = "default_book_language")]
+    pub(crate) language: String,
+    #[serde(default)]
+    pub(crate) cover: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputFormat {
+    /// 纯文本，每章标题一行，随后每段一行
+    #[default]
+    Txt,
+    /// 每章一个 XHTML 文件，带目录和封面，适合电子阅读器
+    Epub,
+    /// 章节标题为二级标题，段落之间空一行
+    Markdown,
+    /// 单个带样式的 HTML 文件，开头为可跳转的目录
+    Html,
+    /// 每章一条记录的 JSON 数组，含正文段落、耗时、时间和错误信息
+    Json,
+    /// 同 json，每行一条记录
+    Ndjson,
+}
+
+impl OutputFormat {
+    /// 未指定输出文件名时使用的默认文件名
+    pub(crate) fn default_file(self) -> &'static str {
+        match self {
+            OutputFormat::Txt => DEFAULT_OUTPUT_FILE,
+            OutputFormat::Epub => "output.epub",
+            OutputFormat::Markdown => "output.md",
+            OutputFormat::Html => "output.html",
+            OutputFormat::Json => "output.json",
+            OutputFormat::Ndjson => "output.ndjson",
+        }
+    }
+}
+
+/// 批注在章节中的位置
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AnnotationStyle {
+    /// 附在章节末尾
+    #[default]
+    Footnote,
+    /// 放在章节开头、正文之前
+    Callout,
+}
+
+/// 内容摘要的哈希粒度
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DigestGranularity {
+    /// 每章一个哈希，只能知道章节有无变化
+    #[default]
+    Chapter,
+    /// 另记录每段的哈希，能统计修改、新增、删除了几段
+    Paragraph,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum FsyncPolicy {
+    /// 不主动落盘，交给操作系统
+    #[default]
+    None,
+    /// 每写完一章执行一次 fsync
+    PerChapter,
+    /// 全部写完后执行一次 fsync
+    AtEnd,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CleanConfig {
+    #[serde(default)]
+    pub(crate) strip_injected: bool,
+    #[serde(default = "default_injected_min_chapters")]
+    pub(crate) injected_min_chapters: usize,
+    #[serde(default)]
+    pub(crate) dedupe_title: bool,
+    /// 写入前移除与前面章节正文相同的重复章节，检测结果总会在汇总中列出
+    #[serde(default)]
+    pub(crate) drop_duplicate_chapters: bool,
+    /// 标题相同也视为重复章节
+    #[serde(default)]
+    pub(crate) duplicate_titles: bool,
+    #[serde(default = "default_true")]
+    pub(crate) decode_entities: bool,
+    #[serde(default)]
+    pub(crate) empty_paragraphs: EmptyParagraphs,
+    #[serde(default = "default_scene_break_patterns")]
+    pub(crate) scene_break_patterns: Vec<String>,
+    #[serde(default)]
+    pub(crate) scene_break_marker: String,
+    /// 字符替换表文件，把站点混入的形近字还原为原字符
+    #[serde(default)]
+    pub(crate) substitutions_file: String,
+    /// 正则列表，段落中匹配的部分被删去，删完不剩文字的段落整段移除
+    #[serde(default)]
+    pub(crate) remove_patterns: Vec<String>,
+    /// 内置规则：移除含"一秒记住"一类站点广告语，或网址与"最新章节"等字样同时出现的短段落
+    #[serde(default)]
+    pub(crate) ad_heuristics: bool,
+    /// 正则列表，从目录页链接文字中删去匹配的片段，清理后的文字用作 EPUB / HTML 目录中的章节名
+    #[serde(default = "default_catalog_title_patterns")]
+    pub(crate) catalog_title_patterns: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EmptyParagraphs {
+    /// 删除所有空段落
+    #[default]
+    Drop,
+    /// 原样保留为空行
+    Keep,
+    /// 连续空段落合并为一个空行，并去掉首尾的空行
+    Collapse,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum IdentityMode {
+    /// 每个请求随机 UA，不保留 Cookie
+    #[default]
+    PerRequest,
+    /// 整个运行期间使用同一身份
+    PerRun,
+    /// 每 rotate_every 个请求更换一次身份
+    Rotate,
+    /// 同时维持 pool_size 个身份，章节之间轮流使用，各自限速
+    Pool,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct IdentityConfig {
+    #[serde(default)]
+    pub(crate) mode: IdentityMode,
+    #[serde(default = "default_rotate_every")]
+    pub(crate) rotate_every: usize,
+    /// pool 模式下的身份数
+    #[serde(default = "default_pool_size")]
+    pub(crate) pool_size: usize,
+    /// pool 模式下每个身份连续处理的章节数
+    #[serde(default = "default_pool_stickiness")]
+    pub(crate) pool_stickiness: usize,
+    #[serde(default)]
+    pub(crate) browser: BrowserPreset,
+    #[serde(default)]
+    pub(crate) region: HeaderRegion,
+}
+
+/// 伪装的浏览器，决定 UA 列表和与之匹配的 Accept 头
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BrowserPreset {
+    #[default]
+    Chrome,
+    Firefox,
+    Safari,
+}
+
+impl BrowserPreset {
+    pub(crate) fn user_agents(self) -> &'static [&'static str] {
+        match self {
+            BrowserPreset::Chrome => CHROME_USER_AGENTS,
+            BrowserPreset::Firefox => FIREFOX_USER_AGENTS,
+            BrowserPreset::Safari => SAFARI_USER_AGENTS,
+        }
+    }
+
+    /// 该浏览器请求页面时发送的 Accept 头
+    pub(crate) fn accept_html(self) -> &'static str {
+        match self {
+            BrowserPreset::Chrome => "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7",
+            BrowserPreset::Firefox => "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8",
+            BrowserPreset::Safari => "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+        }
+    }
+}
+
+/// 站点所在地区，决定 Accept-Language；部分站点按语言返回不同（甚至删节的）内容
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum HeaderRegion {
+    /// 简体中文
+    #[default]
+    ZhCn,
+    /// 繁体中文（台湾）
+    ZhTw,
+    /// 美式英语
+    EnUs,
+}
+
+impl HeaderRegion {
+    pub(crate) fn accept_languages(self) -> &'static [&'static str] {
+        match self {
+            HeaderRegion::ZhCn => &["zh-CN,zh;q=0.9", "zh-CN,zh;q=0.9,en;q=0.8", "zh-CN,zh;q=0.9,en-US;q=0.8,en;q=0.7"],
+            HeaderRegion::ZhTw => &["zh-TW,zh;q=0.9", "zh-TW,zh;q=0.9,en-US;q=0.8,en;q=0.7", "zh-TW,zh-HK;q=0.9,zh;q=0.8,en;q=0.7"],
+            HeaderRegion::EnUs => &["en-US,en;q=0.9", "en-US,en;q=0.9,zh-CN;q=0.8,zh;q=0.7"],
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum IpVersion {
+    /// 由系统解析结果决定
+    #[default]
+    Auto,
+    /// 只通过 IPv4 连接
+    V4,
+    /// 只通过 IPv6 连接
+    V6,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct HttpConfig {
+    #[serde(default)]
+    pub(crate) ip_version: IpVersion,
+    /// 域名 -> 实际连接地址，Host 头和 TLS 证书校验仍使用原域名
+    #[serde(default, deserialize_with = "deserialize_resolve")]
+    pub(crate) resolve: BTreeMap<String, SocketAddr>,
+    /// 地址前缀 -> 替换前缀，请求前改写 URL
+    #[serde(default)]
+    pub(crate) url_rewrites: BTreeMap<String, String>,
+    #[serde(default)]
+    pub(crate) proxies: Vec<ProxySpec>,
+    #[serde(default)]
+    pub(crate) proxy_rotation: ProxyRotation,
+    #[serde(default = "default_proxy_max_failures")]
+    pub(crate) proxy_max_failures: usize,
+    /// 域名 -> 预置 Cookie（如 "over18=1"），同时对其子域名生效
+    #[serde(default)]
+    pub(crate) cookies: BTreeMap<String, String>,
+    /// 章节页响应缓存目录，非空时按 ETag / Last-Modified 发条件请求，未变化的章节使用缓存
+    #[serde(default)]
+    pub(crate) cache_dir: String,
+    /// 单个请求从连接到读完正文的时限，0 表示不限
+    #[serde(default = "default_request_timeout_secs")]
+    pub(crate) request_timeout_secs: u64,
+    /// 建立连接的时限，0 表示不限
+    #[serde(default = "default_connect_timeout_secs")]
+    pub(crate) connect_timeout_secs: u64,
+}
+
+impl HttpConfig {
+    pub(crate) fn request_timeout(&self) -> Option<Duration> {
+        (self.request_timeout_secs > 0).then(|| Duration::from_secs(self.request_timeout_secs))
+    }
+}
+
+/// 代理池中的一个代理：只写地址，或写成表格附带认证信息
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ProxySpec {
+    Url(String),
+    Auth(ProxyAuth),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ProxyAuth {
+    pub(crate) url: String,
+    #[serde(default)]
+    pub(crate) username: String,
+    /// 以 env: 开头时从该环境变量读取
+    #[serde(default)]
+    pub(crate) password: String,
+    /// 随每个请求发给代理的请求头（如 Proxy-Authorization），值同样支持 env:
+    #[serde(default)]
+    pub(crate) headers: BTreeMap<String, String>,
+}
+
+impl ProxySpec {
+    /// 读取环境变量中的密钥并生成 reqwest 代理，启动时调用，缺少环境变量时报错
+    pub(crate) fn to_proxy(&self) -> Result<reqwest::Proxy, String> {
+        let auth = match self {
+            ProxySpec::Url(url) => return reqwest::Proxy::all(url).map_err(|e| format!("代理地址无效 {}: {}", url, e)),
+            ProxySpec::Auth(auth) => auth,
+        };
+        let mut proxy = reqwest::Proxy::all(&auth.url).map_err(|e| format!("代理地址无效 {}: {}", auth.url, e))?;
+        if !auth.username.is_empty() {
+            proxy = proxy.basic_auth(&auth.username, &proxy_secret(&auth.password)?);
+        }
+        if !auth.headers.is_empty() {
+            let mut headers = reqwest::header::HeaderMap::new();
+            for (name, value) in &auth.headers {
+                let name = reqwest::header::HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("代理请求头名称无效: {}", name))?;
+                let mut value = reqwest::header::HeaderValue::from_str(&proxy_secret(value)?).map_err(|_| format!("代理请求头 {} 的值无效", name))?;
+                value.set_sensitive(true);
+                headers.insert(name, value);
+            }
+            proxy = proxy.headers(headers);
+        }
+        Ok(proxy)
+    }
+}
+
+/// "env:名称" 取环境变量，其余原样使用
+fn proxy_secret(value: &str) -> Result<String, String> {
+    match value.strip_prefix("env:") {
+        Some(name) => std::env::var(name).map_err(|_| format!("未设置环境变量 {}，无法读取代理认证信息", name)),
+        None => Ok(value.to_string()),
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ProxyRotation {
+    /// 依次轮流使用
+    #[default]
+    RoundRobin,
+    /// 每次随机挑选
+    Random,
+}
+
+/// 连接地址可以只写 IP（使用协议默认端口），也可以写成 IP:端口
+fn deserialize_resolve<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, SocketAddr>, D::Error> {
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(host, addr)| {
+            let parsed = addr.parse::<SocketAddr>()
+                .or_else(|_| addr.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 0)))
+                .map_err(|_| serde::de::Error::custom(format!("http.resolve 中 {} 的地址无效: {}", host, addr)))?;
+            Ok((host, parsed))
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogTimezone {
+    /// 本机时区
+    #[default]
+    Local,
+    Utc,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct LogConfig {
+    #[serde(default)]
+    pub(crate) timezone: LogTimezone,
+    #[serde(default = "default_time_format")]
+    pub(crate) time_format: String,
+    /// 章节完成日志按章节顺序输出，而不是按完成先后
+    #[serde(default)]
+    pub(crate) ordered: bool,
+    /// 抓取时在终端显示进度条，输出不是终端时自动关闭
+    #[serde(default = "default_true")]
+    pub(crate) progress: bool,
+    /// 输出诊断日志，如正文被识别为 gzip 数据并解压；等同于 level = "debug"
+    #[serde(default)]
+    pub(crate) verbose: bool,
+    /// 日志级别：error、warn、info、debug、trace
+    #[serde(default = "default_log_level")]
+    pub(crate) level: String,
+    /// 每行输出一个 JSON 对象，控制台和日志文件都使用
+    #[serde(default)]
+    pub(crate) json: bool,
+    /// 日志文件，非空时在控制台之外同时写入
+    #[serde(default)]
+    pub(crate) file: String,
+    #[serde(default)]
+    pub(crate) rotation: LogRotation,
+    /// 最多保留的日志文件数，0 为不限
+    #[serde(default)]
+    pub(crate) max_files: usize,
+}
+
+/// 日志文件的滚动周期
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogRotation {
+    /// 始终写同一个文件
+    Never,
+    Hourly,
+    #[default]
+    Daily,
+}
+
+/// 登录会话：预置 Cookie 或在抓取前提交登录表单，配置任一项后所有请求共用同一个 Cookie 容器
+#[derive(Debug, Deserialize)]
+pub(crate) struct AuthConfig {
+    /// 从浏览器复制的 Cookie 字符串，作用于目录页所在域名及其子域名
+    #[serde(default)]
+    pub(crate) cookie: String,
+    /// Netscape 格式的 cookies.txt
+    #[serde(default)]
+    pub(crate) cookies_file: String,
+    /// 登录页地址，非空时抓取前先登录
+    #[serde(default)]
+    pub(crate) login_url: String,
+    #[serde(default = "default_username_field")]
+    pub(crate) username_field: String,
+    #[serde(default = "default_password_field")]
+    pub(crate) password_field: String,
+    #[serde(default)]
+    pub(crate) username: String,
+    #[serde(default)]
+    pub(crate) password: String,
+    /// 从该环境变量读取密码，非空时忽略 password
+    #[serde(default)]
+    pub(crate) password_env: String,
+    /// 随登录表单一起提交的其他字段
+    #[serde(default)]
+    pub(crate) extra_fields: BTreeMap<String, String>,
+    /// 登录后的页面中出现此文本视为登录失败
+    #[serde(default)]
+    pub(crate) failure_marker: String,
+}
+
+impl AuthConfig {
+    pub(crate) fn enabled(&self) -> bool {
+        !self.cookie.is_empty() || !self.cookies_file.is_empty() || !self.login_url.is_empty()
+    }
+}
+
+/// 批量模式：配置了 [[books]] 时依次（或同时）抓取多本书
+#[derive(Debug, Deserialize)]
+pub(crate) struct BatchConfig {
+    /// 同时抓取的书数，为1时逐本抓取
+    #[serde(default = "default_parallel_books")]
+    pub(crate) parallel_books: usize,
+    /// 每本书写入此目录下以书名命名的子目录，并在此目录维护 library.json 索引；为空时按各书的 file 原样写出
+    #[serde(default = "default_library_dir")]
+    pub(crate) output_dir: String,
+}
+
+/// 请求签名：为每个请求按模板计算并追加查询参数
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct SigningConfig {
+    #[serde(default)]
+    pub(crate) params: BTreeMap<String, String>,
+    #[serde(default)]
+    pub(crate) secrets: BTreeMap<String, String>,
+}
+
+/// [hosts."域名"]：章节列表混有多个镜像站时，按请求的域名改用不同的解析和访问规则，未填写的项沿用全局配置
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct HostConfig {
+    #[serde(default)]
+    pub(crate) selectors: HostSelectors,
+    /// 该域名单独限速，与全局限速互不占用
+    #[serde(default)]
+    pub(crate) min_delay_ms: Option<u64>,
+    #[serde(default)]
+    pub(crate) max_delay_ms: Option<u64>,
+    /// 同时抓取该域名章节的上限，在全局并发数之内再加一层限制
+    #[serde(default)]
+    pub(crate) concurrent_limit: Option<usize>,
+    /// 附加或替换默认值的请求头
+    #[serde(default)]
+    pub(crate) headers: BTreeMap<String, String>,
+}
+
+/// 章节页解析相关的选择器，含义同 [selectors] 中的同名项
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct HostSelectors {
+    #[serde(default)]
+    pub(crate) title_selector: Option<String>,
+    #[serde(default)]
+    pub(crate) content_selector: Option<String>,
+    #[serde(default)]
+    pub(crate) note_selector: Option<String>,
+    #[serde(default)]
+    pub(crate) content_next_page_selector: Option<String>,
+    #[serde(default)]
+    pub(crate) json_state_pattern: Option<String>,
+    #[serde(default)]
+    pub(crate) json_title_pointer: Option<String>,
+    #[serde(default)]
+    pub(crate) json_content_pointer: Option<String>,
+}
+
+impl HostSelectors {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.title_selector.is_none() && self.content_selector.is_none() && self.note_selector.is_none()
+            && self.content_next_page_selector.is_none() && self.json_state_pattern.is_none()
+            && self.json_title_pointer.is_none() && self.json_content_pointer.is_none()
+    }
+
+    /// 在全局选择器上覆盖本域名填写的项
+    pub(crate) fn apply(&self, base: &SelectorsConfig) -> SelectorsConfig {
+        let pick = |value: &Option<String>, fallback: &String| value.clone().unwrap_or_else(|| fallback.clone());
+        SelectorsConfig {
+            title_selector: pick(&self.title_selector, &base.title_selector),
+            content_selector: pick(&self.content_selector, &base.content_selector),
+            note_selector: pick(&self.note_selector, &base.note_selector),
+            content_next_page_selector: pick(&self.content_next_page_selector, &base.content_next_page_selector),
+            json_state_pattern: pick(&self.json_state_pattern, &base.json_state_pattern),
+            json_title_pointer: pick(&self.json_title_pointer, &base.json_title_pointer),
+            json_content_pointer: pick(&self.json_content_pointer, &base.json_content_pointer),
+            ..base.clone()
+        }
+    }
+}
+
+/// 抓取结束后的质量门槛，任一项不达标则本次运行判定为失败
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct QualityConfig {
+    #[serde(default)]
+    pub(crate) max_failure_percent: Option<f64>,
+    #[serde(default)]
+    pub(crate) min_avg_chapter_chars: Option<usize>,
+    #[serde(default)]
+    pub(crate) max_suspect_chapters: Option<usize>,
+}
+
+/// 拟人浏览模式：按阅读顺序逐章访问，偶尔回到目录页，加载少量页面资源并随机停留
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct HumanConfig {
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    #[serde(default = "default_dwell_min_ms")]
+    pub(crate) dwell_min_ms: u64,
+    #[serde(default = "default_dwell_max_ms")]
+    pub(crate) dwell_max_ms: u64,
+    #[serde(default = "default_catalog_revisit_chance")]
+    pub(crate) catalog_revisit_chance: f64,
+    #[serde(default = "default_max_assets")]
+    pub(crate) max_assets: usize,
+}
+
+/// 论坛连载模式：把分页帖子中作者本人的回帖当作章节
+#[derive(Debug, Deserialize)]
+pub(crate) struct ForumConfig {
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    #[serde(default)]
+    pub(crate) post_selector: String,
+    #[serde(default)]
+    pub(crate) author_selector: String,
+    #[serde(default)]
+    pub(crate) author: String,
+    #[serde(default)]
+    pub(crate) post_title_selector: String,
+    #[serde(default)]
+    pub(crate) post_content_selector: String,
+    #[serde(default)]
+    pub(crate) next_page_selector: String,
+    #[serde(default = "default_forum_max_pages")]
+    pub(crate) max_pages: usize,
+}
+
+/// 配置段整段缺失时与写了一个空段等价，各字段都取 serde 上声明的默认值。
+/// 派生的 Default 会得到零值（如 concurrent_limit = 0、decode_entities = false），与只写了段名的配置不一致
+macro_rules! default_from_serde {
+    ($($ty:ty),* $(,)?) => {
+        $(impl Default for $ty {
+            fn default() -> Self {
+                toml::from_str("").expect("所有字段都应有默认值")
+            }
+        })*
+    };
+}
+
+default_from_serde!(CrawlConfig, UrlsConfig, SelectorsConfig, OutputConfig, CleanConfig, IdentityConfig, HttpConfig, HumanConfig, ForumConfig, LogConfig, AuthConfig, BatchConfig);
+
+fn default_true() -> bool { true }
+fn default_soft_404_markers() -> Vec<String> {
+    ["页面不存在", "章节不存在", "文章不存在", "内容不存在", "章节已删除", "该章节已被删除", "找不到该章节"].iter().map(|s| s.to_string()).collect()
+}
+fn default_catalog_title_patterns() -> Vec<String> {
+    [
+        r"^[\[【(（]?最新(章节)?[\]】)）]?\s*[:：]?",
+        r"[\[【(（]?\d{4}[-/.年]\d{1,2}[-/.月]\d{1,2}日?(\s*\d{1,2}:\d{2}(:\d{2})?)?[\]】)）]?",
+        r"[\[【(（]?(阅读|点击|浏览)[量数]?\s*[:：]?\s*\d+(\.\d+)?[万千]?次?[\]】)）]?",
+        r"[\[【(（]?\d+(\.\d+)?[万千]?\s*[次人]?(阅读|点击|浏览)[\]】)）]?",
+    ].iter().map(|s| s.to_string()).collect()
+}
+fn default_scene_break_patterns() -> Vec<String> {
+    ["※※※", "***", "---", "＊＊＊", "◇◇◇", "☆☆☆"].iter().map(|s| s.to_string()).collect()
+}
+fn default_concurrent_limit() -> usize { DEFAULT_CONCURRENT_LIMIT }
+fn default_ramp_up_initial() -> usize { DEFAULT_RAMP_UP_INITIAL }
+fn default_ramp_up_secs() -> u64 { DEFAULT_RAMP_UP_SECS }
+fn default_base_url() -> String { DEFAULT_BASE_URL.to_string() }
+fn default_catalog_url() -> String { DEFAULT_CATALOG_URL.to_string() }
+fn default_title_selector() -> String { DEFAULT_TITLE_SELECTOR.to_string() }
+fn default_content_selector() -> String { DEFAULT_CONTENT_SELECTOR.to_string() }
+fn default_chapter_link_selector() -> String { DEFAULT_CHAPTER_LINK_SELECTOR.to_string() }
+fn default_max_retries() -> usize { DEFAULT_MAX_RETRIES }
+fn default_retry_backoff_ms() -> u64 { DEFAULT_RETRY_BACKOFF_MS }
+fn default_injected_min_chapters() -> usize { DEFAULT_INJECTED_MIN_CHAPTERS }
+fn default_rotate_every() -> usize { DEFAULT_ROTATE_EVERY }
+fn default_pool_size() -> usize { DEFAULT_POOL_SIZE }
+fn default_pool_stickiness() -> usize { 1 }
+fn default_parallel_books() -> usize { 1 }
+fn default_library_dir() -> String { "output".to_string() }
+fn default_forum_max_pages() -> usize { DEFAULT_FORUM_MAX_PAGES }
+fn default_catalog_max_pages() -> usize { DEFAULT_CATALOG_MAX_PAGES }
+fn default_dwell_min_ms() -> u64 { DEFAULT_DWELL_MIN_MS }
+fn default_dwell_max_ms() -> u64 { DEFAULT_DWELL_MAX_MS }
+fn default_catalog_revisit_chance() -> f64 { DEFAULT_CATALOG_REVISIT_CHANCE }
+fn default_max_assets() -> usize { DEFAULT_MAX_ASSETS }
+fn default_proxy_max_failures() -> usize { DEFAULT_PROXY_MAX_FAILURES }
+fn default_request_timeout_secs() -> u64 { DEFAULT_REQUEST_TIMEOUT_SECS }
+fn default_connect_timeout_secs() -> u64 { DEFAULT_CONNECT_TIMEOUT_SECS }
+fn default_time_format() -> String { DEFAULT_TIME_FORMAT.to_string() }
+fn default_log_level() -> String { DEFAULT_LOG_LEVEL.to_string() }
+fn default_output_file() -> String { DEFAULT_OUTPUT_FILE.to_string() }
+fn default_state_file() -> String { DEFAULT_STATE_FILE.to_string() }
+fn default_state_save_every() -> usize { DEFAULT_STATE_SAVE_EVERY }
+fn default_resume_backoff_max_secs() -> u64 { DEFAULT_RESUME_BACKOFF_MAX_SECS }
+fn default_failures_file() -> String { DEFAULT_FAILURES_FILE.to_string() }
+fn default_book_language() -> String { DEFAULT_BOOK_LANGUAGE.to_string() }
+fn default_note_separator() -> String { DEFAULT_NOTE_SEPARATOR.to_string() }
+fn default_username_field() -> String { DEFAULT_USERNAME_FIELD.to_string() }
+fn default_password_field() -> String { DEFAULT_PASSWORD_FIELD.to_string() }
+
+/// 命令行等外部给出的设置，优先级高于配置文件，为 None 或 false 的项不覆盖
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// 配置文件路径，为 None 时依次查找当前目录和程序所在目录下的 config.toml
+    pub config: Option<std::path::PathBuf>,
+    /// 覆盖 urls.catalog_url，同时忽略配置文件中的 urls.book_url
+    pub catalog_url: Option<String>,
+    /// 覆盖 urls.book_url
+    pub book_url: Option<String>,
+    /// 覆盖 urls.base_url
+    pub base_url: Option<String>,
+    /// 覆盖 output.file
+    pub output: Option<String>,
+    /// 覆盖 crawl.concurrent_limit
+    pub concurrency: Option<usize>,
+    /// 覆盖 selectors.title_selector
+    pub title_selector: Option<String>,
+    /// 覆盖 selectors.content_selector
+    pub content_selector: Option<String>,
+    /// 覆盖 selectors.chapter_link_selector
+    pub chapter_link_selector: Option<String>,
+    /// 开启 crawl.resume
+    pub resume: bool,
+    /// 覆盖 crawl.retry_failures
+    pub retry_failures: Option<String>,
+    /// 开启 log.ordered
+    pub ordered_logs: bool,
+    /// 关闭 log.progress
+    pub no_progress: bool,
+    /// 开启 log.verbose
+    pub verbose: bool,
+    /// 覆盖 log.level
+    pub log_level: Option<String>,
+    /// 覆盖 crawl.start_chapter / end_chapter
+    pub range: Option<(usize, usize)>,
+    /// 使用配置文件中 [sites.<名称>] 的站点配置，为 None 时按目录页域名自动匹配
+    pub site: Option<String>,
+}
+
+impl Overrides {
+    pub(crate) fn apply(self, config: &mut Config) {
+        if let Some(v) = self.catalog_url {
+            config.urls.catalog_url = v;
+            config.urls.book_url.clear();
+        }
+        if let Some(v) = self.book_url { config.urls.book_url = v; }
+        if let Some(v) = self.base_url { config.urls.base_url = v; }
+        if let Some(v) = self.output { config.output.file = v; }
+        if let Some(v) = self.concurrency { config.crawl.concurrent_limit = v; }
+        if let Some(v) = self.title_selector { config.selectors.title_selector = v; }
+        if let Some(v) = self.content_selector { config.selectors.content_selector = v; }
+        if let Some(v) = self.chapter_link_selector { config.selectors.chapter_link_selector = v; }
+        if self.resume { config.crawl.resume = true; }
+        if let Some(path) = self.retry_failures { config.crawl.retry_failures = path; }
+        if self.ordered_logs { config.log.ordered = true; }
+        if self.no_progress { config.log.progress = false; }
+        if self.verbose { config.log.verbose = true; }
+        if let Some(v) = self.log_level { config.log.level = v; }
+        if let Some((start, end)) = self.range {
+            config.crawl.start_chapter = start;
+            config.crawl.end_chapter = end;
+        }
+    }
+}
+
+fn find_config_file() -> Option<std::path::PathBuf> {
+    if let Ok(cwd) = std::env::current_dir() {
+        let config_in_cwd = cwd.join("config.toml");
+        if config_in_cwd.exists() {
+            return Some(config_in_cwd);
+        }
+    }
+
+    if let Ok(exe_path) = std::env::current_exe() {
+        let exe_dir = exe_path.parent().unwrap_or(&exe_path);
+        let config_in_exe_dir = exe_dir.join("config.toml");
+        if config_in_exe_dir.exists() {
+            return Some(config_in_exe_dir);
+        }
+    }
+
+    None
+}
+
+/// 读取配置文件时的错误
+pub(crate) enum ConfigError {
+    /// 写法有误，退回默认配置
+    Parse(toml::de::Error),
+    /// 无法按用户的意图继续，如指定的站点配置不存在
+    Invalid(String),
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+/// 从根配置中取出 [[books]] 数组，没有时为空
+fn take_books(root: &mut toml::Table) -> Vec<toml::Table> {
+    match root.remove("books") {
+        None => Vec::new(),
+        Some(toml::Value::Array(books)) => books.into_iter()
+            .enumerate()
+            .filter_map(|(i, book)| match book {
+                toml::Value::Table(book) => Some(book),
+                _ => {
+                    warn!("books 中的第 {} 项不是表，已忽略", i + 1);
+                    None
+                }
+            })
+            .collect(),
+        Some(_) => {
+            warn!("books 应写成 [[books]] 表数组，已忽略");
+            Vec::new()
+        }
+    }
+}
+
+/// 得到一本书的完整配置：先按这本书的目录页匹配站点配置，再合并 [[books]] 项，书中写的选择器等优先于站点配置。
+/// catalog_url、book_url、base_url 写入 [urls]，file、title、author、format、tags 写入 [output]，site 指定站点配置，其余子表按段合并
+fn load_book(root: &toml::Table, number: usize, mut book: toml::Table, site: Option<&str>) -> Result<Config, ConfigError> {
+    if !(book.contains_key("catalog_url") || book.contains_key("book_url")) || !book.contains_key("file") {
+        return Err(ConfigError::Invalid(format!("第 {} 本书缺少 catalog_url（或 book_url）或 file，每本书都要有各自的目录页和输出文件", number)));
+    }
+    let mut table = root.clone();
+    let book_site = book.remove("site").and_then(|v| v.as_str().map(str::to_string));
+    let catalog_url = book.get("book_url").or_else(|| book.get("catalog_url")).and_then(|v| v.as_str()).map(str::to_string);
+    select_site(&mut table, book_site.as_deref().or(site), catalog_url.as_deref())?;
+    for (key, value) in book {
+        let (section, entries) = match (key.as_str(), value) {
+            ("catalog_url" | "book_url" | "base_url", value) => ("urls".to_string(), toml::Table::from_iter([(key, value)])),
+            ("file" | "title" | "author" | "format" | "tags", value) => ("output".to_string(), toml::Table::from_iter([(key, value)])),
+            (_, toml::Value::Table(section)) => (key, section),
+            _ => {
+                warn!("第 {} 本书中的 {} 不是配置段，已忽略", number, key);
+                continue;
+            }
+        };
+        let target = table.entry(section).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if let Some(target) = target.as_table_mut() {
+            target.extend(entries);
+        }
+    }
+    Ok(toml::Value::Table(table).try_into()?)
+}
+
+/// 批量模式下把一本书的输出文件放到 output_dir/书名/ 下，之后以输出文件名开头的断点等文件随之放在同一子目录
+fn place_in_library(book: &mut Config, output_dir: &str) {
+    if output_dir.is_empty() {
+        return;
+    }
+    let path = std::path::Path::new(&book.output.file);
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let dir = std::path::Path::new(output_dir).join(library::dir_name(&book_title(book)));
+    book.output.file = dir.join(name).to_string_lossy().into_owned();
+}
+
+/// 书名：未设置 output.title 时为输出文件名（不含扩展名）
+pub(crate) fn book_title(config: &Config) -> String {
+    if config.output.title.is_empty() {
+        std::path::Path::new(&config.output.file).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+    } else {
+        config.output.title.clone()
+    }
+}
+
+/// 断点、失败章节列表、报告和时间线文件沿用根配置的文件名时，改为以这本书的输出文件名开头，各书互不覆盖
+fn separate_book_files(book: &mut Config, root: &Config) {
+    let file = &book.output.file;
+    let shared = [
+        (&mut book.crawl.state_file, &root.crawl.state_file),
+        (&mut book.output.failures_file, &root.output.failures_file),
+        (&mut book.output.report_file, &root.output.report_file),
+        (&mut book.output.index_file, &root.output.index_file),
+        (&mut book.output.digest_file, &root.output.digest_file),
+        (&mut book.output.timeline_file, &root.output.timeline_file),
+    ];
+    for (path, root_path) in shared {
+        if path.is_empty() || path != root_path {
+            continue;
+        }
+        let name = std::path::Path::new(root_path.as_str()).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        *path = format!("{}.{}", file, name.trim_start_matches('.'));
+    }
+}
+
+/// 站点配置中的一段覆盖到配置根上：base_url 写入 [urls]，各子表逐项覆盖同名的段
+fn apply_site_profile(root: &mut toml::Table, name: &str, profile: toml::Table) {
+    for (key, value) in profile {
+        match (key.as_str(), value) {
+            ("hosts", _) => {}
+            ("base_url", value) => {
+                let urls = root.entry("urls").or_insert_with(|| toml::Value::Table(toml::Table::new()));
+                if let Some(urls) = urls.as_table_mut() {
+                    urls.insert(key, value);
+                }
+            }
+            (_, toml::Value::Table(section)) => {
+                let target = root.entry(key.clone()).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+                if let Some(target) = target.as_table_mut() {
+                    target.extend(section);
+                }
+            }
+            _ => warn!("站点配置 [sites.{}] 中的 {} 不是配置段，已忽略", name, key),
+        }
+    }
+}
+
+/// 站点配置匹配的域名：显式的 hosts，未配置时取 base_url 的域名
+fn site_hosts(profile: &toml::Table) -> Vec<String> {
+    match profile.get("hosts").and_then(|v| v.as_array()) {
+        Some(hosts) => hosts.iter().filter_map(|h| h.as_str()).map(str::to_string).collect(),
+        None => profile.get("base_url")
+            .and_then(|v| v.as_str())
+            .and_then(|url| reqwest::Url::parse(url).ok())
+            .and_then(|url| url.host_str().map(str::to_string))
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// 从 [sites] 中选出本次使用的站点配置并覆盖到配置根上：命令行 --site 优先，否则按目录页域名匹配，
+/// 域名同时匹配其子域名
+fn select_site(root: &mut toml::Table, site: Option<&str>, catalog_override: Option<&str>) -> Result<(), ConfigError> {
+    let Some(toml::Value::Table(mut sites)) = root.remove("sites") else {
+        if let Some(site) = site {
+            return Err(ConfigError::Invalid(format!("配置文件中没有 [sites] 站点配置，无法使用 --site {}", site)));
+        }
+        return Ok(());
+    };
+    let name = match site {
+        Some(site) if sites.contains_key(site) => site.to_string(),
+        Some(site) => {
+            let names: Vec<&str> = sites.keys().map(String::as_str).collect();
+            return Err(ConfigError::Invalid(format!("未找到站点配置 [sites.{}]，已配置: {}", site, names.join(", "))));
+        }
+        None => {
+            let url_of = |key: &str| root.get("urls").and_then(|urls| urls.get(key)).and_then(|v| v.as_str()).filter(|url| !url.is_empty());
+            let catalog_url = catalog_override
+                .or_else(|| url_of("book_url"))
+                .or_else(|| url_of("catalog_url"))
+                .and_then(|url| reqwest::Url::parse(url).ok());
+            let Some(host) = catalog_url.as_ref().and_then(|url| url.host_str()) else {
+                return Ok(());
+            };
+            let matched = sites.iter()
+                .filter_map(|(name, profile)| profile.as_table().map(|profile| (name, profile)))
+                .find(|(_, profile)| site_hosts(profile).iter().any(|h| host_matches(host, h)));
+            match matched {
+                Some((name, _)) => name.clone(),
+                None => return Ok(()),
+            }
+        }
+    };
+    match sites.remove(&name) {
+        Some(toml::Value::Table(profile)) => {
+            info!("使用站点配置: {}", name);
+            apply_site_profile(root, &name, profile);
+        }
+        _ => warn!("站点配置 [sites.{}] 不是表，已忽略", name),
+    }
+    Ok(())
+}
+
+/// 读取配置文件并应用覆盖项，打印最终生效的配置。配置文件写法有误时退回默认配置，
+/// 指定的配置文件不存在、站点配置找不到等无法按用户意图继续的情况返回错误
+pub fn load_config(mut overrides: Overrides) -> Result<Config, Box<dyn std::error::Error>> {
+    logging::init();
+    let config_path = match overrides.config.take() {
+        Some(path) if path.exists() => Some(path),
+        Some(path) => return Err(format!("指定的配置文件不存在: {}", path.display()).into()),
+        None => find_config_file(),
+    };
+    let mut config = match config_path {
+        Some(ref path) => {
+            info!("已找到配置文件: {}", path.display());
+            match std::fs::read_to_string(path) {
+                Ok(content) => {
+                    let parsed = toml::from_str::<toml::Table>(&content).map_err(ConfigError::from).and_then(|mut root| {
+                        let mut books = take_books(&mut root);
+                        if (overrides.catalog_url.is_some() || overrides.book_url.is_some()) && !books.is_empty() {
+                            info!("命令行指定了 --catalog-url 或 --book-url，忽略配置文件中的 {} 本 [[books]]", books.len());
+                            books.clear();
+                        }
+                        let book_root = if books.is_empty() { toml::Table::new() } else { root.clone() };
+                        select_site(&mut root, overrides.site.as_deref(), overrides.book_url.as_deref().or(overrides.catalog_url.as_deref()))?;
+                        let mut config: Config = toml::Value::Table(root).try_into()?;
+                        for (i, book) in books.into_iter().enumerate() {
+                            config.books.push(load_book(&book_root, i + 1, book, overrides.site.as_deref())?);
+                        }
+                        Ok(config)
+                    });
+                    match parsed {
+                        Ok(config) => config,
+                        Err(ConfigError::Parse(e)) => {
+                            warn!("配置文件解析失败，使用默认配置: {}", e);
+                            Config::default()
+                        }
+                        Err(ConfigError::Invalid(e)) => return Err(e.into()),
+                    }
+                }
+                Err(e) => {
+                    warn!("无法读取配置文件，使用默认配置: {}", e);
+                    Config::default()
+                }
+            }
+        }
+        None => {
+            if let Some(site) = &overrides.site {
+                return Err(format!("未找到 config.toml，无法使用 --site {}", site).into());
+            }
+            info!("未找到 config.toml，使用默认配置");
+            Config::default()
+        }
+    };
+    if !config.books.is_empty() {
+        if overrides.output.is_some() || overrides.retry_failures.is_some() {
+            return Err("批量模式下每本书各有输出文件和失败章节列表，不能使用 --output 或 --retry-failures".into());
+        }
+        let mut books = std::mem::take(&mut config.books);
+        for book in &mut books {
+            overrides.clone().apply(book);
+            place_in_library(book, &config.batch.output_dir);
+            separate_book_files(book, &config);
+        }
+        config.books = books;
+    }
+    overrides.apply(&mut config);
+    if config.output.file == DEFAULT_OUTPUT_FILE {
+        config.output.file = config.output.format.default_file().to_string();
+    }
+    init_log_clock(&config.log);
+    print_config(&config);
+    Ok(config)
+}
+
+fn print_config(config: &Config) {
+    info!("=========================================");
+    info!("当前配置:");
+    info!("  [crawl]");
+    info!("    concurrent_limit = {}", config.crawl.concurrent_limit);
+    info!("    ramp_up_initial = {}", config.crawl.ramp_up_initial);
+    info!("    ramp_up_secs = {}", config.crawl.ramp_up_secs);
+    info!("    paywall_markers = {:?}", config.crawl.paywall_markers);
+    info!("    soft_404_markers = {:?}", config.crawl.soft_404_markers);
+    info!("    min_paragraphs = {}", config.crawl.min_paragraphs);
+    info!("    accept_partial = {}", config.crawl.accept_partial);
+    info!("    max_retries = {}", config.crawl.max_retries);
+    info!("    retry_backoff_ms = {}", config.crawl.retry_backoff_ms);
+    info!("    stdin_control = {}", config.crawl.stdin_control);
+    info!("    abort_after_consecutive_failures = {}", config.crawl.abort_after_consecutive_failures);
+    info!("    min_delay_ms = {}", config.crawl.min_delay_ms);
+    info!("    max_delay_ms = {}", config.crawl.max_delay_ms);
+    info!("    state_file = {}", config.crawl.state_file);
+    info!("    state_save_every = {}", config.crawl.state_save_every);
+    info!("    resume = {}", config.crawl.resume);
+    info!("    retry_failures = {}", config.crawl.retry_failures);
+    info!("    resume_backoff_secs = {}", config.crawl.resume_backoff_secs);
+    info!("    resume_backoff_max_secs = {}", config.crawl.resume_backoff_max_secs);
+    info!("    respect_robots_txt = {}", config.crawl.respect_robots_txt);
+    info!("    start_chapter = {}", config.crawl.start_chapter);
+    info!("    end_chapter = {}", config.crawl.end_chapter);
+    info!("    prefilter_html = {}", config.crawl.prefilter_html);
+    info!("  [urls]");
+    info!("    base_url = {}", config.urls.base_url);
+    info!("    catalog_url = {}", config.urls.catalog_url);
+    info!("    book_url = {}", config.urls.book_url);
+    info!("    book_id_pattern = {}", config.urls.book_id_pattern);
+    info!("    catalog_page_template = {}", config.urls.catalog_page_template);
+    info!("    catalog_max_pages = {}", config.urls.catalog_max_pages);
+    info!("    strip_query_params = {:?}", config.urls.strip_query_params);
+    info!("    sort_key_pattern = {}", config.urls.sort_key_pattern);
+    info!("  [selectors]");
+    info!("    title_selector = {}", config.selectors.title_selector);
+    info!("    content_selector = {}", config.selectors.content_selector);
+    info!("    chapter_link_selector = {}", config.selectors.chapter_link_selector);
+    info!("    note_selector = {}", config.selectors.note_selector);
+    info!("    catalog_next_page_selector = {}", config.selectors.catalog_next_page_selector);
+    info!("    catalog_link_selector = {}", config.selectors.catalog_link_selector);
+    info!("    volume_selector = {}", config.selectors.volume_selector);
+    info!("    content_next_page_selector = {}", config.selectors.content_next_page_selector);
+    if !config.selectors.json_state_pattern.is_empty() {
+        info!("    json_state_pattern = {}", config.selectors.json_state_pattern);
+        info!("    json_title_pointer = {}", config.selectors.json_title_pointer);
+        info!("    json_content_pointer = {}", config.selectors.json_content_pointer);
+    }
+    info!("  [output]");
+    info!("    format = {:?}", config.output.format);
+    info!("    file = {}", config.output.file);
+    info!("    fsync = {:?}", config.output.fsync);
+    info!("    report_file = {}", config.output.report_file);
+    info!("    index_file = {}", config.output.index_file);
+    info!("    digest_file = {}", config.output.digest_file);
+    info!("    digest_granularity = {:?}", config.output.digest_granularity);
+    info!("    failures_file = {}", config.output.failures_file);
+    info!("    timeline_file = {}", config.output.timeline_file);
+    info!("    note_separator = {}", config.output.note_separator);
+    info!("    placeholders = {}", config.output.placeholders);
+    info!("    annotations_file = {}", config.output.annotations_file);
+    info!("    annotation_style = {:?}", config.output.annotation_style);
+    info!("    spill = {}", config.output.spill);
+    info!("    store_dir = {}", config.output.store_dir);
+    if matches!(config.output.format, OutputFormat::Markdown | OutputFormat::Html | OutputFormat::Epub) {
+        info!("    title = {}", config.output.title);
+    }
+    if config.output.format == OutputFormat::Epub {
+        info!("    author = {}", config.output.author);
+        info!("    tags = {:?}", config.output.tags);
+        info!("    language = {}", config.output.language);
+        info!("    cover = {}", config.output.cover);
+    }
+    info!("  [identity]");
+    info!("    mode = {:?}", config.identity.mode);
+    info!("    rotate_every = {}", config.identity.rotate_every);
+    info!("    pool_size = {}", config.identity.pool_size);
+    info!("    pool_stickiness = {}", config.identity.pool_stickiness);
+    info!("    browser = {:?}", config.identity.browser);
+    info!("    region = {:?}", config.identity.region);
+    if config.forum.enabled {
+        info!("  [forum]");
+        info!("    post_selector = {}", config.forum.post_selector);
+        info!("    author_selector = {}", config.forum.author_selector);
+        info!("    author = {}", config.forum.author);
+        info!("    post_title_selector = {}", config.forum.post_title_selector);
+        info!("    post_content_selector = {}", config.forum.post_content_selector);
+        info!("    next_page_selector = {}", config.forum.next_page_selector);
+        info!("    max_pages = {}", config.forum.max_pages);
+    }
+    if config.human.enabled {
+        info!("  [human]");
+        info!("    dwell_min_ms = {}", config.human.dwell_min_ms);
+        info!("    dwell_max_ms = {}", config.human.dwell_max_ms);
+        info!("    catalog_revisit_chance = {}", config.human.catalog_revisit_chance);
+        info!("    max_assets = {}", config.human.max_assets);
+    }
+    info!("  [quality]");
+    info!("    max_failure_percent = {:?}", config.quality.max_failure_percent);
+    info!("    min_avg_chapter_chars = {:?}", config.quality.min_avg_chapter_chars);
+    info!("    max_suspect_chapters = {:?}", config.quality.max_suspect_chapters);
+    info!("  [http]");
+    info!("    ip_version = {:?}", config.http.ip_version);
+    info!("    resolve = {:?}", config.http.resolve);
+    info!("    url_rewrites = {:?}", config.http.url_rewrites);
+    if !config.http.proxies.is_empty() {
+        // 代理地址可能带账号密码，只输出数量
+        info!("    proxies = {} 个", config.http.proxies.len());
+        info!("    proxy_rotation = {:?}", config.http.proxy_rotation);
+        info!("    proxy_max_failures = {}", config.http.proxy_max_failures);
+    }
+    for (domain, cookies) in &config.http.cookies {
+        info!("    cookies.{} = {}", domain, cookies);
+    }
+    info!("    cache_dir = {}", config.http.cache_dir);
+    info!("    request_timeout_secs = {}", config.http.request_timeout_secs);
+    info!("    connect_timeout_secs = {}", config.http.connect_timeout_secs);
+    if !config.signing.params.is_empty() {
+        info!("  [signing]");
+        for (name, template) in &config.signing.params {
+            info!("    params.{} = {}", name, template);
+        }
+        info!("    secrets = {:?}", config.signing.secrets.keys().collect::<Vec<_>>());
+    }
+    if config.auth.enabled() {
+        // Cookie 和密码属于登录凭据，只输出是否设置
+        let is_set = |value: &str| if value.is_empty() { "(未设置)" } else { "(已设置)" };
+        info!("  [auth]");
+        info!("    cookie = {}", is_set(&config.auth.cookie));
+        info!("    cookies_file = {}", config.auth.cookies_file);
+        info!("    login_url = {}", config.auth.login_url);
+        if !config.auth.login_url.is_empty() {
+            info!("    username_field = {}", config.auth.username_field);
+            info!("    password_field = {}", config.auth.password_field);
+            info!("    username = {}", config.auth.username);
+            info!("    password = {}", is_set(&config.auth.password));
+            info!("    password_env = {}", config.auth.password_env);
+            info!("    extra_fields = {:?}", config.auth.extra_fields);
+            info!("    failure_marker = {}", config.auth.failure_marker);
+        }
+    }
+    for (domain, host) in &config.hosts {
+        info!("  [hosts.\"{}\"]", domain);
+        let selectors = [
+            ("title_selector", &host.selectors.title_selector),
+            ("content_selector", &host.selectors.content_selector),
+            ("note_selector", &host.selectors.note_selector),
+            ("content_next_page_selector", &host.selectors.content_next_page_selector),
+            ("json_state_pattern", &host.selectors.json_state_pattern),
+            ("json_title_pointer", &host.selectors.json_title_pointer),
+            ("json_content_pointer", &host.selectors.json_content_pointer),
+        ];
+        for (name, value) in selectors {
+            if let Some(value) = value {
+                info!("    selectors.{} = {}", name, value);
+            }
+        }
+        if let Some(min_delay_ms) = host.min_delay_ms {
+            info!("    min_delay_ms = {}", min_delay_ms);
+        }
+        if let Some(max_delay_ms) = host.max_delay_ms {
+            info!("    max_delay_ms = {}", max_delay_ms);
+        }
+        if let Some(concurrent_limit) = host.concurrent_limit {
+            info!("    concurrent_limit = {}", concurrent_limit);
+        }
+        for (name, value) in &host.headers {
+            info!("    headers.{} = {}", name, value);
+        }
+    }
+    info!("  [log]");
+    info!("    timezone = {:?}", config.log.timezone);
+    info!("    time_format = {}", config.log.time_format);
+    info!("    ordered = {}", config.log.ordered);
+    info!("    progress = {}", config.log.progress);
+    info!("    verbose = {}", config.log.verbose);
+    info!("    level = {}", config.log.level);
+    info!("    json = {}", config.log.json);
+    info!("    file = {}", config.log.file);
+    info!("    rotation = {:?}", config.log.rotation);
+    info!("    max_files = {}", config.log.max_files);
+    info!("  [clean]");
+    info!("    strip_injected = {}", config.clean.strip_injected);
+    info!("    injected_min_chapters = {}", config.clean.injected_min_chapters);
+    info!("    dedupe_title = {}", config.clean.dedupe_title);
+    info!("    drop_duplicate_chapters = {}", config.clean.drop_duplicate_chapters);
+    info!("    duplicate_titles = {}", config.clean.duplicate_titles);
+    info!("    decode_entities = {}", config.clean.decode_entities);
+    info!("    empty_paragraphs = {:?}", config.clean.empty_paragraphs);
+    info!("    scene_break_patterns = {:?}", config.clean.scene_break_patterns);
+    info!("    scene_break_marker = {}", config.clean.scene_break_marker);
+    info!("    substitutions_file = {}", config.clean.substitutions_file);
+    info!("    remove_patterns = {:?}", config.clean.remove_patterns);
+    info!("    ad_heuristics = {}", config.clean.ad_heuristics);
+    info!("    catalog_title_patterns = {:?}", config.clean.catalog_title_patterns);
+    if !config.books.is_empty() {
+        info!("  [batch]");
+        info!("    parallel_books = {}", config.batch.parallel_books);
+        info!("    output_dir = {}", config.batch.output_dir);
+        for (i, book) in config.books.iter().enumerate() {
+            info!("  [[books]] {}: {} <- {}", i + 1, book.output.file, book.urls.entry_url());
+        }
+    }
+    info!("=========================================");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proxy_specs_accept_urls_and_credentials() {
+        let http: HttpConfig = toml::from_str(r#"
+proxies = [
+    "http://127.0.0.1:8001",
+    { url = "http://127.0.0.1:8002", username = "user", password = "env:PATH" },
+    { url = "http://127.0.0.1:8003", headers = { "Proxy-Authorization" = "Bearer token" } },
+]
+"#).unwrap();
+        assert_eq!(http.proxies.len(), 3);
+        assert!(matches!(&http.proxies[0], ProxySpec::Url(url) if url == "http://127.0.0.1:8001"));
+        assert!(http.proxies.iter().all(|spec| spec.to_proxy().is_ok()));
+
+        let missing = ProxySpec::Auth(ProxyAuth {
+            url: "http://127.0.0.1:8004".to_string(),
+            username: "user".to_string(),
+            password: "env:RUST_CRAWLER_UNSET_PROXY_PASSWORD".to_string(),
+            headers: BTreeMap::new(),
+        });
+        assert!(missing.to_proxy().unwrap_err().contains("RUST_CRAWLER_UNSET_PROXY_PASSWORD"));
+        assert_eq!(proxy_secret("plain").unwrap(), "plain");
+    }
+
+    #[test]
+    fn missing_config_sections_use_field_defaults() {
+        let empty: Config = toml::from_str("").unwrap();
+        assert_eq!(format!("{:?}", empty), format!("{:?}", Config::default()));
+        let sections = ["crawl", "urls", "selectors", "output", "clean", "identity", "forum", "http", "human", "quality", "log", "signing", "auth", "batch"];
+        let with_empty_sections: Config = toml::from_str(&sections.map(|name| format!("[{}]", name)).join("\n")).unwrap();
+        assert_eq!(format!("{:?}", with_empty_sections), format!("{:?}", empty));
+        assert_eq!(empty.crawl.concurrent_limit, DEFAULT_CONCURRENT_LIMIT);
+        assert!(empty.clean.decode_entities);
+    }
+
+    #[test]
+    fn unknown_site_profile_is_an_error() {
+        let path = std::env::temp_dir().join(format!("rust_crawler_sites_{}.toml", std::process::id()));
+        std::fs::write(&path, "[sites.biquge]\nbase_url = \"https://www.biquge.com/\"\n").unwrap();
+        let overrides = |site: &str| Overrides { config: Some(path.clone()), site: Some(site.to_string()), ..Overrides::default() };
+        let error = load_config(overrides("alicesw")).unwrap_err();
+        assert!(error.to_string().contains("[sites.alicesw]"), "{}", error);
+        assert_eq!(load_config(overrides("biquge")).unwrap().urls.base_url, "https://www.biquge.com/");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn book_without_output_file_is_an_error() {
+        let path = std::env::temp_dir().join(format!("rust_crawler_books_{}.toml", std::process::id()));
+        std::fs::write(&path, "[[books]]\ncatalog_url = \"https://example.com/a/\"\nfile = \"a.txt\"\n\n[[books]]\ncatalog_url = \"https://example.com/b/\"\n").unwrap();
+        let error = load_config(Overrides { config: Some(path.clone()), ..Overrides::default() }).unwrap_err();
+        assert!(error.to_string().contains("第 2 本书"), "{}", error);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn batch_books_go_to_library_subdirectories() {
+        let path = std::env::temp_dir().join(format!("rust_crawler_library_books_{}.toml", std::process::id()));
+        std::fs::write(&path, "\
+[output]
+failures_file = \"failures.json\"
+
+[batch]
+output_dir = \"lib\"
+
+[[books]]
+catalog_url = \"https://example.com/a/\"
+file = \"a.epub\"
+title = \"甲/书\"
+tags = [\"仙侠\"]
+
+[[books]]
+catalog_url = \"https://example.com/b/\"
+file = \"out/b.txt\"
+").unwrap();
+        let config = load_config(Overrides { config: Some(path.clone()), ..Overrides::default() }).unwrap();
+        let files: Vec<(&str, &str)> = config.books.iter().map(|b| (b.output.file.as_str(), b.output.failures_file.as_str())).collect();
+        let join = |parts: &[&str]| parts.iter().collect::<std::path::PathBuf>().to_string_lossy().into_owned();
+        assert_eq!(files, [
+            (join(&["lib", "甲_书", "a.epub"]).as_str(), join(&["lib", "甲_书", "a.epub.failures.json"]).as_str()),
+            (join(&["lib", "b", "b.txt"]).as_str(), join(&["lib", "b", "b.txt.failures.json"]).as_str()),
+        ]);
+        assert_eq!(config.books[0].output.tags, ["仙侠"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+//! 内容摘要：每次运行把写出的章节按地址记录哈希，下次运行时与上次比较，报告站点修订过哪些章节
+//!
+//! 粒度为 chapter 时只记录整章的哈希，只能知道"某章有变化"；为 paragraph 时另记录每段的哈希，
+//! 按段落序列比较后可以报告"第812章 修改 3 段、新增 1 段"。本次没有写出的章节（失败、付费、不在抓取范围内）
+//! 沿用上次的摘要，下次抓到时仍与之比较。
+
+use crate::pipeline::{Sink, StageError};
+use crate::{ChapterResult, DigestGranularity, output_path};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+/// 汇总中最多列出的修订章节数，完整列表见摘要文件
+const MAX_LISTED: usize = 20;
+
+#[derive(Serialize, Deserialize, Default)]
+struct DigestFile {
+    #[serde(default)]
+    chapters: Vec<ChapterDigest>,
+    /// 与上次相比有变化的章节
+    #[serde(default)]
+    revised: Vec<Revision>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChapterDigest {
+    /// 章节序号，从1开始
+    index: usize,
+    url: String,
+    title: String,
+    hash: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    paragraphs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Revision {
+    index: usize,
+    url: String,
+    title: String,
+    title_changed: bool,
+    content_changed: bool,
+    /// 只有两次都按段落记录时才有段落统计
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paragraphs: Option<ParagraphChanges>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ParagraphChanges {
+    modified: usize,
+    added: usize,
+    removed: usize,
+}
+
+/// 作为 sink 接收写出的章节，在 finish 时与上次的摘要比较并保存
+pub(crate) struct DigestSink {
+    path: String,
+    granularity: DigestGranularity,
+    /// 上次的摘要，地址 -> 摘要
+    previous: HashMap<String, ChapterDigest>,
+    current: Vec<ChapterDigest>,
+    revised: Vec<Revision>,
+    new_chapters: usize,
+}
+
+impl DigestSink {
+    /// path 为空时返回 None；摘要文件不存在时从空白开始，本次所有章节都记为新增
+    pub(crate) fn open(path: &str, granularity: DigestGranularity) -> Option<Self> {
+        if path.is_empty() {
+            return None;
+        }
+        let previous = match std::fs::read_to_string(output_path(path)) {
+            Ok(json) => serde_json::from_str::<DigestFile>(&json).unwrap_or_else(|e| {
+                warn!("摘要文件解析失败，本次不做比较: {} ({})", path, e);
+                DigestFile::default()
+            }),
+            Err(_) => DigestFile::default(),
+        };
+        let previous = previous.chapters.into_iter().map(|chapter| (chapter.url.clone(), chapter)).collect();
+        Some(DigestSink {
+            path: path.to_string(),
+            granularity,
+            previous,
+            current: Vec::new(),
+            revised: Vec::new(),
+            new_chapters: 0,
+        })
+    }
+}
+
+fn md5_hex(text: &str) -> String {
+    format!("{:x}", md5::compute(text))
+}
+
+/// 按段落的最长公共子序列统计变化：两边都没有匹配上的段落按对数记为修改，其余记为新增或删除
+fn paragraph_changes(old: &[String], new: &[String]) -> ParagraphChanges {
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] { lengths[i + 1][j + 1] + 1 } else { lengths[i + 1][j].max(lengths[i][j + 1]) };
+        }
+    }
+    let common = lengths[0][0];
+    let (removed, added) = (old.len() - common, new.len() - common);
+    let modified = removed.min(added);
+    ParagraphChanges { modified, added: added - modified, removed: removed - modified }
+}
+
+impl Sink for DigestSink {
+    fn write(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        let paragraphs: Vec<String> = match self.granularity {
+            DigestGranularity::Chapter => Vec::new(),
+            // 段落哈希只取前 16 位，足以区分同一章内的段落，摘要文件也不会太大
+            DigestGranularity::Paragraph => result.content.iter().map(|para| md5_hex(para)[..16].to_string()).collect(),
+        };
+        let digest = ChapterDigest {
+            index: result.index + 1,
+            url: result.url.clone(),
+            title: result.title.clone(),
+            hash: md5_hex(&result.content.join("\n")),
+            paragraphs,
+        };
+        match self.previous.get(&digest.url) {
+            None => self.new_chapters += 1,
+            Some(old) => {
+                let title_changed = old.title != digest.title;
+                let content_changed = old.hash != digest.hash;
+                if title_changed || content_changed {
+                    let paragraphs = (content_changed && !old.paragraphs.is_empty() && !digest.paragraphs.is_empty())
+                        .then(|| paragraph_changes(&old.paragraphs, &digest.paragraphs));
+                    self.revised.push(Revision {
+                        index: digest.index,
+                        url: digest.url.clone(),
+                        title: digest.title.clone(),
+                        title_changed,
+                        content_changed,
+                        paragraphs,
+                    });
+                }
+            }
+        }
+        self.current.push(digest);
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), StageError> {
+        if !self.previous.is_empty() {
+            info!(
+                "与上次相比: {} 章有修订，新增 {} 章",
+                self.revised.len(), self.new_chapters
+            );
+            for revision in self.revised.iter().take(MAX_LISTED) {
+                let mut changes = Vec::new();
+                if revision.title_changed {
+                    changes.push("标题变化".to_string());
+                }
+                match &revision.paragraphs {
+                    Some(p) => {
+                        for (count, label) in [(p.modified, "修改"), (p.added, "新增"), (p.removed, "删除")] {
+                            if count > 0 {
+                                changes.push(format!("{} {} 段", label, count));
+                            }
+                        }
+                    }
+                    None if revision.content_changed => changes.push("正文变化".to_string()),
+                    None => {}
+                }
+                info!("  [{}] {}: {}", revision.index, revision.title, changes.join("，"));
+            }
+            if self.revised.len() > MAX_LISTED {
+                info!("  ……其余 {} 章见 {}", self.revised.len() - MAX_LISTED, self.path);
+            }
+        }
+        for chapter in &self.current {
+            self.previous.remove(&chapter.url);
+        }
+        let mut chapters = std::mem::take(&mut self.current);
+        chapters.extend(self.previous.drain().map(|(_, chapter)| chapter));
+        chapters.sort_by_key(|chapter| chapter.index);
+        let file = DigestFile { chapters, revised: std::mem::take(&mut self.revised) };
+        std::fs::write(output_path(&self.path), serde_json::to_string(&file)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paras(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn changes(modified: usize, added: usize, removed: usize) -> ParagraphChanges {
+        ParagraphChanges { modified, added, removed }
+    }
+
+    #[test]
+    fn paragraph_changes_count_edits() {
+        let old = paras(&["a", "b", "c"]);
+        assert_eq!(paragraph_changes(&old, &old), changes(0, 0, 0));
+        assert_eq!(paragraph_changes(&old, &paras(&["a", "x", "b", "c"])), changes(0, 1, 0));
+        assert_eq!(paragraph_changes(&old, &paras(&["a", "c"])), changes(0, 0, 1));
+        assert_eq!(paragraph_changes(&old, &paras(&["a", "B", "c"])), changes(1, 0, 0));
+        assert_eq!(paragraph_changes(&old, &paras(&["A", "b", "y", "z"])), changes(2, 1, 0));
+        assert_eq!(paragraph_changes(&old, &paras(&["c", "b", "a"])), changes(2, 0, 0));
+    }
+
+    #[test]
+    fn paragraph_changes_with_empty_sides() {
+        assert_eq!(paragraph_changes(&[], &[]), changes(0, 0, 0));
+        assert_eq!(paragraph_changes(&[], &paras(&["a", "b"])), changes(0, 2, 0));
+        assert_eq!(paragraph_changes(&paras(&["a", "b"]), &[]), changes(0, 0, 2));
+    }
+}
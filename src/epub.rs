@@ -0,0 +1,389 @@
+//! EPUB 输出：每章一个 XHTML 文件，附带元数据、目录和可选封面
+//!
+//! 抓取全部结束并经过清洗后，sink 阶段按章节顺序把每章写入压缩包，目录（nav.xhtml / toc.ncx）和 content.opf
+//! 在 finish 时补上。
+
+use crate::pipeline::{Sink, StageError};
+use crate::{ChapterResult, FsyncPolicy, PARTIAL_MARKER, html_escape};
+use crate::sinks::{placeholder_text, placeholder_title};
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+use tracing::debug;
+
+const STYLESHEET: &str = "body{margin:0 5%;line-height:1.6}\n\
+h1{font-size:1.4em;text-align:center;margin:1em 0}\n\
+p{text-indent:2em;margin:0.4em 0}\n\
+p.notice{text-indent:0;text-align:center;color:#888}\n\
+div.cover{text-align:center}\n\
+div.cover img{max-width:100%;max-height:100%}\n";
+
+/// 书籍元数据，写入 content.opf
+pub(crate) struct EpubMetadata {
+    pub(crate) title: String,
+    pub(crate) author: String,
+    /// 每个标签一个 dc:subject，阅读器和书库软件据此分类
+    pub(crate) tags: Vec<String>,
+    pub(crate) language: String,
+    /// 用于生成稳定的书籍标识，同一来源多次抓取得到同一本书
+    pub(crate) source: String,
+}
+
+/// 已写入的一个章节页面
+struct EpubPage {
+    file: String,
+    title: String,
+    volume: Option<String>,
+    /// 占位页为 0
+    chars: usize,
+    /// 是否为实际抓到的章节（而非失败或付费占位页）
+    fetched: bool,
+}
+
+struct EpubCover {
+    file: String,
+    media_type: &'static str,
+}
+
+pub(crate) struct EpubSink {
+    zip: ZipWriter<File>,
+    metadata: EpubMetadata,
+    cover: Option<EpubCover>,
+    pages: Vec<EpubPage>,
+    fsync: FsyncPolicy,
+}
+
+/// 按扩展名判断封面图片类型
+fn image_media_type(path: &str) -> Option<&'static str> {
+    let lower = path.to_ascii_lowercase();
+    if lower.ends_with(".jpg") || lower.ends_with(".jpeg") {
+        Some("image/jpeg")
+    } else if lower.ends_with(".png") {
+        Some("image/png")
+    } else if lower.ends_with(".gif") {
+        Some("image/gif")
+    } else if lower.ends_with(".webp") {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+fn xhtml_page(title: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n\
+         <head><meta charset=\"utf-8\"/><title>{}</title><link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/></head>\n\
+         <body>\n{}</body>\n</html>\n",
+        html_escape(title), body
+    )
+}
+
+impl EpubSink {
+    /// 创建压缩包并写入 mimetype、容器描述、样式表和封面，cover 为空表示不带封面
+    pub(crate) fn create(output_file: File, metadata: EpubMetadata, cover: &str, fsync: FsyncPolicy) -> Result<Self, Box<dyn Error>> {
+        let mut zip = ZipWriter::new(output_file);
+        // 规范要求 mimetype 为第一个文件且不压缩
+        zip.start_file("mimetype", SimpleFileOptions::default().compression_method(CompressionMethod::Stored))?;
+        zip.write_all(b"application/epub+zip")?;
+        zip.start_file("META-INF/container.xml", SimpleFileOptions::default())?;
+        zip.write_all(
+            b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+              <container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+              <rootfiles><rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/></rootfiles>\n\
+              </container>\n",
+        )?;
+        zip.start_file("OEBPS/style.css", SimpleFileOptions::default())?;
+        zip.write_all(STYLESHEET.as_bytes())?;
+
+        let cover = if cover.is_empty() {
+            None
+        } else {
+            let media_type = image_media_type(cover).ok_or_else(|| format!("不支持的封面图片格式: {}", cover))?;
+            let image = std::fs::read(cover).map_err(|e| format!("无法读取封面图片 {}: {}", cover, e))?;
+            let extension = cover.rsplit('.').next().unwrap_or("jpg").to_ascii_lowercase();
+            let file = format!("cover.{}", extension);
+            // 图片本身已压缩，再压一遍只会浪费时间
+            zip.start_file(format!("OEBPS/{}", file), SimpleFileOptions::default().compression_method(CompressionMethod::Stored))?;
+            zip.write_all(&image)?;
+            zip.start_file("OEBPS/cover.xhtml", SimpleFileOptions::default())?;
+            let body = format!("<div class=\"cover\"><img src=\"{}\" alt=\"{}\"/></div>\n", file, html_escape(&metadata.title));
+            zip.write_all(xhtml_page(&metadata.title, &body).as_bytes())?;
+            Some(EpubCover { file, media_type })
+        };
+
+        Ok(EpubSink { zip, metadata, cover, pages: Vec::new(), fsync })
+    }
+
+    fn write_page(&mut self, result: &ChapterResult, title: String, body: &str) -> Result<(), StageError> {
+        let file = format!("chapter_{:05}.xhtml", result.index + 1);
+        self.zip.start_file(format!("OEBPS/{}", file), SimpleFileOptions::default())?;
+        self.zip.write_all(xhtml_page(&title, body).as_bytes())?;
+        let chars = if result.success { result.chars } else { 0 };
+        self.pages.push(EpubPage { file, title, volume: result.volume.clone(), chars, fetched: result.success });
+        Ok(())
+    }
+
+    /// 把连续属于同一分卷的页面归为一组，没有分卷的页面各自成组
+    fn volume_groups(&self) -> Vec<(Option<&str>, &[EpubPage])> {
+        let mut groups: Vec<(Option<&str>, &[EpubPage])> = Vec::new();
+        let mut start = 0;
+        for end in 1..=self.pages.len() {
+            let volume = self.pages[start].volume.as_deref();
+            if end == self.pages.len() || volume.is_none() || self.pages[end].volume.as_deref() != volume {
+                groups.push((volume, &self.pages[start..end]));
+                start = end;
+            }
+        }
+        groups
+    }
+
+    /// 由来源地址的 MD5 生成 urn:uuid 形式的书籍标识
+    fn identifier(&self) -> String {
+        let hex = format!("{:x}", md5::compute(self.metadata.source.as_bytes()));
+        format!("urn:uuid:{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+    }
+
+    fn render_opf(&self) -> String {
+        let mut manifest = String::from(
+            "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+             <item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>\n\
+             <item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n",
+        );
+        let mut spine = String::new();
+        let mut cover_meta = String::new();
+        if let Some(cover) = &self.cover {
+            manifest.push_str(&format!("<item id=\"cover-image\" href=\"{}\" media-type=\"{}\" properties=\"cover-image\"/>\n", cover.file, cover.media_type));
+            manifest.push_str("<item id=\"cover\" href=\"cover.xhtml\" media-type=\"application/xhtml+xml\"/>\n");
+            spine.push_str("<itemref idref=\"cover\" linear=\"no\"/>\n");
+            cover_meta.push_str("<meta name=\"cover\" content=\"cover-image\"/>\n");
+        }
+        for (i, page) in self.pages.iter().enumerate() {
+            manifest.push_str(&format!("<item id=\"c{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n", i + 1, page.file));
+            spine.push_str(&format!("<itemref idref=\"c{}\"/>\n", i + 1));
+        }
+        let author = if self.metadata.author.is_empty() {
+            String::new()
+        } else {
+            format!("<dc:creator>{}</dc:creator>\n", html_escape(&self.metadata.author))
+        };
+        let subjects: String = self.metadata.tags.iter()
+            .map(|tag| tag.trim())
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| format!("<dc:subject>{}</dc:subject>\n", html_escape(tag)))
+            .collect();
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n\
+             <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+             <dc:identifier id=\"book-id\">{}</dc:identifier>\n\
+             <dc:title>{}</dc:title>\n\
+             {}{}<dc:language>{}</dc:language>\n\
+             <dc:source>{}</dc:source>\n\
+             <meta property=\"dcterms:modified\">{}</meta>\n\
+             {}</metadata>\n\
+             <manifest>\n{}</manifest>\n\
+             <spine toc=\"ncx\">\n{}</spine>\n\
+             </package>\n",
+            self.identifier(), html_escape(&self.metadata.title), author, subjects, html_escape(&self.metadata.language),
+            html_escape(&self.metadata.source), chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            cover_meta, manifest, spine
+        )
+    }
+
+    /// EPUB 3 阅读器使用的目录页，有分卷时章节嵌套在分卷下
+    fn render_nav(&self) -> String {
+        let mut items = String::new();
+        for (volume, pages) in self.volume_groups() {
+            let mut chapters = String::new();
+            for page in pages {
+                chapters.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", page.file, html_escape(&page.title)));
+            }
+            match volume {
+                Some(name) => items.push_str(&format!(
+                    "<li><a href=\"{}\">{}</a>\n<ol>\n{}</ol>\n</li>\n",
+                    pages[0].file, html_escape(&volume_label(name, pages)), chapters
+                )),
+                None => items.push_str(&chapters),
+            }
+        }
+        xhtml_page("目录", &format!("<nav epub:type=\"toc\" id=\"toc\">\n<h1>目录</h1>\n<ol>\n{}</ol>\n</nav>\n", items))
+    }
+
+    /// 兼容只认 EPUB 2 目录的旧阅读器
+    fn render_ncx(&self) -> String {
+        let mut points = String::new();
+        let mut order = 0;
+        for (volume, pages) in self.volume_groups() {
+            let volume_order = volume.map(|_| {
+                order += 1;
+                order
+            });
+            let mut chapters = String::new();
+            for page in pages {
+                order += 1;
+                chapters.push_str(&nav_point(order, &page.title, &page.file, ""));
+            }
+            match (volume, volume_order) {
+                (Some(name), Some(volume_order)) => {
+                    points.push_str(&nav_point(volume_order, &volume_label(name, pages), &pages[0].file, &format!("\n{}", chapters)));
+                }
+                _ => points.push_str(&chapters),
+            }
+        }
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\n\
+             <head><meta name=\"dtb:uid\" content=\"{}\"/></head>\n\
+             <docTitle><text>{}</text></docTitle>\n\
+             <navMap>\n{}</navMap>\n\
+             </ncx>\n",
+            self.identifier(), html_escape(&self.metadata.title), points
+        )
+    }
+}
+
+fn nav_point(order: usize, label: &str, file: &str, children: &str) -> String {
+    format!(
+        "<navPoint id=\"p{0}\" playOrder=\"{0}\"><navLabel><text>{1}</text></navLabel><content src=\"{2}\"/>{3}</navPoint>\n",
+        order, html_escape(label), file, children
+    )
+}
+
+/// 目录中的分卷标题，附带已抓到的章节数和字数，如 "第一卷 风起（48/50章，152340字）"
+fn volume_label(name: &str, pages: &[EpubPage]) -> String {
+    let fetched = pages.iter().filter(|page| page.fetched).count();
+    let chars: usize = pages.iter().map(|page| page.chars).sum();
+    format!("{}（{}/{}章，{}字）", name, fetched, pages.len(), chars)
+}
+
+impl Sink for EpubSink {
+    fn write(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        debug!("第{}章: {}", result.index + 1, result.title);
+        let mut body = format!("<h1>{}</h1>\n", html_escape(&result.title));
+        if result.partial {
+            body.push_str(&format!("<p class=\"notice\">{}</p>\n", PARTIAL_MARKER));
+        }
+        for para in &result.content {
+            if para.is_empty() {
+                body.push_str("<p><br/></p>\n");
+            } else {
+                body.push_str(&format!("<p>{}</p>\n", html_escape(para)));
+            }
+        }
+        self.write_page(result, result.toc_title().to_string(), &body)
+    }
+
+    fn write_placeholder(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        let Some(notice) = placeholder_text(result) else {
+            return Ok(());
+        };
+        let title = placeholder_title(result);
+        let body = format!("<h1>{}</h1>\n<p class=\"notice\">{}</p>\n", html_escape(&title), html_escape(&notice));
+        self.write_page(result, title, &body)
+    }
+
+    /// 补上目录和 content.opf，关闭压缩包后按 fsync 策略落盘；
+    /// 压缩包要写完中央目录才完整，per-chapter 在这里与 at-end 等价
+    fn finish(mut self: Box<Self>) -> Result<(), StageError> {
+        let nav = self.render_nav();
+        self.zip.start_file("OEBPS/nav.xhtml", SimpleFileOptions::default())?;
+        self.zip.write_all(nav.as_bytes())?;
+        let ncx = self.render_ncx();
+        self.zip.start_file("OEBPS/toc.ncx", SimpleFileOptions::default())?;
+        self.zip.write_all(ncx.as_bytes())?;
+        let opf = self.render_opf();
+        self.zip.start_file("OEBPS/content.opf", SimpleFileOptions::default())?;
+        self.zip.write_all(opf.as_bytes())?;
+        let sink = *self;
+        let mut output_file = sink.zip.finish()?;
+        output_file.flush()?;
+        if sink.fsync != FsyncPolicy::None {
+            output_file.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn metadata() -> EpubMetadata {
+        EpubMetadata {
+            title: "测试<书>".to_string(),
+            author: "作者".to_string(),
+            tags: vec!["仙侠".to_string(), " ".to_string()],
+            language: "zh-CN".to_string(),
+            source: "https://example.com/book/".to_string(),
+        }
+    }
+
+    #[test]
+    fn renders_chapters_and_placeholders() {
+        let path = std::env::temp_dir().join(format!("rust_crawler_epub_{}.epub", std::process::id()));
+        let mut sink = Box::new(EpubSink::create(File::create(&path).unwrap(), metadata(), "", FsyncPolicy::None).unwrap());
+        let now = chrono::Utc::now();
+        let mut first = ChapterResult::success(0, "第一章 & 开端".to_string(), "https://example.com/1.html".to_string(), vec!["正文".to_string()], 0, now);
+        first.volume = Some("第一卷".to_string());
+        sink.write(&first).unwrap();
+        let mut paywalled = ChapterResult::paywalled(1, "https://example.com/2.html".to_string(), "VIP", 0, now);
+        paywalled.volume = Some("第一卷".to_string());
+        sink.write_placeholder(&paywalled).unwrap();
+        let mut failed = ChapterResult::failure(2, "https://example.com/3.html".to_string(), "HTTP 500".to_string(), 0, now);
+        failed.catalog_title = Some("第三章 远行".to_string());
+        sink.write_placeholder(&failed).unwrap();
+
+        let nav = sink.render_nav();
+        assert!(nav.contains("<li><a href=\"chapter_00001.xhtml\">第一卷（1/2章，2字）</a>\n<ol>\n"));
+        assert!(nav.contains("<li><a href=\"chapter_00001.xhtml\">第一章 &amp; 开端</a></li>\n"));
+        assert!(nav.contains("<li><a href=\"chapter_00002.xhtml\">第2章（付费章节）</a></li>\n</ol>\n</li>\n"));
+        assert!(nav.contains("<li><a href=\"chapter_00003.xhtml\">第三章 远行（抓取失败）</a></li>\n</ol>\n</nav>"));
+
+        let opf = sink.render_opf();
+        assert!(opf.contains("<dc:title>测试&lt;书&gt;</dc:title>"));
+        assert!(opf.contains("<dc:creator>作者</dc:creator>\n<dc:subject>仙侠</dc:subject>\n<dc:language>"));
+        assert_eq!(opf.matches("<dc:subject>").count(), 1);
+        assert!(opf.contains("<itemref idref=\"c1\"/>\n<itemref idref=\"c2\"/>\n<itemref idref=\"c3\"/>\n</spine>"));
+        assert!(!opf.contains("cover"));
+        sink.finish().unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut mimetype = archive.by_index(0).unwrap();
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), CompressionMethod::Stored);
+        let mut text = String::new();
+        mimetype.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "application/epub+zip");
+        drop(mimetype);
+        let mut page = String::new();
+        archive.by_name("OEBPS/chapter_00002.xhtml").unwrap().read_to_string(&mut page).unwrap();
+        assert!(page.contains("<p class=\"notice\">【本章为付费章节，未抓取: https://example.com/2.html】</p>"));
+        for name in ["META-INF/container.xml", "OEBPS/nav.xhtml", "OEBPS/toc.ncx", "OEBPS/content.opf"] {
+            assert!(archive.by_name(name).is_ok(), "{}", name);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cover_is_stored_and_listed() {
+        let dir = std::env::temp_dir();
+        let cover = dir.join(format!("rust_crawler_cover_{}.PNG", std::process::id()));
+        std::fs::write(&cover, b"\x89PNG").unwrap();
+        let path = dir.join(format!("rust_crawler_cover_{}.epub", std::process::id()));
+        let sink = EpubSink::create(File::create(&path).unwrap(), metadata(), &cover.to_string_lossy(), FsyncPolicy::None).unwrap();
+        let opf = sink.render_opf();
+        assert!(opf.contains("<item id=\"cover-image\" href=\"cover.png\" media-type=\"image/png\" properties=\"cover-image\"/>"));
+        assert!(opf.contains("<itemref idref=\"cover\" linear=\"no\"/>"));
+        Box::new(sink).finish().unwrap();
+        let mut archive = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(archive.by_name("OEBPS/cover.png").unwrap().compression(), CompressionMethod::Stored);
+
+        assert!(EpubSink::create(File::create(&path).unwrap(), metadata(), "cover.bmp", FsyncPolicy::None).is_err());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&cover).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+//! 测试夹具录制：抓取单个页面，保存清理过的 HTML 与按当前配置解析出的结果，
+//! 站点改版、调整选择器后可以据此补一个回归测试
+
+use crate::{Config, Identity, PageOutcome, build_extractor, format_time_rfc3339, parse_catalog_page, parse_optional_selector, read_html};
+use regex::Regex;
+use serde::Serialize;
+use std::error::Error;
+use std::path::Path;
+use std::sync::LazyLock;
+use tracing::info;
+
+const FIXTURE_DIR: &str = "tests/fixtures";
+
+/// 与 HTML 一同保存的解析结果
+#[derive(Serialize)]
+struct Fixture {
+    url: String,
+    final_url: String,
+    status: u16,
+    recorded_at: String,
+    /// chapter / paywalled / redirect / title-missing
+    outcome: &'static str,
+    title: String,
+    paragraphs: Vec<String>,
+    notes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paywall_marker: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirect: Option<String>,
+    /// 按 chapter_link_selector 找到的章节链接，录制的是目录页时才有
+    catalog_links: Vec<String>,
+}
+
+/// 去掉脚本、样式、注释和 iframe：与解析无关，且常带每次请求都不同的统计代码和令牌；
+/// 使用内嵌 JSON 提取时正文就在脚本里，此时保留脚本
+fn scrub(html: &str, keep_scripts: bool) -> String {
+    static SCRIPT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<script\b[^>]*>.*?</script>").unwrap());
+    static NOISE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?is)<style\b[^>]*>.*?</style>|<iframe\b[^>]*>.*?</iframe>|<noscript\b[^>]*>.*?</noscript>|<!--.*?-->").unwrap()
+    });
+    let html = NOISE.replace_all(html, "");
+    if keep_scripts {
+        html.into_owned()
+    } else {
+        SCRIPT.replace_all(&html, "").into_owned()
+    }
+}
+
+/// 由页面地址生成文件名，如 https://www.example.com/book/12.html -> www.example.com_book_12_html
+fn fixture_name(url: &reqwest::Url) -> String {
+    let path: String = url.path().chars().map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' }).collect();
+    format!("{}_{}", url.host_str().unwrap_or("page"), path.trim_matches('_')).trim_end_matches('_').to_string()
+}
+
+pub(crate) async fn record(config: &Config, identity: &Identity, url: &str, name: Option<&str>) -> Result<(), Box<dyn Error>> {
+    identity.throttle(url).await;
+    let resp = identity.get(url).send().await?;
+    let status = resp.status().as_u16();
+    let final_url = resp.url().clone();
+    let html = read_html(resp).await?;
+    let html = scrub(&html, !config.selectors.json_state_pattern.is_empty());
+
+    let mut fixture = Fixture {
+        url: url.to_string(),
+        final_url: final_url.to_string(),
+        status,
+        recorded_at: format_time_rfc3339(chrono::Utc::now()),
+        outcome: "title-missing",
+        title: String::new(),
+        paragraphs: Vec::new(),
+        notes: Vec::new(),
+        paywall_marker: None,
+        redirect: None,
+        catalog_links: Vec::new(),
+    };
+    let extractor = match identity.hosts.pages(final_url.as_str()) {
+        Some(pages) => pages.extractor.as_ref(),
+        None => &*build_extractor(&config.selectors, &config.crawl.paywall_markers)?,
+    };
+    match extractor.extract(&html, &final_url) {
+        PageOutcome::Chapter(title, paragraphs, notes) => {
+            fixture.outcome = "chapter";
+            fixture.title = title;
+            fixture.paragraphs = paragraphs;
+            fixture.notes = notes;
+        }
+        PageOutcome::Paywalled(marker) => {
+            fixture.outcome = "paywalled";
+            fixture.paywall_marker = Some(marker);
+        }
+        PageOutcome::Redirect(next) => {
+            fixture.outcome = "redirect";
+            fixture.redirect = Some(next.to_string());
+        }
+        PageOutcome::TitleMissing => {}
+    }
+    let link_sel = parse_optional_selector(&config.selectors.chapter_link_selector)?.ok_or("chapter_link_selector 不能为空")?;
+    let (links, _) = parse_catalog_page(&html, &final_url, &config.urls.base_url, &config.urls.strip_query_params, &link_sel, None, None);
+    fixture.catalog_links = links.into_iter().map(|link| link.url).collect();
+
+    let name = name.map_or_else(|| fixture_name(&final_url), str::to_string);
+    let dir = Path::new(FIXTURE_DIR);
+    std::fs::create_dir_all(dir)?;
+    let html_path = dir.join(format!("{}.html", name));
+    let json_path = dir.join(format!("{}.json", name));
+    std::fs::write(&html_path, &html)?;
+    std::fs::write(&json_path, serde_json::to_string_pretty(&fixture)?)?;
+    info!("已保存夹具: {} | {}", html_path.display(), json_path.display());
+    info!(
+        "HTTP {} | 解析结果: {} | 标题: {} | 正文 {} 段 | 注释 {} 段 | 章节链接 {} 个",
+        status, fixture.outcome, fixture.title, fixture.paragraphs.len(), fixture.notes.len(), fixture.catalog_links.len()
+    );
+    Ok(())
+}
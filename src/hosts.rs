@@ -0,0 +1,102 @@
+//! 按域名覆盖配置：[hosts."域名"] 中的选择器、限速、请求头和并发上限只作用于该域名及其子域名的请求，
+//! 章节列表混有多个镜像站时不必拆成几份配置分别抓取
+
+use crate::pipeline::Extract;
+use crate::prefilter::{HtmlPrefilter, PrefilterStats};
+use crate::{Config, RateLimiter, build_extractor, build_prefilter, host_matches, parse_optional_selector};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// 章节页的解析规则：正文提取方式与正文分页链接
+pub(crate) struct PageRules {
+    pub(crate) extractor: Box<dyn Extract>,
+    pub(crate) content_next_sel: Option<scraper::Selector>,
+    pub(crate) prefilter: Option<HtmlPrefilter>,
+}
+
+struct HostRule {
+    domain: String,
+    /// 填写了选择器时才有，否则沿用全局解析规则
+    pages: Option<PageRules>,
+    /// 外层为 None 时沿用全局限速，内层为 None 表示该域名不限速
+    limiter: Option<Option<Arc<RateLimiter>>>,
+    permits: Option<Arc<Semaphore>>,
+    headers: HeaderMap,
+}
+
+#[derive(Default)]
+pub(crate) struct HostOverrides {
+    /// 按域名长度从长到短排列，子域名的规则优先于父域名
+    rules: Vec<HostRule>,
+    /// 全局与各域名的 HTML 预过滤共用的统计
+    pub(crate) prefilter_stats: Arc<PrefilterStats>,
+}
+
+impl HostOverrides {
+    pub(crate) fn new(config: &Config) -> Result<Self, Box<dyn Error>> {
+        let prefilter_stats = Arc::new(PrefilterStats::default());
+        let mut rules = Vec::new();
+        for (domain, host) in &config.hosts {
+            let pages = if host.selectors.is_empty() {
+                None
+            } else {
+                let selectors = host.selectors.apply(&config.selectors);
+                Some(PageRules {
+                    extractor: build_extractor(&selectors, &config.crawl.paywall_markers)?,
+                    content_next_sel: parse_optional_selector(&selectors.content_next_page_selector)?,
+                    prefilter: build_prefilter(config, &selectors, &prefilter_stats),
+                })
+            };
+            let limiter = (host.min_delay_ms.is_some() || host.max_delay_ms.is_some()).then(|| {
+                let min_delay_ms = host.min_delay_ms.unwrap_or(0);
+                RateLimiter::new(min_delay_ms, host.max_delay_ms.unwrap_or(min_delay_ms)).map(Arc::new)
+            });
+            let mut headers = HeaderMap::new();
+            for (name, value) in &host.headers {
+                let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| format!("[hosts.\"{}\"] 请求头名称 {} 无效: {}", domain, name, e))?;
+                let value = HeaderValue::from_str(value).map_err(|e| format!("[hosts.\"{}\"] 请求头 {} 的值无效: {}", domain, name, e))?;
+                headers.insert(name, value);
+            }
+            rules.push(HostRule {
+                domain: domain.clone(),
+                pages,
+                limiter,
+                permits: host.concurrent_limit.map(|limit| Arc::new(Semaphore::new(limit.max(1)))),
+                headers,
+            });
+        }
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.domain.len()));
+        Ok(HostOverrides { rules, prefilter_stats })
+    }
+
+    fn find(&self, url: &str) -> Option<&HostRule> {
+        if self.rules.is_empty() {
+            return None;
+        }
+        let url = reqwest::Url::parse(url).ok()?;
+        let host = url.host_str()?;
+        self.rules.iter().find(|rule| host_matches(host, &rule.domain))
+    }
+
+    /// 页面所在域名覆盖了选择器时返回其解析规则
+    pub(crate) fn pages(&self, url: &str) -> Option<&PageRules> {
+        self.find(url)?.pages.as_ref()
+    }
+
+    /// 域名单独配置了限速时返回其限速器（None 表示不限速），否则返回 None 沿用全局限速
+    pub(crate) fn limiter(&self, url: &str) -> Option<Option<&Arc<RateLimiter>>> {
+        self.find(url)?.limiter.as_ref().map(Option::as_ref)
+    }
+
+    pub(crate) fn headers(&self, url: &str) -> Option<&HeaderMap> {
+        self.find(url).map(|rule| &rule.headers).filter(|headers| !headers.is_empty())
+    }
+
+    /// 域名配置了并发上限时等待并取得一个许可，许可释放前该域名的其他章节排队
+    pub(crate) async fn acquire(&self, url: &str) -> Option<OwnedSemaphorePermit> {
+        let permits = self.find(url)?.permits.clone()?;
+        permits.acquire_owned().await.ok()
+    }
+}
@@ -0,0 +1,146 @@
+//! 单文件 HTML 输出：开头为可跳转的目录，随后依次是各章节
+//!
+//! 目录要等所有章节写完才知道，章节正文先写入临时文件，finish 时写出页头和目录后再把正文接上。
+
+use crate::pipeline::{Sink, StageError};
+use crate::{ChapterResult, FsyncPolicy, PARTIAL_MARKER, html_escape};
+use crate::sinks::{placeholder_text, placeholder_title};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tracing::debug;
+
+const STYLESHEET: &str = "body{max-width:42em;margin:0 auto;padding:1em 5%;line-height:1.8;font-size:1.1em;color:#222;background:#fdfdf8}\n\
+h1{text-align:center}\n\
+h2{text-align:center;margin:2.5em 0 1em}\n\
+nav ol{columns:2;padding-left:2em}\n\
+nav a,a.back{color:#36c;text-decoration:none}\n\
+p{text-indent:2em;margin:0.5em 0}\n\
+p.notice{text-indent:0;text-align:center;color:#888}\n\
+a.back{display:block;text-align:right;font-size:0.85em;margin-top:1em}\n";
+
+pub(crate) struct HtmlSink {
+    output_file: File,
+    body: BufWriter<File>,
+    body_path: PathBuf,
+    title: String,
+    /// 目录项：锚点、章节标题
+    toc: Vec<(String, String)>,
+    fsync: FsyncPolicy,
+}
+
+impl HtmlSink {
+    /// body_path 为章节正文的临时文件，finish 后删除
+    pub(crate) fn create(output_file: File, body_path: PathBuf, title: String, fsync: FsyncPolicy) -> std::io::Result<Self> {
+        let body = BufWriter::new(File::create(&body_path)?);
+        Ok(HtmlSink { output_file, body, body_path, title, toc: Vec::new(), fsync })
+    }
+
+    /// heading 为章节中的标题，toc_title 为目录中的章节名
+    fn write_section(&mut self, result: &ChapterResult, heading: &str, toc_title: String, body: &str) -> Result<(), StageError> {
+        let anchor = format!("c{}", result.index + 1);
+        write!(
+            self.body,
+            "<section id=\"{}\">\n<h2>{}</h2>\n{}<a class=\"back\" href=\"#toc\">返回目录</a>\n</section>\n",
+            anchor, html_escape(heading), body
+        )?;
+        self.toc.push((anchor, toc_title));
+        Ok(())
+    }
+}
+
+impl Sink for HtmlSink {
+    fn write(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        debug!("第{}章: {}", result.index + 1, result.title);
+        let mut body = String::new();
+        if result.partial {
+            body.push_str(&format!("<p class=\"notice\">{}</p>\n", PARTIAL_MARKER));
+        }
+        for para in &result.content {
+            if para.is_empty() {
+                body.push_str("<p><br/></p>\n");
+            } else {
+                body.push_str(&format!("<p>{}</p>\n", html_escape(para)));
+            }
+        }
+        self.write_section(result, &result.title, result.toc_title().to_string(), &body)
+    }
+
+    fn write_placeholder(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        let Some(notice) = placeholder_text(result) else {
+            return Ok(());
+        };
+        let title = placeholder_title(result);
+        self.write_section(result, &title, title.clone(), &format!("<p class=\"notice\">{}</p>\n", html_escape(&notice)))
+    }
+
+    /// 写出页头和目录，接上章节正文后按 fsync 策略落盘并删除临时文件
+    fn finish(mut self: Box<Self>) -> Result<(), StageError> {
+        self.body.flush()?;
+        let mut head = format!(
+            "<!DOCTYPE html>\n<html lang=\"zh\">\n<head>\n<meta charset=\"utf-8\"/>\n\
+             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"/>\n\
+             <title>{0}</title>\n<style>\n{1}</style>\n</head>\n<body>\n<h1>{0}</h1>\n\
+             <nav id=\"toc\">\n<ol>\n",
+            html_escape(&self.title), STYLESHEET
+        );
+        for (anchor, title) in &self.toc {
+            head.push_str(&format!("<li><a href=\"#{}\">{}</a></li>\n", anchor, html_escape(title)));
+        }
+        head.push_str("</ol>\n</nav>\n");
+        self.output_file.write_all(head.as_bytes())?;
+        std::io::copy(&mut File::open(&self.body_path)?, &mut self.output_file)?;
+        self.output_file.write_all(b"</body>\n</html>\n")?;
+        self.output_file.flush()?;
+        if self.fsync != FsyncPolicy::None {
+            self.output_file.sync_all()?;
+        }
+        let _ = std::fs::remove_file(&self.body_path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toc_precedes_sections_and_body_file_is_removed() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rust_crawler_html_{}.html", std::process::id()));
+        let body_path = dir.join(format!("rust_crawler_html_{}.html.body", std::process::id()));
+        let mut sink = Box::new(HtmlSink::create(File::create(&path).unwrap(), body_path.clone(), "书<名>".to_string(), FsyncPolicy::None).unwrap());
+        let now = chrono::Utc::now();
+        let mut first = ChapterResult::success(0, "第一章 & 开端".to_string(), "https://example.com/1.html".to_string(), vec!["<b>正文</b>".to_string(), String::new()], 0, now);
+        first.partial = true;
+        sink.write(&first).unwrap();
+        sink.write_placeholder(&ChapterResult::paywalled(1, "https://example.com/2.html".to_string(), "VIP", 0, now)).unwrap();
+        sink.write_placeholder(&ChapterResult::failure(2, "https://example.com/3.html".to_string(), "HTTP 500".to_string(), 0, now)).unwrap();
+        // 目录使用目录页中的章节名，章节中的标题仍为章节页的标题
+        let mut named = ChapterResult::success(3, "第四章 真名（求月票）".to_string(), "https://example.com/4.html".to_string(), vec![], 0, now);
+        named.catalog_title = Some("第四章 真名".to_string());
+        sink.write(&named).unwrap();
+        let mut named_failure = ChapterResult::failure(4, "https://example.com/5.html".to_string(), "HTTP 500".to_string(), 0, now);
+        named_failure.catalog_title = Some("第五章 远行".to_string());
+        sink.write_placeholder(&named_failure).unwrap();
+        sink.finish().unwrap();
+
+        let html = std::fs::read_to_string(&path).unwrap();
+        assert!(html.contains("<title>书&lt;名&gt;</title>"));
+        assert!(html.contains(
+            "<ol>\n<li><a href=\"#c1\">第一章 &amp; 开端</a></li>\n\
+             <li><a href=\"#c2\">第2章（付费章节）</a></li>\n\
+             <li><a href=\"#c3\">第3章（抓取失败）</a></li>\n\
+             <li><a href=\"#c4\">第四章 真名</a></li>\n\
+             <li><a href=\"#c5\">第五章 远行（抓取失败）</a></li>\n</ol>"
+        ));
+        assert!(html.contains("<section id=\"c4\">\n<h2>第四章 真名（求月票）</h2>"));
+        assert!(html.find("<nav id=\"toc\">").unwrap() < html.find("<section id=\"c1\">").unwrap());
+        assert!(html.contains(&format!("<p class=\"notice\">{}</p>\n<p>&lt;b&gt;正文&lt;/b&gt;</p>\n<p><br/></p>\n", PARTIAL_MARKER)));
+        assert!(html.contains("<p class=\"notice\">【本章为付费章节，未抓取: https://example.com/2.html】</p>"));
+        assert!(html.contains("<p class=\"notice\">【本章抓取失败: https://example.com/3.html】</p>"));
+        assert!(html.ends_with("</section>\n</body>\n</html>\n"));
+        assert!(!body_path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+//! 配置向导：rust_crawler init 询问目录页地址，试抓目录页和第一章，
+//! 按页面结构推测章节链接、标题和正文选择器，确认后写出带注释的 config.toml
+//!
+//! 推测规则很朴素：章节链接取包含链接最多的容器，正文取直接包含文字最多的容器，标题优先取 h1。
+//! 每一项都可以在提示时直接改写，改写后同样会试抓一次确认效果。
+
+// 向导的提示和试抓结果是交互内容，直接打印而不经过日志
+#![allow(clippy::print_stdout)]
+
+use crate::{Identity, PageOutcome, SelectorsConfig, build_extractor, dedupe_catalog_links, parse_catalog_page, read_html};
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+use tracing::info;
+
+/// 一个容器下至少有这么多链接才可能是章节列表
+const MIN_CHAPTER_LINKS: usize = 3;
+
+/// 显示提示并读取一行输入，直接回车时返回默认值
+fn ask(prompt: &str, default: &str) -> std::io::Result<String> {
+    if default.is_empty() {
+        print!("{}: ", prompt);
+    } else {
+        print!("{} [{}]: ", prompt, default);
+    }
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    if std::io::stdin().read_line(&mut line)? == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "输入已结束"));
+    }
+    let line = line.trim();
+    Ok(if line.is_empty() { default.to_string() } else { line.to_string() })
+}
+
+fn confirm(prompt: &str, default: bool) -> std::io::Result<bool> {
+    let answer = ask(&format!("{} ({})", prompt, if default { "Y/n" } else { "y/N" }), "")?;
+    Ok(match answer.to_ascii_lowercase().as_str() {
+        "" => default,
+        answer => answer.starts_with('y'),
+    })
+}
+
+/// 能直接写进选择器的 id 或类名：只含字母、数字、- 和 _，且不以数字开头
+fn css_ident(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// 元素自身的选择器：有 id 时取 #id，否则取 标签.类名，两者都没有时返回 None
+fn element_selector(elem: ElementRef) -> Option<String> {
+    let value = elem.value();
+    if let Some(id) = value.id().filter(|id| css_ident(id)) {
+        return Some(format!("#{}", id));
+    }
+    value.classes().find(|class| css_ident(class)).map(|class| format!("{}.{}", value.name(), class))
+}
+
+/// 向上找到最近的带 id 或类名的祖先元素
+fn nearest_container(elem: ElementRef) -> Option<String> {
+    elem.ancestors().filter_map(ElementRef::wrap).find_map(element_selector)
+}
+
+/// 章节链接选择器：按最近的带 id 或类名的容器给链接分组，取链接最多的一组
+fn suggest_link_selector(document: &Html) -> Option<String> {
+    let link_sel = Selector::parse("a[href]").unwrap();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut order = Vec::new();
+    for link in document.select(&link_sel) {
+        let href = link.value().attr("href").unwrap_or_default();
+        if href.starts_with('#') || href.starts_with("javascript:") {
+            continue;
+        }
+        if let Some(container) = nearest_container(link) {
+            let count = counts.entry(container.clone()).or_insert(0);
+            if *count == 0 {
+                order.push(container);
+            }
+            *count += 1;
+        }
+    }
+    // 数量相同时取页面中先出现的容器
+    let best = order.into_iter().rev().max_by_key(|container| counts[container])?;
+    (counts[&best] >= MIN_CHAPTER_LINKS).then(|| format!("{} a", best))
+}
+
+/// 标题选择器：有 h1 时取 h1（带 id 或类名时一并写上），其次取 id 或类名中含 title / name 的元素，最后取 title
+fn suggest_title_selector(document: &Html) -> String {
+    let h1_sel = Selector::parse("h1").unwrap();
+    if let Some(h1) = document.select(&h1_sel).next() {
+        return element_selector(h1).unwrap_or_else(|| "h1".to_string());
+    }
+    document.root_element().descendants().filter_map(ElementRef::wrap)
+        .filter(|elem| elem.text().any(|text| !text.trim().is_empty()))
+        .filter_map(element_selector)
+        .find(|selector| {
+            let lower = selector.to_ascii_lowercase();
+            lower.contains("title") || lower.contains("name")
+        })
+        .unwrap_or_else(|| "title".to_string())
+}
+
+/// 正文选择器：取直接包含文字（含直接子段落）最多的带 id 或类名的元素；
+/// 其中有多个 p 段落时选段落，否则整块作为正文。title_selector 选中的标题元素不参与比较
+fn suggest_content_selector(document: &Html, title_selector: &str) -> Option<String> {
+    let p_sel = Selector::parse("p").unwrap();
+    let mut best: Option<(usize, String, usize)> = None;
+    for elem in document.root_element().descendants().filter_map(ElementRef::wrap) {
+        let Some(selector) = element_selector(elem).filter(|selector| selector != title_selector) else {
+            continue;
+        };
+        let mut text_len = 0;
+        let mut paragraphs = 0;
+        for child in elem.children() {
+            if let Some(text) = child.value().as_text() {
+                text_len += text.trim().chars().count();
+            } else if let Some(child) = ElementRef::wrap(child) && p_sel.matches(&child) {
+                text_len += child.text().map(|t| t.trim().chars().count()).sum::<usize>();
+                paragraphs += 1;
+            }
+        }
+        if best.as_ref().is_none_or(|(len, _, _)| text_len > *len) {
+            best = Some((text_len, selector, paragraphs));
+        }
+    }
+    let (_, selector, paragraphs) = best.filter(|(len, _, _)| *len > 0)?;
+    Some(if paragraphs >= 3 { format!("{} p", selector) } else { selector })
+}
+
+/// 书名：目录页的 h1，没有时取 title 中第一个分隔符之前的部分
+fn book_title(document: &Html) -> String {
+    let sel = Selector::parse("h1, title").unwrap();
+    let text = document.select(&sel).next().map(|elem| elem.text().collect::<String>()).unwrap_or_default();
+    text.split(['_', '|', '-', '—']).next().unwrap_or_default().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// 去掉文件名中不能使用的字符
+fn file_name(title: &str) -> String {
+    let name: String = title.chars().filter(|c| !matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|')).collect();
+    if name.trim().is_empty() { "output".to_string() } else { name.trim().to_string() }
+}
+
+async fn fetch(identity: &Identity, url: &str) -> Result<(reqwest::Url, String), Box<dyn Error>> {
+    identity.throttle(url).await;
+    let resp = identity.get(url).send().await?;
+    if !resp.status().is_success() {
+        return Err(format!("{} 返回 HTTP {}", url, resp.status()).into());
+    }
+    let final_url = resp.url().clone();
+    Ok((final_url, read_html(resp).await?))
+}
+
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+pub(crate) async fn wizard(identity: &Identity, path: &Path) -> Result<(), Box<dyn Error>> {
+    if path.exists() && !confirm(&format!("{} 已存在，是否覆盖", path.display()), false)? {
+        return Ok(());
+    }
+    let catalog_url = loop {
+        let url = ask("目录页地址", "")?;
+        match reqwest::Url::parse(&url) {
+            Ok(url) if url.scheme().starts_with("http") => break url,
+            _ => println!("请输入以 http:// 或 https:// 开头的完整地址"),
+        }
+    };
+    let base_url = format!("{}/", catalog_url.origin().ascii_serialization());
+
+    info!("正在抓取目录页: {}", catalog_url);
+    let (catalog_final, catalog_html) = fetch(identity, catalog_url.as_str()).await?;
+    let catalog = Html::parse_document(&catalog_html);
+    let mut link_default = suggest_link_selector(&catalog).unwrap_or_default();
+    let chapter_urls = loop {
+        let selector = ask("章节链接选择器", &link_default)?;
+        let Ok(link_sel) = Selector::parse(&selector) else {
+            println!("无效的选择器: {}", selector);
+            continue;
+        };
+        let (links, _) = parse_catalog_page(&catalog_html, &catalog_final, &base_url, &[], &link_sel, None, None);
+        let (urls, _) = dedupe_catalog_links(links);
+        match (urls.first(), urls.last()) {
+            (Some(first), Some(last)) => {
+                println!("找到 {} 个章节链接，第一个: {}，最后一个: {}", urls.len(), first, last);
+                if confirm("使用这个选择器", true)? {
+                    link_default = selector;
+                    break urls;
+                }
+            }
+            _ => println!("选择器 {} 在目录页中没有匹配到链接", selector),
+        }
+        link_default = selector;
+    };
+
+    info!("正在试抓第一章: {}", chapter_urls[0]);
+    let (chapter_final, chapter_html) = fetch(identity, &chapter_urls[0]).await?;
+    let chapter = Html::parse_document(&chapter_html);
+    let title_selector = suggest_title_selector(&chapter);
+    let mut selectors = SelectorsConfig {
+        content_selector: suggest_content_selector(&chapter, &title_selector).unwrap_or_default(),
+        title_selector,
+        chapter_link_selector: link_default,
+        ..SelectorsConfig::default()
+    };
+    loop {
+        selectors.title_selector = ask("章节标题选择器", &selectors.title_selector)?;
+        selectors.content_selector = ask("正文选择器", &selectors.content_selector)?;
+        let extractor = match build_extractor(&selectors, &[]) {
+            Ok(extractor) => extractor,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        match extractor.extract(&chapter_html, &chapter_final) {
+            PageOutcome::Chapter(title, paragraphs, _) => {
+                let paragraphs: Vec<&String> = paragraphs.iter().filter(|p| !p.trim().is_empty()).collect();
+                let preview: String = paragraphs.first().map(|p| p.trim().chars().take(60).collect()).unwrap_or_default();
+                println!("标题: {}", title.trim());
+                println!("正文 {} 段，第一段: {}", paragraphs.len(), preview);
+                if !paragraphs.is_empty() && confirm("使用这两个选择器", true)? {
+                    break;
+                }
+            }
+            _ => println!("标题选择器 {} 在章节页中没有匹配到内容", selectors.title_selector),
+        }
+    }
+
+    let output_file = ask("输出文件", &format!("{}.txt", file_name(&book_title(&catalog))))?;
+    // 按扩展名选择输出格式，txt 为默认值不必写出
+    let format = match Path::new(&output_file).extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("epub") => "epub",
+        Some("md") => "markdown",
+        Some("html") => "html",
+        Some("json") => "json",
+        Some("ndjson") => "ndjson",
+        _ => "",
+    };
+    let format = if format.is_empty() {
+        String::new()
+    } else {
+        format!("\n# 输出格式：txt / epub / markdown / html / json / ndjson\nformat = \"{}\"\n", format)
+    };
+
+    let content = format!(
+        "# 由 rust_crawler init 生成于 {generated}\n\
+         # 只包含抓取这本书所需的配置项，其余选项及说明见 config_example.toml\n\
+         \n\
+         [urls]\n\
+         # 基础URL，章节链接为相对地址时拼接在其后\n\
+         base_url = {base_url}\n\
+         \n\
+         # 章节汇总页面URL\n\
+         catalog_url = {catalog_url}\n\
+         \n\
+         [selectors]\n\
+         # 章节标题CSS选择器，在章节页中匹配第一个元素\n\
+         title_selector = {title}\n\
+         \n\
+         # 正文内容CSS选择器，在章节页中匹配，每个匹配的元素为一段\n\
+         content_selector = {content}\n\
+         \n\
+         # 章节链接CSS选择器，在目录页中匹配，按页面顺序抓取\n\
+         chapter_link_selector = {link}\n\
+         \n\
+         [output]\n\
+         # 输出文件名\n\
+         file = {file}\n\
+         {format}",
+        generated = chrono::Local::now().format("%Y-%m-%d %H:%M"),
+        base_url = toml_string(&base_url),
+        catalog_url = toml_string(catalog_url.as_str()),
+        title = toml_string(&selectors.title_selector),
+        content = toml_string(&selectors.content_selector),
+        link = toml_string(&selectors.chapter_link_selector),
+        file = toml_string(&output_file),
+        format = format,
+    );
+    std::fs::write(path, content).map_err(|e| format!("无法写入 {}: {}", path.display(), e))?;
+    info!("配置已写入 {}，共 {} 章，运行 rust_crawler -c {} 开始抓取", path.display(), chapter_urls.len(), path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_selector_picks_container_with_most_links() {
+        let html = Html::parse_document(
+            "<div class=\"nav\"><a href=\"/\">首页</a><a href=\"/top\">排行</a><a href=\"/new\">最新</a></div>\
+             <ul id=\"list\"><li><a href=\"#top\">顶部</a></li><li><a href=\"/1.html\">第1章</a></li>\
+             <li><a href=\"/2.html\">第2章</a></li><li><a href=\"/3.html\">第3章</a></li><li><a href=\"/4.html\">第4章</a></li></ul>",
+        );
+        assert_eq!(suggest_link_selector(&html).as_deref(), Some("#list a"));
+
+        // 数量相同时取先出现的容器，链接太少时不推测
+        let tie = Html::parse_document(
+            "<div class=\"a\"><a href=\"/1\">1</a><a href=\"/2\">2</a><a href=\"/3\">3</a></div>\
+             <div class=\"b\"><a href=\"/4\">4</a><a href=\"/5\">5</a><a href=\"/6\">6</a></div>",
+        );
+        assert_eq!(suggest_link_selector(&tie).as_deref(), Some("div.a a"));
+        let few = Html::parse_document("<div class=\"a\"><a href=\"/1\">1</a><a href=\"/2\">2</a><a href=\"javascript:void(0)\">3</a></div>");
+        assert_eq!(suggest_link_selector(&few), None);
+    }
+
+    #[test]
+    fn title_selector_prefers_h1_then_named_elements() {
+        assert_eq!(suggest_title_selector(&Html::parse_document("<h1 class=\"j_chapterName\">第1章</h1>")), "h1.j_chapterName");
+        assert_eq!(suggest_title_selector(&Html::parse_document("<h1>第1章</h1>")), "h1");
+        assert_eq!(suggest_title_selector(&Html::parse_document("<div class=\"box\">x</div><div class=\"bookname\">第1章</div>")), "div.bookname");
+        assert_eq!(suggest_title_selector(&Html::parse_document("<title>第1章</title><div class=\"box\">x</div>")), "title");
+    }
+
+    #[test]
+    fn content_selector_picks_element_with_most_direct_text() {
+        let paragraphs = Html::parse_document(
+            "<h1 id=\"title\">第1章 很长很长很长很长很长很长很长很长的标题</h1>\
+             <div class=\"footer\">版权所有</div>\
+             <div id=\"content\"><p>第一段正文。</p><p>第二段正文。</p><p>第三段正文。</p></div>",
+        );
+        assert_eq!(suggest_content_selector(&paragraphs, "#title").as_deref(), Some("#content p"));
+
+        let text = Html::parse_document("<div class=\"read\">第一行正文<br/>第二行正文<br/>第三行</div><div class=\"ad\">广告</div>");
+        assert_eq!(suggest_content_selector(&text, "h1").as_deref(), Some("div.read"));
+        assert_eq!(suggest_content_selector(&Html::parse_document("<div class=\"empty\"></div>"), "h1"), None);
+    }
+}
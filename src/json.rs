@@ -0,0 +1,163 @@
+//! JSON / NDJSON 输出：每章一条记录，下游工具可以直接读取抓取结果
+//!
+//! json 写成一个数组，ndjson 每行一条记录；失败和付费章节同样输出一条，status 与 error 说明原因。
+
+use crate::pipeline::{Sink, StageError};
+use crate::{ChapterResult, FsyncPolicy, format_time_rfc3339, result_status};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use tracing::debug;
+
+#[derive(Serialize)]
+struct ChapterRecord<'a> {
+    /// 章节序号，从1开始
+    index: usize,
+    title: &'a str,
+    url: &'a str,
+    /// success / partial / paywalled / not_found / failed
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    volume: Option<&'a str>,
+    paragraphs: &'a [String],
+    chars: usize,
+    http_status: Option<u16>,
+    error: Option<&'a str>,
+    duration_ms: u64,
+    wait_ms: u64,
+    started_at: String,
+    completed_at: String,
+    /// 从断点文件恢复，本次运行没有实际请求
+    resumed: bool,
+}
+
+impl<'a> ChapterRecord<'a> {
+    fn new(result: &'a ChapterResult) -> Self {
+        ChapterRecord {
+            index: result.index + 1,
+            title: &result.title,
+            url: &result.url,
+            // 与报告和失败章节列表中的状态一致
+            status: result_status(result),
+            volume: result.volume.as_deref(),
+            paragraphs: if result.success { &result.content } else { &[] },
+            chars: if result.success { result.chars } else { 0 },
+            http_status: result.http_status,
+            error: result.error_msg.as_deref(),
+            duration_ms: result.duration_ms,
+            wait_ms: result.wait_ms,
+            started_at: format_time_rfc3339(result.started_at),
+            completed_at: format_time_rfc3339(result.completed_at),
+            resumed: result.resumed,
+        }
+    }
+}
+
+pub(crate) struct JsonSink {
+    output: BufWriter<File>,
+    /// true 时每行一条记录（NDJSON），否则写成数组
+    lines: bool,
+    written: usize,
+    fsync: FsyncPolicy,
+}
+
+impl JsonSink {
+    pub(crate) fn create(output_file: File, lines: bool, fsync: FsyncPolicy) -> std::io::Result<Self> {
+        let mut output = BufWriter::new(output_file);
+        if !lines {
+            output.write_all(b"[")?;
+        }
+        Ok(JsonSink { output, lines, written: 0, fsync })
+    }
+
+    fn write_record(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        if !self.lines {
+            self.output.write_all(if self.written == 0 { b"\n" } else { b",\n" })?;
+        }
+        serde_json::to_writer(&mut self.output, &ChapterRecord::new(result))?;
+        if self.lines {
+            self.output.write_all(b"\n")?;
+        }
+        self.written += 1;
+        if self.fsync == FsyncPolicy::PerChapter {
+            self.output.flush()?;
+            self.output.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for JsonSink {
+    fn write(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        debug!("第{}章: {}", result.index + 1, result.title);
+        self.write_record(result)
+    }
+
+    fn write_placeholder(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        self.write_record(result)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), StageError> {
+        if !self.lines {
+            self.output.write_all(b"\n]\n")?;
+        }
+        self.output.flush()?;
+        if self.fsync != FsyncPolicy::None {
+            self.output.get_ref().sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_all(lines: bool, results: &[ChapterResult]) -> String {
+        let path = std::env::temp_dir().join(format!("rust_crawler_json_{}_{}_{}", lines, results.len(), std::process::id()));
+        let mut sink = Box::new(JsonSink::create(File::create(&path).unwrap(), lines, FsyncPolicy::None).unwrap());
+        for result in results {
+            if result.success {
+                sink.write(result).unwrap();
+            } else {
+                sink.write_placeholder(result).unwrap();
+            }
+        }
+        sink.finish().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        text
+    }
+
+    fn results() -> Vec<ChapterResult> {
+        let now = chrono::Utc::now();
+        let mut paywalled = ChapterResult::success(1, "第二章".to_string(), "https://example.com/2.html".to_string(), vec!["试读".to_string()], 0, now);
+        paywalled.paywalled = true;
+        vec![
+            ChapterResult::success(0, "第一章".to_string(), "https://example.com/1.html".to_string(), vec!["正文".to_string()], 0, now),
+            paywalled,
+            ChapterResult::failure(2, "https://example.com/3.html".to_string(), "HTTP 500".to_string(), 0, now),
+        ]
+    }
+
+    #[test]
+    fn array_framing() {
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&write_all(false, &[])).unwrap(), serde_json::json!([]));
+        let records: Vec<serde_json::Value> = serde_json::from_str(&write_all(false, &results())).unwrap();
+        let summary: Vec<(u64, &str)> = records.iter().map(|r| (r["index"].as_u64().unwrap(), r["status"].as_str().unwrap())).collect();
+        assert_eq!(summary, [(1, "success"), (2, "paywalled"), (3, "failed")]);
+        assert_eq!(records[0]["paragraphs"], serde_json::json!(["正文"]));
+        assert_eq!(records[2]["error"], "HTTP 500");
+    }
+
+    #[test]
+    fn ndjson_writes_one_record_per_line() {
+        assert_eq!(write_all(true, &[]), "");
+        let text = write_all(true, &results());
+        let statuses: Vec<String> = text.lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["status"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(statuses, ["success", "paywalled", "failed"]);
+        assert!(text.ends_with("}\n"));
+    }
+}
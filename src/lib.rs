@@ -0,0 +1,4871 @@
+//! 小说章节爬虫
+//!
+//! 命令行程序的抓取和写出流程都在这里，配置的结构与加载见 `config` 模块；`main.rs` 只负责解析参数后调用 [`run`]。
+//! 嵌入到其他程序时用 [`CrawlerBuilder`] 配置目录页、选择器、并发数和 HTTP 客户端，
+//! 再通过 [`Crawler::run`] 取得按章节顺序排列的 [`ChapterResult`]。
+//! 需要在运行中暂停、取消或显示进度时改用 [`Crawler::start`]，通过返回的 [`CrawlHandle`] 控制。
+
+// 日志一律经 tracing 输出，调试时临时加的打印语句留在代码里会被 clippy 拦下
+#![warn(clippy::print_stdout, clippy::print_stderr, clippy::dbg_macro)]
+
+mod annotations;
+mod auth;
+mod cache;
+mod checkpoint;
+mod config;
+mod digest;
+mod epub;
+mod fixture;
+mod hosts;
+mod html;
+mod init;
+mod json;
+mod library;
+mod logging;
+mod pipeline;
+mod prefilter;
+mod progress;
+mod robots;
+mod sinks;
+mod spill;
+mod text_index;
+mod units;
+mod xpath;
+
+use annotations::Annotations;
+use cache::{CachedPage, ResponseCache};
+use checkpoint::Checkpoint;
+use config::*;
+pub use config::{Config, Overrides, load_config};
+use digest::DigestSink;
+use epub::{EpubMetadata, EpubSink};
+use hosts::HostOverrides;
+use html::HtmlSink;
+use json::JsonSink;
+use text_index::ChapterIndex;
+use units::UnitFormat;
+use pipeline::{ChapterJob, ConcurrentStage, Extract, Flow, Pipeline, Sink, Transform};
+use prefilter::{HtmlPrefilter, PrefilterStats};
+use progress::ProgressDisplay;
+use robots::RobotsPolicy;
+use sinks::{FileOutput, MarkdownSink, TextSink};
+use spill::{BlobStore, SpillStore};
+use rand::Rng;
+use regex::Regex;
+use rand::seq::SliceRandom;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, OnceLock};
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tokio::time::{timeout, Duration};
+use tokio_util::sync::CancellationToken;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+
+/// 日志时间的显示方式，加载配置后设置一次；设置前使用本机时区和默认格式
+static LOG_CLOCK: OnceLock<(LogTimezone, String)> = OnceLock::new();
+
+fn init_log_clock(config: &LogConfig) {
+    // 格式串非法时 chrono 会在格式化时 panic，这里提前检查并回退到默认格式
+    let valid = chrono::format::StrftimeItems::new(&config.time_format)
+        .all(|item| !matches!(item, chrono::format::Item::Error));
+    let format = if valid {
+        config.time_format.clone()
+    } else {
+        warn!("日志时间格式无效，使用默认格式: {}", config.time_format);
+        DEFAULT_TIME_FORMAT.to_string()
+    };
+    let _ = LOG_CLOCK.set((config.timezone, format));
+    logging::configure(config);
+}
+
+/// 按配置的时区和格式显示时间；内部一律以 UTC 记录
+fn format_time(time: chrono::DateTime<chrono::Utc>) -> String {
+    match LOG_CLOCK.get() {
+        Some((LogTimezone::Utc, format)) => time.format(format).to_string(),
+        Some((LogTimezone::Local, format)) => time.with_timezone(&chrono::Local).format(format).to_string(),
+        None => time.with_timezone(&chrono::Local).format(DEFAULT_TIME_FORMAT).to_string(),
+    }
+}
+
+/// 报告等机器可读输出中的时间，使用与日志相同的时区，RFC 3339 格式
+fn format_time_rfc3339(time: chrono::DateTime<chrono::Utc>) -> String {
+    match LOG_CLOCK.get() {
+        Some((LogTimezone::Utc, _)) => time.to_rfc3339(),
+        _ => time.with_timezone(&chrono::Local).to_rfc3339(),
+    }
+}
+
+fn get_timestamp() -> String {
+    format_time(chrono::Utc::now())
+}
+
+/// Windows 控制台默认使用 GBK 代码页，切换到 UTF-8 以正确显示中文日志
+#[cfg(windows)]
+pub fn setup_console() {
+    unsafe {
+        windows_sys::Win32::System::Console::SetConsoleOutputCP(65001);
+    }
+}
+
+#[cfg(not(windows))]
+pub fn setup_console() {}
+
+/// 把输出路径转换为可直接打开的形式：Windows 上超过 MAX_PATH 的路径加上 \\?\ 前缀
+#[cfg(windows)]
+fn output_path(path: &str) -> std::path::PathBuf {
+    const MAX_PATH: usize = 260;
+    match std::path::absolute(path) {
+        Ok(abs) if abs.as_os_str().len() >= MAX_PATH && !abs.as_os_str().to_string_lossy().starts_with(r"\\?\") => {
+            let abs = abs.to_string_lossy();
+            match abs.strip_prefix(r"\\") {
+                Some(unc) => std::path::PathBuf::from(format!(r"\\?\UNC\{}", unc)),
+                None => std::path::PathBuf::from(format!(r"\\?\{}", abs)),
+            }
+        }
+        _ => std::path::PathBuf::from(path),
+    }
+}
+
+#[cfg(not(windows))]
+fn output_path(path: &str) -> std::path::PathBuf {
+    std::path::PathBuf::from(path)
+}
+
+/// 一个章节的抓取结果
+pub struct ChapterResult {
+    pub index: usize,
+    pub title: String,
+    pub url: String,
+    pub content: Vec<String>,
+    pub success: bool,
+    pub paywalled: bool,
+    /// 软 404：站点返回 200，但页面表明章节不存在
+    pub not_found: bool,
+    pub partial: bool,
+    /// 网络错误或服务端临时故障，重试可能成功
+    pub transient: bool,
+    /// 从断点文件恢复，本次运行没有实际请求
+    pub resumed: bool,
+    /// 最后一次章节请求的 HTTP 状态码，请求没有发出（网络错误）时为空
+    pub http_status: Option<u16>,
+    /// 429 响应给出的 Retry-After，重试前按它等待而不是按退避时间
+    pub retry_after: Option<Duration>,
+    /// 所属分卷，配置了 selectors.volume_selector 且目录页中有分卷标题时才有
+    pub volume: Option<String>,
+    /// 目录页中该章链接的文字，已按 clean.catalog_title_patterns 去掉"最新"前缀、日期、阅读数等，EPUB 和 HTML 的目录优先使用
+    pub catalog_title: Option<String>,
+    /// 正文字数；清洗后会重新统计，正文转存到磁盘后仍可用于汇总
+    pub chars: usize,
+    /// 正文已转存到磁盘（output.spill），content 为空，写出时再读回
+    pub spilled: bool,
+    pub error_msg: Option<String>,
+    pub duration_ms: u64,
+    pub wait_ms: u64,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub completed_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl ChapterResult {
+    /// 目录中显示的章节名：目录页链接文字，没有时为章节页的标题
+    pub(crate) fn toc_title(&self) -> &str {
+        self.catalog_title.as_deref().unwrap_or(&self.title)
+    }
+
+    fn success(index: usize, title: String, url: String, content: Vec<String>, duration_ms: u64, completed_at: chrono::DateTime<chrono::Utc>) -> Self {
+        let chars = content_chars(&content);
+        ChapterResult {
+            index,
+            title,
+            url,
+            content,
+            success: true,
+            paywalled: false,
+            not_found: false,
+            partial: false,
+            transient: false,
+            resumed: false,
+            http_status: None,
+            retry_after: None,
+            volume: None,
+            catalog_title: None,
+            chars,
+            spilled: false,
+            error_msg: None,
+            duration_ms,
+            wait_ms: 0,
+            started_at: completed_at,
+            completed_at,
+        }
+    }
+
+    fn failure(index: usize, url: String, error_msg: String, duration_ms: u64, completed_at: chrono::DateTime<chrono::Utc>) -> Self {
+        ChapterResult {
+            index,
+            title: String::new(),
+            url,
+            content: Vec::new(),
+            success: false,
+            paywalled: false,
+            not_found: false,
+            partial: false,
+            transient: false,
+            resumed: false,
+            http_status: None,
+            retry_after: None,
+            volume: None,
+            catalog_title: None,
+            chars: 0,
+            spilled: false,
+            error_msg: Some(error_msg),
+            duration_ms,
+            wait_ms: 0,
+            started_at: completed_at,
+            completed_at,
+        }
+    }
+
+    fn transient_failure(index: usize, url: String, error_msg: String, duration_ms: u64, completed_at: chrono::DateTime<chrono::Utc>) -> Self {
+        let mut result = Self::failure(index, url, error_msg, duration_ms, completed_at);
+        result.transient = true;
+        result
+    }
+
+    fn not_found(index: usize, url: String, evidence: &str, duration_ms: u64, completed_at: chrono::DateTime<chrono::Utc>) -> Self {
+        let mut result = ChapterResult::failure(index, url, format!("{}: {}", SOFT_404, evidence), duration_ms, completed_at);
+        result.not_found = true;
+        result
+    }
+
+    fn paywalled(index: usize, url: String, marker: &str, duration_ms: u64, completed_at: chrono::DateTime<chrono::Utc>) -> Self {
+        ChapterResult {
+            index,
+            title: String::new(),
+            url,
+            content: Vec::new(),
+            success: false,
+            paywalled: true,
+            not_found: false,
+            partial: false,
+            transient: false,
+            resumed: false,
+            http_status: None,
+            retry_after: None,
+            volume: None,
+            catalog_title: None,
+            chars: 0,
+            spilled: false,
+            error_msg: Some(format!("Paywall marker found: {}", marker)),
+            duration_ms,
+            wait_ms: 0,
+            started_at: completed_at,
+            completed_at,
+        }
+    }
+
+    fn log_entry(&self) -> ChapterLog {
+        let idx = self.index + 1;
+        let message = if self.success && self.partial {
+            format!("[{}] 爬取成功但内容过短: {} ({}段, {}ms)", idx, self.title, self.content.len(), self.duration_ms)
+        } else if self.success {
+            format!("[{}] 爬取成功: {} ({}ms)", idx, self.title, self.duration_ms)
+        } else if self.paywalled {
+            format!("[{}] 付费章节，已跳过: {}", idx, self.url)
+        } else {
+            format!("[{}] 爬取失败: {} ({})", idx, self.url, self.error_msg.as_ref().unwrap_or(&String::new()))
+        };
+        ChapterLog {
+            index: idx,
+            url: self.url.clone(),
+            status: result_status(self),
+            duration_ms: self.duration_ms,
+            completed_at: format_time_rfc3339(self.completed_at),
+            message,
+        }
+    }
+
+    fn log(&self) {
+        self.log_entry().emit();
+    }
+}
+
+/// 一章的完成日志；按章节顺序输出时先暂存，输出时仍显示该章实际完成的时间
+struct ChapterLog {
+    /// 从1开始
+    index: usize,
+    url: String,
+    status: &'static str,
+    duration_ms: u64,
+    completed_at: String,
+    message: String,
+}
+
+impl ChapterLog {
+    fn emit(&self) {
+        let ChapterLog { index, url, status, duration_ms, completed_at, message } = self;
+        if *status == "failed" {
+            warn!(chapter = index, url = %url, status, duration_ms, completed_at = %completed_at, "{}", message);
+        } else {
+            info!(chapter = index, url = %url, status, duration_ms, completed_at = %completed_at, "{}", message);
+        }
+    }
+}
+
+static CHROME_USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/118.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/117.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Edge/120.0.0.0",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Edge/119.0.0.0",
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+];
+
+static FIREFOX_USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:121.0) Gecko/20100101 Firefox/121.0",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:120.0) Gecko/20100101 Firefox/120.0",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:121.0) Gecko/20100101 Firefox/121.0",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0",
+];
+
+static SAFARI_USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Safari/605.1.15",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Safari/605.1.15",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Mobile/15E148 Safari/604.1",
+];
+
+/// 单个主机的请求与连接统计，用于确认连接池是否生效
+#[derive(Debug, Default, Clone, Serialize)]
+struct HostNetStats {
+    requests: usize,
+    connections: usize,
+    dns_lookups: usize,
+    tls_handshakes: usize,
+    /// 下载的页面正文字节数（解压前）
+    bytes: u64,
+    #[serde(skip)]
+    https: bool,
+}
+
+impl HostNetStats {
+    /// 复用已有连接的请求占比
+    fn reuse_rate(&self) -> f64 {
+        if self.requests == 0 { 0.0 } else { 1.0 - (self.connections as f64 / self.requests as f64).min(1.0) }
+    }
+}
+
+/// 所有身份的客户端共用一份统计，按主机名汇总
+static NET_STATS: std::sync::Mutex<BTreeMap<String, HostNetStats>> = std::sync::Mutex::new(BTreeMap::new());
+
+fn record_request(url: &str) {
+    let Ok(url) = reqwest::Url::parse(url) else { return };
+    let Some(host) = url.host_str() else { return };
+    let mut stats = NET_STATS.lock().unwrap();
+    let entry = stats.entry(host.to_string()).or_default();
+    entry.requests += 1;
+    entry.https |= url.scheme() == "https";
+}
+
+fn record_download(url: &str, bytes: u64) {
+    let Ok(url) = reqwest::Url::parse(url) else { return };
+    let Some(host) = url.host_str() else { return };
+    NET_STATS.lock().unwrap().entry(host.to_string()).or_default().bytes += bytes;
+}
+
+/// 统计新建连接的 DNS 解析器：连接池每新建一个连接解析一次，复用已有连接时不会调用。
+/// http.resolve 中的主机直接返回配置的地址，计入连接但不计入 DNS 解析；
+/// 以 IP 地址访问的主机不经过解析器，不统计连接数
+struct CountingResolver {
+    overrides: BTreeMap<String, SocketAddr>,
+}
+
+impl reqwest::dns::Resolve for CountingResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        let overridden = self.overrides.get(&host).copied();
+        {
+            let mut stats = NET_STATS.lock().unwrap();
+            let entry = stats.entry(host.clone()).or_default();
+            entry.connections += 1;
+            if overridden.is_none() {
+                entry.dns_lookups += 1;
+            }
+            if entry.https {
+                entry.tls_handshakes += 1;
+            }
+        }
+        Box::pin(async move {
+            let addrs: reqwest::dns::Addrs = match overridden {
+                Some(addr) => Box::new(std::iter::once(addr)),
+                None => Box::new(tokio::net::lookup_host((host.as_str(), 0)).await?.collect::<Vec<_>>().into_iter()),
+            };
+            Ok(addrs)
+        })
+    }
+}
+
+/// 按 [http] 配置创建 reqwest 客户端构造器，所有身份共用同一套网络设置
+fn client_builder(http: &HttpConfig) -> reqwest::ClientBuilder {
+    // 端口为 0 时 reqwest 使用 URL 协议的默认端口
+    let mut builder = reqwest::Client::builder()
+        .dns_resolver(Arc::new(CountingResolver { overrides: http.resolve.clone() }));
+    if let Some(limit) = http.request_timeout() {
+        builder = builder.timeout(limit);
+    }
+    if http.connect_timeout_secs > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(http.connect_timeout_secs));
+    }
+    // 绑定到指定协议族的本地地址后，连接时只会尝试该协议族的目标地址
+    match http.ip_version {
+        IpVersion::Auto => builder,
+        IpVersion::V4 => builder.local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        IpVersion::V6 => builder.local_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+    }
+}
+
+/// 域名匹配：与配置的域名相同或是其子域名
+fn host_matches(host: &str, domain: &str) -> bool {
+    host == domain || host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// 按域名预置的 Cookie，让年龄确认之类的拦截页直接放行
+struct PresetCookies(BTreeMap<String, String>);
+
+impl PresetCookies {
+    /// 拆成 (域名, "名称=值") 列表，域名匹配其本身及子域名
+    fn pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().flat_map(|(domain, cookies)| {
+            cookies.split(';').map(str::trim).filter(|pair| !pair.is_empty()).map(move |pair| (domain.as_str(), pair))
+        })
+    }
+
+    /// 创建已写入预置 Cookie 的容器；之后站点下发的同名 Cookie 会覆盖预置值，和浏览器里点过确认一样
+    fn seeded_jar(&self) -> reqwest::cookie::Jar {
+        let jar = reqwest::cookie::Jar::default();
+        for (domain, pair) in self.pairs() {
+            if let Ok(url) = reqwest::Url::parse(&format!("http://{}/", domain)) {
+                jar.add_cookie_str(&format!("{}; Domain={}; Path=/", pair, domain), &url);
+            }
+        }
+        jar
+    }
+}
+
+/// per_request 模式的共享客户端使用：只发送预置 Cookie，不保存站点下发的 Cookie
+impl reqwest::cookie::CookieStore for PresetCookies {
+    fn set_cookies(&self, _cookie_headers: &mut dyn Iterator<Item = &reqwest::header::HeaderValue>, _url: &reqwest::Url) {}
+
+    fn cookies(&self, url: &reqwest::Url) -> Option<reqwest::header::HeaderValue> {
+        let host = url.host_str()?;
+        let matched: Vec<&str> = self.pairs()
+            .filter(|(domain, _)| host_matches(host, domain))
+            .map(|(_, pair)| pair)
+            .collect();
+        if matched.is_empty() {
+            return None;
+        }
+        reqwest::header::HeaderValue::from_str(&matched.join("; ")).ok()
+    }
+}
+
+/// 签名模板可用的变量，同一请求内所有参数共用同一时间戳
+struct SignVars<'a> {
+    url: &'a str,
+    host: &'a str,
+    path: &'a str,
+    query: &'a str,
+    ts: i64,
+    ts_ms: i64,
+}
+
+/// 按 [signing] 模板为请求地址追加动态参数。模板中 {…} 为占位符：
+/// {url} {host} {path} {query} {ts} {ts_ms} 取请求信息，{secret:名称} 取 signing.secrets，
+/// {env:名称} 取环境变量，{md5:…} {upper:…} {lower:…} 对内部模板的结果做变换，可以嵌套
+struct RequestSigner {
+    params: Vec<(String, String)>,
+    secrets: BTreeMap<String, String>,
+}
+
+impl RequestSigner {
+    /// 未配置签名参数时返回 None；模板有误时在启动阶段就报错
+    fn new(config: &SigningConfig) -> Result<Option<Self>, String> {
+        if config.params.is_empty() {
+            return Ok(None);
+        }
+        let signer = RequestSigner {
+            params: config.params.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            secrets: config.secrets.clone(),
+        };
+        let probe = SignVars { url: "", host: "", path: "", query: "", ts: 0, ts_ms: 0 };
+        for (name, template) in &signer.params {
+            signer.render(template, &probe).map_err(|e| format!("签名参数 {} 模板错误: {}", name, e))?;
+        }
+        Ok(Some(signer))
+    }
+
+    fn render(&self, template: &str, vars: &SignVars) -> Result<String, String> {
+        let mut out = String::new();
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            out.push_str(&rest[..open]);
+            let mut depth = 0;
+            let close = rest[open..].char_indices()
+                .find(|&(_, c)| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .map(|(i, _)| open + i)
+                .ok_or_else(|| format!("缺少右括号: {}", template))?;
+            out.push_str(&self.eval(&rest[open + 1..close], vars)?);
+            rest = &rest[close + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    fn eval(&self, expr: &str, vars: &SignVars) -> Result<String, String> {
+        if let Some((func, arg)) = expr.split_once(':') {
+            return match func {
+                "md5" => Ok(format!("{:x}", md5::compute(self.render(arg, vars)?))),
+                "upper" => Ok(self.render(arg, vars)?.to_uppercase()),
+                "lower" => Ok(self.render(arg, vars)?.to_lowercase()),
+                "secret" => self.secrets.get(arg).cloned().ok_or_else(|| format!("未定义的密钥: {}", arg)),
+                "env" => std::env::var(arg).map_err(|_| format!("环境变量未设置: {}", arg)),
+                _ => Err(format!("未知函数: {}", func)),
+            };
+        }
+        match expr {
+            "url" => Ok(vars.url.to_string()),
+            "host" => Ok(vars.host.to_string()),
+            "path" => Ok(vars.path.to_string()),
+            "query" => Ok(vars.query.to_string()),
+            "ts" => Ok(vars.ts.to_string()),
+            "ts_ms" => Ok(vars.ts_ms.to_string()),
+            _ => Err(format!("未知占位符: {}", expr)),
+        }
+    }
+
+    /// 返回追加了签名参数的地址，地址无法解析时原样返回
+    fn sign(&self, url: &str) -> String {
+        let Ok(mut parsed) = reqwest::Url::parse(url) else {
+            return url.to_string();
+        };
+        let now = chrono::Utc::now();
+        let vars = SignVars {
+            url,
+            host: parsed.host_str().unwrap_or(""),
+            path: parsed.path(),
+            query: parsed.query().unwrap_or(""),
+            ts: now.timestamp(),
+            ts_ms: now.timestamp_millis(),
+        };
+        let mut signed = Vec::with_capacity(self.params.len());
+        for (name, template) in &self.params {
+            match self.render(template, &vars) {
+                Ok(value) => signed.push((name.as_str(), value)),
+                Err(e) => warn!("签名参数 {} 计算失败: {}", name, e),
+            }
+        }
+        let kept: Vec<(String, String)> = parsed.query_pairs()
+            .filter(|(k, _)| !self.params.iter().any(|(name, _)| name == k))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        parsed.query_pairs_mut()
+            .clear()
+            .extend_pairs(kept)
+            .extend_pairs(signed);
+        parsed.to_string()
+    }
+}
+
+/// 一组保持一致的请求身份：UA、Accept、Accept-Language 与各自独立的 Cookie 容器
+#[derive(Clone)]
+struct Identity {
+    client: reqwest::Client,
+    user_agent: &'static str,
+    accept: &'static str,
+    accept_language: &'static str,
+    signer: Option<Arc<RequestSigner>>,
+    url_rewrites: Arc<BTreeMap<String, String>>,
+    limiter: Option<Arc<RateLimiter>>,
+    /// 使用的代理在代理池中的位置
+    proxy: Option<(Arc<ProxyPool>, usize)>,
+    /// pool 模式下本身份在身份池中的位置
+    pool_slot: Option<(Arc<IdentityPool>, usize)>,
+    hosts: Arc<HostOverrides>,
+    robots: Option<Arc<RobotsPolicy>>,
+}
+
+impl Identity {
+    fn random(client: reqwest::Client, browser: BrowserPreset, region: HeaderRegion) -> Self {
+        let mut rng = rand::thread_rng();
+        let user_agents = browser.user_agents();
+        let accept_languages = region.accept_languages();
+        Identity {
+            client,
+            user_agent: user_agents.choose(&mut rng).unwrap_or(&user_agents[0]),
+            accept: browser.accept_html(),
+            accept_language: accept_languages.choose(&mut rng).unwrap_or(&accept_languages[0]),
+            signer: None,
+            url_rewrites: Arc::default(),
+            limiter: None,
+            proxy: None,
+            pool_slot: None,
+            hosts: Arc::default(),
+            robots: None,
+        }
+    }
+
+    /// 带独立 Cookie 容器的身份，配置了 [auth] 时改用共享的登录会话；
+    /// 配置了代理池时从池中挑一个代理，整个身份期间固定使用
+    fn with_cookie_jar(http: &HttpConfig, browser: BrowserPreset, region: HeaderRegion, proxies: Option<&Arc<ProxyPool>>, session: Option<&Arc<reqwest::cookie::Jar>>) -> reqwest::Result<Self> {
+        let proxy = proxies.and_then(|pool| pool.pick().map(|index| (pool.clone(), index)));
+        let jar = session.cloned().unwrap_or_else(|| Arc::new(PresetCookies(http.cookies.clone()).seeded_jar()));
+        let mut builder = client_builder(http).cookie_provider(jar);
+        if let Some((pool, index)) = &proxy {
+            builder = builder.proxy(pool.entries[*index].proxy.clone());
+        }
+        let mut identity = Self::random(builder.build()?, browser, region);
+        identity.proxy = proxy;
+        Ok(identity)
+    }
+
+    /// 记录一次请求是否经代理成功送达，供代理池剔除失效代理
+    fn report_proxy(&self, ok: bool) {
+        if let Some((pool, index)) = &self.proxy {
+            pool.report(*index, ok);
+        }
+    }
+
+    /// 本身份被站点限流，pool 模式下冷却期间不再分配给后续章节
+    fn report_throttled(&self, retry_after: Option<Duration>) {
+        if let Some((pool, index)) = &self.pool_slot {
+            pool.cool_down(*index, retry_after.unwrap_or(DEFAULT_POOL_COOLDOWN));
+        }
+    }
+
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.get_with_accept(url, self.accept)
+    }
+
+    /// 请求页面前调用，开启限速时等到轮到本请求为止；[hosts] 中单独配置了限速的域名按各自的节奏
+    async fn throttle(&self, url: &str) {
+        let limiter = self.hosts.limiter(url).unwrap_or(self.limiter.as_ref());
+        if let Some(limiter) = limiter {
+            limiter.wait().await;
+        }
+        if let Some(robots) = &self.robots {
+            robots.throttle(url).await;
+        }
+    }
+
+    /// 按最长匹配的前缀改写地址
+    fn rewrite_url<'a>(&self, url: &'a str) -> Cow<'a, str> {
+        self.url_rewrites.iter()
+            .filter(|(from, _)| url.starts_with(from.as_str()))
+            .max_by_key(|(from, _)| from.len())
+            .map_or(Cow::Borrowed(url), |(from, to)| Cow::Owned(format!("{}{}", to, &url[from.len()..])))
+    }
+
+    /// 提交表单，目前只用于登录
+    fn post_form(&self, url: &str, form: &[(String, String)]) -> reqwest::RequestBuilder {
+        let url = self.rewrite_url(url);
+        record_request(&url);
+        let request = self.client.post(url.as_ref())
+            .header("User-Agent", self.user_agent)
+            .header("Accept-Language", self.accept_language)
+            .header("Accept", self.accept)
+            .form(form);
+        match self.hosts.headers(&url) {
+            Some(headers) => request.headers(headers.clone()),
+            None => request,
+        }
+    }
+
+    fn get_with_accept(&self, url: &str, accept: &str) -> reqwest::RequestBuilder {
+        let host_headers = self.hosts.headers(url);
+        let url = self.rewrite_url(url);
+        let url = match &self.signer {
+            Some(signer) => Cow::Owned(signer.sign(&url)),
+            None => url,
+        };
+        record_request(&url);
+        let request = self.client.get(url.as_ref())
+            .header("User-Agent", self.user_agent)
+            .header("Accept-Language", self.accept_language)
+            .header("Accept", accept);
+        // headers() 替换同名请求头，[hosts] 中配置的 User-Agent 等会取代默认值
+        match host_headers {
+            Some(headers) => request.headers(headers.clone()),
+            None => request,
+        }
+    }
+}
+
+struct IdentityManager {
+    mode: IdentityMode,
+    rotate_every: usize,
+    browser: BrowserPreset,
+    region: HeaderRegion,
+    http: HttpConfig,
+    shared_client: reqwest::Client,
+    signer: Option<Arc<RequestSigner>>,
+    url_rewrites: Arc<BTreeMap<String, String>>,
+    limiter: Option<Arc<RateLimiter>>,
+    proxies: Option<Arc<ProxyPool>>,
+    hosts: Arc<HostOverrides>,
+    /// [auth] 的登录会话，所有身份共用
+    session: Option<Arc<reqwest::cookie::Jar>>,
+    /// 开启 respect_robots_txt 后在抓取开始时设置
+    robots: OnceLock<Arc<RobotsPolicy>>,
+    current: std::sync::Mutex<(Identity, usize)>,
+    pool: Option<Arc<IdentityPool>>,
+}
+
+impl IdentityManager {
+    fn new(
+        config: &IdentityConfig,
+        http: &HttpConfig,
+        signer: Option<RequestSigner>,
+        limiter: Option<RateLimiter>,
+        hosts: HostOverrides,
+        session: Option<Arc<reqwest::cookie::Jar>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let proxies = ProxyPool::new(http, session.as_ref())?.map(Arc::new);
+        // 站点按会话限速时，每个身份各自按 min_delay_ms / max_delay_ms 的节奏请求，总吞吐随身份数增加
+        let (limiter, pool) = if config.mode == IdentityMode::Pool {
+            let slots = (0..config.pool_size.max(1))
+                .map(|_| {
+                    let mut identity = Identity::with_cookie_jar(http, config.browser, config.region, proxies.as_ref(), session.as_ref())?;
+                    identity.limiter = limiter.as_ref().and_then(|l| RateLimiter::new(l.min_delay_ms, l.max_delay_ms)).map(Arc::new);
+                    Ok(identity)
+                })
+                .collect::<reqwest::Result<Vec<_>>>()?;
+            (None, Some(Arc::new(IdentityPool::new(slots, config.pool_stickiness))))
+        } else {
+            (limiter, None)
+        };
+        Ok(Self {
+            mode: config.mode,
+            rotate_every: config.rotate_every.max(1),
+            browser: config.browser,
+            region: config.region,
+            http: http.clone(),
+            shared_client: match &session {
+                Some(session) => client_builder(http).cookie_provider(session.clone()).build()?,
+                None if http.cookies.is_empty() => client_builder(http).build()?,
+                None => client_builder(http).cookie_provider(Arc::new(PresetCookies(http.cookies.clone()))).build()?,
+            },
+            signer: signer.map(Arc::new),
+            url_rewrites: Arc::new(http.url_rewrites.clone()),
+            limiter: limiter.map(Arc::new),
+            hosts: Arc::new(hosts),
+            robots: OnceLock::new(),
+            current: std::sync::Mutex::new((Identity::with_cookie_jar(http, config.browser, config.region, proxies.as_ref(), session.as_ref())?, 0)),
+            proxies,
+            session,
+            pool,
+        })
+    }
+
+    /// 改用外部提供的客户端：每个请求都使用它，不再自建带 Cookie 或代理的客户端
+    fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.shared_client = client;
+        if let Some(pool) = self.pool.take() {
+            // 身份池各自的限速合并回一个共享的限速
+            self.limiter = pool.slots[0].lock().unwrap().identity.limiter.clone();
+        }
+        self.mode = IdentityMode::PerRequest;
+        self.proxies = None;
+        self
+    }
+
+    /// 取得下一个请求应使用的身份
+    fn next(&self) -> Identity {
+        let mut identity = self.next_identity();
+        identity.signer = self.signer.clone();
+        identity.url_rewrites = self.url_rewrites.clone();
+        if self.pool.is_none() {
+            identity.limiter = self.limiter.clone();
+        }
+        identity.hosts = self.hosts.clone();
+        identity.robots = self.robots.get().cloned();
+        identity
+    }
+
+    /// 开始遵守 robots.txt，之后取得的身份都会按 Crawl-delay 限速
+    fn enable_robots(&self) -> Arc<RobotsPolicy> {
+        self.robots.get_or_init(Arc::default).clone()
+    }
+
+    fn next_identity(&self) -> Identity {
+        if let Some(pool) = &self.pool {
+            return pool.next(|| Identity::with_cookie_jar(&self.http, self.browser, self.region, self.proxies.as_ref(), self.session.as_ref()));
+        }
+        if self.mode == IdentityMode::PerRequest {
+            let proxy = self.proxies.as_ref().and_then(|pool| pool.pick().map(|index| (pool.clone(), index)));
+            let client = match &proxy {
+                Some((pool, index)) => pool.entries[*index].client.clone(),
+                None => self.shared_client.clone(),
+            };
+            let mut identity = Identity::random(client, self.browser, self.region);
+            identity.proxy = proxy;
+            return identity;
+        }
+        let mut current = self.current.lock().unwrap();
+        // 当前身份的代理被移出代理池后立即换一个身份，避免继续使用失效代理
+        let proxy_removed = current.0.proxy.as_ref().is_some_and(|(pool, index)| pool.is_removed(*index));
+        if (self.mode == IdentityMode::Rotate && current.1 >= self.rotate_every) || proxy_removed {
+            match Identity::with_cookie_jar(&self.http, self.browser, self.region, self.proxies.as_ref(), self.session.as_ref()) {
+                Ok(identity) => {
+                    debug!("更换请求身份: {}", identity.user_agent);
+                    *current = (identity, 0);
+                }
+                Err(e) => warn!("创建新身份失败，继续使用当前身份: {}", e),
+            }
+        }
+        current.1 += 1;
+        current.0.clone()
+    }
+}
+
+/// 身份被限流（429）且响应没有给出 Retry-After 时的冷却时间
+const DEFAULT_POOL_COOLDOWN: Duration = Duration::from_secs(60);
+
+struct PoolSlot {
+    identity: Identity,
+    /// 被限流后到此时刻前不再分配
+    cooldown_until: Instant,
+}
+
+/// pool 模式的身份池：每个身份有独立的 Cookie、代理、UA 和限速，章节之间整体轮换
+struct IdentityPool {
+    slots: Vec<std::sync::Mutex<PoolSlot>>,
+    stickiness: usize,
+    /// (当前身份, 已连续分配的章节数)
+    cursor: std::sync::Mutex<(usize, usize)>,
+}
+
+impl IdentityPool {
+    fn new(identities: Vec<Identity>, stickiness: usize) -> Self {
+        let now = Instant::now();
+        IdentityPool {
+            slots: identities.into_iter().map(|identity| std::sync::Mutex::new(PoolSlot { identity, cooldown_until: now })).collect(),
+            stickiness: stickiness.max(1),
+            cursor: std::sync::Mutex::new((0, 0)),
+        }
+    }
+
+    fn cooling(&self, index: usize, now: Instant) -> bool {
+        self.slots[index].lock().unwrap().cooldown_until > now
+    }
+
+    /// 当前身份用满 stickiness 章或正在冷却时轮到下一个未冷却的身份，全部冷却时取最早结束冷却的；
+    /// 身份的代理被移出代理池后用 rebuild 换一个新身份，限速沿用原来的
+    fn next(self: &Arc<Self>, rebuild: impl FnOnce() -> reqwest::Result<Identity>) -> Identity {
+        let now = Instant::now();
+        let mut cursor = self.cursor.lock().unwrap();
+        let count = self.slots.len();
+        if cursor.1 >= self.stickiness || self.cooling(cursor.0, now) {
+            let next = (1..=count)
+                .map(|step| (cursor.0 + step) % count)
+                .find(|&index| !self.cooling(index, now))
+                .unwrap_or_else(|| (0..count).min_by_key(|&index| self.slots[index].lock().unwrap().cooldown_until).unwrap_or(0));
+            *cursor = (next, 0);
+        }
+        cursor.1 += 1;
+        let index = cursor.0;
+        drop(cursor);
+        let mut slot = self.slots[index].lock().unwrap();
+        if slot.identity.proxy.as_ref().is_some_and(|(pool, proxy)| pool.is_removed(*proxy)) {
+            match rebuild() {
+                Ok(mut identity) => {
+                    identity.limiter = slot.identity.limiter.clone();
+                    info!("身份 #{} 的代理已失效，更换为新身份: {}", index + 1, identity.user_agent);
+                    slot.identity = identity;
+                }
+                Err(e) => warn!("创建新身份失败，继续使用身份 #{}: {}", index + 1, e),
+            }
+        }
+        let mut identity = slot.identity.clone();
+        identity.pool_slot = Some((self.clone(), index));
+        identity
+    }
+
+    fn cool_down(&self, index: usize, duration: Duration) {
+        let mut slot = self.slots[index].lock().unwrap();
+        let until = Instant::now() + duration;
+        if until > slot.cooldown_until {
+            slot.cooldown_until = until;
+            info!("身份 #{} 被限流，{}s 内不再使用", index + 1, duration.as_secs());
+        }
+    }
+}
+
+/// 代理池中的一个代理，连续失败 proxy_max_failures 次后移出
+struct ProxyEntry {
+    proxy: reqwest::Proxy,
+    /// 不保存 Cookie 的共享客户端（配置了 [auth] 时使用登录会话），供 per_request 模式使用
+    client: reqwest::Client,
+    failures: AtomicUsize,
+    removed: AtomicBool,
+}
+
+/// 按请求轮换的代理池；reqwest 的代理绑定在客户端上，因此每个代理各建一个客户端
+struct ProxyPool {
+    entries: Vec<ProxyEntry>,
+    rotation: ProxyRotation,
+    max_failures: usize,
+    cursor: AtomicUsize,
+    exhausted: AtomicBool,
+}
+
+impl ProxyPool {
+    fn new(http: &HttpConfig, session: Option<&Arc<reqwest::cookie::Jar>>) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if http.proxies.is_empty() {
+            return Ok(None);
+        }
+        let client = |proxy: &reqwest::Proxy| {
+            let builder = client_builder(http).proxy(proxy.clone());
+            match session {
+                Some(session) => builder.cookie_provider(session.clone()).build(),
+                None => builder.build(),
+            }
+        };
+        let entries = http.proxies.iter()
+            .map(|spec| {
+                let proxy = spec.to_proxy()?;
+                Ok(ProxyEntry {
+                    client: client(&proxy)?,
+                    proxy,
+                    failures: AtomicUsize::new(0),
+                    removed: AtomicBool::new(false),
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+        Ok(Some(ProxyPool {
+            entries,
+            rotation: http.proxy_rotation,
+            max_failures: http.proxy_max_failures.max(1),
+            cursor: AtomicUsize::new(0),
+            exhausted: AtomicBool::new(false),
+        }))
+    }
+
+    /// 挑选下一个可用代理；全部被移出后返回 None，改为直连
+    fn pick(&self) -> Option<usize> {
+        let alive: Vec<usize> = (0..self.entries.len()).filter(|&i| !self.is_removed(i)).collect();
+        if alive.is_empty() {
+            if !self.exhausted.swap(true, Ordering::Relaxed) {
+                warn!("代理池中的代理已全部失效，改为直连");
+            }
+            return None;
+        }
+        let slot = match self.rotation {
+            ProxyRotation::RoundRobin => self.cursor.fetch_add(1, Ordering::Relaxed) % alive.len(),
+            ProxyRotation::Random => rand::thread_rng().gen_range(0..alive.len()),
+        };
+        Some(alive[slot])
+    }
+
+    fn is_removed(&self, index: usize) -> bool {
+        self.entries[index].removed.load(Ordering::Relaxed)
+    }
+
+    /// 成功则清零连续失败次数，失败次数达到上限时移出代理池
+    fn report(&self, index: usize, ok: bool) {
+        let entry = &self.entries[index];
+        if ok {
+            entry.failures.store(0, Ordering::Relaxed);
+            return;
+        }
+        let failures = entry.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.max_failures && !entry.removed.swap(true, Ordering::Relaxed) {
+            info!("代理 #{} 连续失败 {} 次，已移出代理池", index + 1, failures);
+        }
+    }
+}
+
+/// 所有任务共享的请求限速：相邻两次页面请求之间至少间隔一个随机延迟，与并发数无关
+struct RateLimiter {
+    min_delay_ms: u64,
+    max_delay_ms: u64,
+    next_slot: std::sync::Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// 两个延迟都为 0 时不限速
+    fn new(min_delay_ms: u64, max_delay_ms: u64) -> Option<Self> {
+        let max_delay_ms = max_delay_ms.max(min_delay_ms);
+        (max_delay_ms > 0).then(|| RateLimiter {
+            min_delay_ms,
+            max_delay_ms,
+            next_slot: std::sync::Mutex::new(Instant::now()),
+        })
+    }
+
+    /// 预约下一个可用时刻并等待到该时刻
+    async fn wait(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            let delay_ms = rand::thread_rng().gen_range(self.min_delay_ms..=self.max_delay_ms);
+            *next_slot = slot + Duration::from_millis(delay_ms);
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+/// 当前生效的并发数；manual 表示用户已手动调整过，慢启动不再接管
+struct ConcurrencyState {
+    current: usize,
+    manual: bool,
+    /// 调小并发后还在后台等待收回的许可，再次调整时取消
+    shrink: Option<PendingShrink>,
+    /// 每次发起收回时加一，收回任务据此判断自己是否已被取消
+    generation: u64,
+}
+
+struct PendingShrink {
+    generation: u64,
+    permits: usize,
+    task: tokio::task::AbortHandle,
+}
+
+/// 抓取任务共用的并发许可，支持慢启动和运行中调整
+struct ConcurrencyControl {
+    semaphore: Arc<Semaphore>,
+    concurrency: Arc<std::sync::Mutex<ConcurrencyState>>,
+}
+
+impl ConcurrencyControl {
+    fn new(initial_permits: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(initial_permits)),
+            concurrency: Arc::new(std::sync::Mutex::new(ConcurrencyState { current: initial_permits, manual: false, shrink: None, generation: 0 })),
+        }
+    }
+
+    /// 慢启动：在 ramp_secs 内把并发从 initial 线性提升到 limit，避免开局瞬间打满连接
+    fn spawn_ramp_up(&self, initial: usize, limit: usize, ramp_secs: u64) {
+        if initial >= limit || ramp_secs == 0 {
+            return;
+        }
+        let semaphore = self.semaphore.clone();
+        let concurrency = self.concurrency.clone();
+        let steps = limit - initial;
+        let interval = Duration::from_millis(ramp_secs * 1000 / steps as u64);
+        tokio::spawn(async move {
+            for current in initial + 1..=limit {
+                tokio::time::sleep(interval).await;
+                let mut state = concurrency.lock().unwrap();
+                if state.manual {
+                    break;
+                }
+                semaphore.add_permits(1);
+                state.current = current;
+                debug!("并发提升至 {}/{}", current, limit);
+            }
+        });
+    }
+
+    /// 运行中调整并发数：增加时立即补发许可，减少时在后台收回空闲下来的许可。
+    /// 上一次减少还没收回完时先取消它，按实际发出的许可数重新计算，避免之后的增加被未完成的收回抵消
+    fn set_concurrency(semaphore: &Arc<Semaphore>, concurrency: &Arc<std::sync::Mutex<ConcurrencyState>>, target: usize) {
+        let mut state = concurrency.lock().unwrap();
+        state.manual = true;
+        let mut issued = state.current;
+        if let Some(shrink) = state.shrink.take() {
+            shrink.task.abort();
+            issued += shrink.permits;
+        }
+        if target > issued {
+            semaphore.add_permits(target - issued);
+        } else if target < issued {
+            let permits = issued - target;
+            state.generation += 1;
+            let generation = state.generation;
+            let (semaphore, concurrency) = (semaphore.clone(), concurrency.clone());
+            let task = tokio::spawn(async move {
+                let Ok(acquired) = semaphore.acquire_many_owned(permits as u32).await else { return };
+                let mut state = concurrency.lock().unwrap();
+                // 已被之后的调整取消时不收回，acquired 释放后许可回到信号量
+                if state.shrink.as_ref().is_some_and(|shrink| shrink.generation == generation) {
+                    acquired.forget();
+                    state.shrink = None;
+                }
+            });
+            state.shrink = Some(PendingShrink { generation, permits, task: task.abort_handle() });
+        }
+        info!("并发数调整: {} -> {}", state.current, target);
+        state.current = target;
+    }
+
+    /// 从标准输入读取新的并发数（输入数字后回车），无需重启即可在被限流时手动降速
+    fn spawn_stdin_control(&self) {
+        use tokio::io::AsyncBufReadExt;
+        let semaphore = self.semaphore.clone();
+        let concurrency = self.concurrency.clone();
+        tokio::spawn(async move {
+            let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                match line.trim().parse::<usize>() {
+                    Ok(target) if target > 0 => Self::set_concurrency(&semaphore, &concurrency, target),
+                    _ => info!("无法识别的输入: {}（输入正整数调整并发数）", line.trim()),
+                }
+            }
+        });
+    }
+}
+
+/// 在页面中查找付费/VIP标记，返回命中的第一个标记
+fn find_paywall_marker<'a>(html: &str, markers: &'a [String]) -> Option<&'a str> {
+    markers.iter()
+        .find(|marker| !marker.is_empty() && html.contains(marker.as_str()))
+        .map(|marker| marker.as_str())
+}
+
+/// 计算段落的相似度指纹：去掉 ASCII（网址、数字）、全角字母数字和空白，
+/// 只对中文等正文字符流式求哈希，使仅网址不同的插入广告得到相同指纹。
+/// 保留字符少于4个的段落太短，不参与比较，返回 None
+fn similarity_fingerprint(paragraph: &str) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    let kept = paragraph.chars()
+        .filter(|c| !c.is_ascii() && !c.is_whitespace() && !is_zero_width(*c) && !('\u{FF01}'..='\u{FF5E}').contains(c))
+        .inspect(|c| c.hash(&mut hasher))
+        .count();
+    if kept < 4 { None } else { Some(hasher.finish()) }
+}
+
+/// 章节正文：已转存的从磁盘读取，读取失败时记录警告并返回 None，由调用方跳过该章
+fn chapter_content<'a>(result: &'a ChapterResult, spill: Option<&SpillStore>) -> Option<Cow<'a, [String]>> {
+    match spill {
+        Some(spill) if result.spilled => match spill.read(result.index) {
+            Ok(content) => Some(Cow::Owned(content)),
+            Err(e) => {
+                warn!("读取第{}章转存的正文失败，跳过该章: {}", result.index + 1, e);
+                None
+            }
+        },
+        _ => Some(Cow::Borrowed(&result.content)),
+    }
+}
+
+/// 统计跨章节重复出现的段落，出现在至少 min_chapters 个章节中的视为插入广告，
+/// 返回 指纹 -> (出现章节数, 示例原文)
+/// 正文已转存的章节逐章从磁盘读取。开启 dedupe_title 时与标题重复的首段会在清洗时移除，不参与统计
+fn detect_injected_paragraphs(results: &[ChapterResult], spill: Option<&SpillStore>, min_chapters: usize, dedupe_title: bool) -> HashMap<u64, (usize, String)> {
+    let mut counts: HashMap<u64, (usize, String)> = HashMap::new();
+    for result in results.iter().filter(|r| r.success) {
+        let Some(content) = chapter_content(result, spill) else { continue };
+        let mut seen_in_chapter = HashSet::new();
+        let title_paragraph = content.iter()
+            .position(|para| !para.trim().is_empty())
+            .filter(|&first| dedupe_title && is_duplicate_title(&result.title, &content[first]));
+        for (i, para) in content.iter().enumerate() {
+            if Some(i) == title_paragraph {
+                continue;
+            }
+            let Some(key) = similarity_fingerprint(para) else { continue };
+            if !seen_in_chapter.insert(key) {
+                continue;
+            }
+            let entry = counts.entry(key).or_insert_with(|| (0, para.clone()));
+            entry.0 += 1;
+        }
+    }
+    counts.retain(|_, (count, _)| *count >= min_chapters.max(2));
+    counts
+}
+
+/// 与前面某一章重复的章节，序号从0开始
+struct DuplicateChapter {
+    index: usize,
+    original: usize,
+    /// 正文相同；为 false 时只是标题相同
+    same_content: bool,
+}
+
+/// 找出正文（或开启 by_title 时标题）与前面某一章相同的章节，如目录中地址不同、内容却重复的章节。
+/// 比较清洗前的正文，正文已转存的章节逐章从磁盘读取
+fn detect_duplicate_chapters(results: &[ChapterResult], spill: Option<&SpillStore>, by_title: bool) -> Vec<DuplicateChapter> {
+    let mut contents: HashMap<[u8; 16], usize> = HashMap::new();
+    let mut titles: HashMap<String, usize> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for result in results.iter().filter(|r| r.success) {
+        let Some(content) = chapter_content(result, spill) else { continue };
+        if !content.is_empty() {
+            let digest = md5::compute(content.join("\n")).0;
+            if let Some(&original) = contents.get(&digest) {
+                duplicates.push(DuplicateChapter { index: result.index, original, same_content: true });
+                continue;
+            }
+            contents.insert(digest, result.index);
+        }
+        if by_title {
+            let title: String = result.title.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect();
+            if title.is_empty() {
+                continue;
+            }
+            match titles.get(&title) {
+                Some(&original) => duplicates.push(DuplicateChapter { index: result.index, original, same_content: false }),
+                None => {
+                    titles.insert(title, result.index);
+                }
+            }
+        }
+    }
+    duplicates
+}
+
+fn is_zero_width(c: char) -> bool {
+    matches!(c, '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}')
+}
+
+fn named_entity(name: &str) -> Option<char> {
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{00A0}',
+        "ensp" => '\u{2002}',
+        "emsp" => '\u{2003}',
+        "thinsp" => '\u{2009}',
+        "zwsp" => '\u{200B}',
+        "zwnj" => '\u{200C}',
+        "zwj" => '\u{200D}',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "middot" => '·',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        _ => return None,
+    })
+}
+
+/// 解码一层 HTML 实体（命名实体与 &#123; / &#x7B; 数字引用），无法识别的原样保留
+fn decode_entities_once(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..].find(';')
+            .filter(|&end| end > 0 && end <= 10)
+            .and_then(|end| {
+                let name = &rest[1..end + 1];
+                let c = match name.strip_prefix('#') {
+                    Some(num) => match num.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                        None => num.parse().ok().and_then(char::from_u32),
+                    },
+                    None => named_entity(name),
+                }?;
+                Some((c, end + 2))
+            });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 清理提取文本中残留的实体：站点双重编码时 scraper 只解一层，这里反复解码直到稳定，
+/// 再把各类不换行空格统一为普通空格、去掉零宽字符。文本无需处理时不分配新字符串
+fn decode_entities(text: &str) -> Cow<'_, str> {
+    const MAX_ROUNDS: usize = 3;
+    let needs_work = |s: &str| s.contains('&') || s.chars().any(|c| is_zero_width(c) || matches!(c, '\u{00A0}' | '\u{2002}' | '\u{2003}' | '\u{2009}'));
+    if !needs_work(text) {
+        return Cow::Borrowed(text);
+    }
+    let mut decoded = text.to_string();
+    for _ in 0..MAX_ROUNDS {
+        if !decoded.contains('&') {
+            break;
+        }
+        let next = decode_entities_once(&decoded);
+        if next == decoded {
+            break;
+        }
+        decoded = next;
+    }
+    Cow::Owned(decoded.chars()
+        .filter(|c| !is_zero_width(*c))
+        .map(|c| if matches!(c, '\u{00A0}' | '\u{2002}' | '\u{2003}' | '\u{2009}') { ' ' } else { c })
+        .collect())
+}
+
+/// 字符替换表：部分站点把常用字换成外观相同的其他 Unicode 字符（如西里尔字母 а 代替 a）来追踪转载，
+/// 按替换表还原。每行一对 "混淆字符 原字符"，以空白分隔，字符可写成 U+0430 的形式，
+/// 只写一项表示删除该字符；# 开头的行为注释
+struct Substitutions {
+    /// 所有待替换的字符串，长的在前，保证最长匹配
+    pattern: Regex,
+    map: HashMap<String, String>,
+}
+
+impl Substitutions {
+    fn load(path: &str) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if path.is_empty() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(path).map_err(|e| format!("无法读取字符替换表 {}: {}", path, e))?;
+        let parse_token = |token: &str| -> Option<String> {
+            match token.strip_prefix("U+").or_else(|| token.strip_prefix("u+")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).map(String::from),
+                None => Some(token.to_string()),
+            }
+        };
+        let mut map = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || format!("字符替换表 {} 第 {} 行格式错误: {}", path, number + 1, line);
+            let (from, to) = match tokens.as_slice() {
+                [from] => (parse_token(from).ok_or_else(invalid)?, String::new()),
+                [from, to] => (parse_token(from).ok_or_else(invalid)?, parse_token(to).ok_or_else(invalid)?),
+                _ => return Err(invalid().into()),
+            };
+            map.insert(from, to);
+        }
+        if map.is_empty() {
+            return Ok(None);
+        }
+        let mut keys: Vec<&String> = map.keys().collect();
+        keys.sort_by_key(|key| std::cmp::Reverse(key.len()));
+        let pattern = Regex::new(&keys.iter().map(|key| regex::escape(key)).collect::<Vec<_>>().join("|"))?;
+        info!("已加载字符替换表 {}: {} 项", path, map.len());
+        Ok(Some(Substitutions { pattern, map }))
+    }
+
+    /// 返回替换后的文本与替换次数
+    fn apply<'a>(&self, text: &'a str) -> (Cow<'a, str>, usize) {
+        let mut count = 0;
+        let replaced = self.pattern.replace_all(text, |caps: &regex::Captures| {
+            count += 1;
+            self.map[&caps[0]].clone()
+        });
+        (replaced, count)
+    }
+}
+
+/// 内置广告规则只检查不超过此字数的段落，正文段落即使提到网址或"收藏"也不会被误删
+const AD_LINE_MAX_CHARS: usize = 80;
+
+/// 段落中的网址或域名
+static AD_URL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)https?://|www\.|[a-z0-9-]+\.(?:com|net|org|cc|la|info|co|me|tw|cn|xyz|top|vip)\b").unwrap()
+});
+
+/// 站点常见的广告语和水印，正文里几乎不会出现，短段落含有即移除
+static AD_PHRASES: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new("收藏本站|记住本站|一秒记住|本书首发|首发域名|手机用户请|点此举报|无弹窗|笔趣阁").unwrap()
+});
+
+/// 正文里也会出现的说法（"他翻到最新章节"），只有与网址同时出现时才算广告
+static AD_HINTS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new("请收藏|最新章节|免费阅读|手机阅读|加入书签|章节错误|首发").unwrap()
+});
+
+/// 内置广告规则：短段落含站点广告语，或同时含网址和广告字样。单有网址不算，正文中的 "example.me" 之类不受影响
+fn is_ad_line(text: &str) -> bool {
+    text.chars().count() <= AD_LINE_MAX_CHARS && (AD_PHRASES.is_match(text) || (AD_URL.is_match(text) && AD_HINTS.is_match(text)))
+}
+
+/// 正文清洗流水线：所有规则在一次遍历中逐段判定，段落按值移动，不为每条规则生成中间字符串。
+/// 性能基准见测试 clean_pass_benchmark（cargo test --release -- --ignored --nocapture clean_pass_benchmark）
+struct Cleaner {
+    dedupe_title: bool,
+    decode_entities: bool,
+    substitutions: Option<Substitutions>,
+    remove_patterns: Vec<Regex>,
+    ad_heuristics: bool,
+    empty_paragraphs: EmptyParagraphs,
+    /// 场景分隔符，已去掉空白以便与段落比较
+    scene_break_patterns: Vec<String>,
+    scene_break_marker: String,
+    injected: HashSet<u64>,
+    stats: CleanStats,
+}
+
+#[derive(Default)]
+struct CleanStats {
+    paragraphs: usize,
+    entities_decoded: usize,
+    substituted: usize,
+    title_duplicates: usize,
+    injected: usize,
+    scene_breaks: usize,
+    /// 整段移除的广告段落
+    ad_lines: usize,
+    /// 删去了广告片段、保留其余正文的段落
+    ad_fragments: usize,
+}
+
+impl Cleaner {
+    fn new(config: &CleanConfig, substitutions: Option<Substitutions>, remove_patterns: Vec<Regex>, injected: HashSet<u64>) -> Self {
+        Self {
+            dedupe_title: config.dedupe_title,
+            decode_entities: config.decode_entities,
+            substitutions,
+            remove_patterns,
+            ad_heuristics: config.ad_heuristics,
+            empty_paragraphs: config.empty_paragraphs,
+            scene_break_patterns: config.scene_break_patterns.iter()
+                .map(|p| p.chars().filter(|c| !c.is_whitespace()).collect())
+                .collect(),
+            scene_break_marker: config.scene_break_marker.clone(),
+            injected,
+            stats: CleanStats::default(),
+        }
+    }
+
+    /// 实体解码和形近字还原：只有内容确实变化时才替换段落
+    fn decode(&self, text: String, stats: &mut CleanStats) -> String {
+        let text = if !self.decode_entities {
+            text
+        } else {
+            match decode_entities(&text) {
+                Cow::Borrowed(_) => text,
+                Cow::Owned(decoded) => {
+                    stats.entities_decoded += 1;
+                    decoded
+                }
+            }
+        };
+        let Some(substitutions) = &self.substitutions else {
+            return text;
+        };
+        match substitutions.apply(&text) {
+            (Cow::Borrowed(_), _) => text,
+            (Cow::Owned(replaced), count) => {
+                stats.substituted += count;
+                replaced
+            }
+        }
+    }
+
+    /// 删去 remove_patterns 匹配的片段；删完只剩空白和标点，或短段落命中内置广告规则时整段移除，返回 None
+    fn strip_ads(&self, para: String, stats: &mut CleanStats) -> Option<String> {
+        let mut text = para;
+        let mut changed = false;
+        for pattern in &self.remove_patterns {
+            if let Cow::Owned(replaced) = pattern.replace_all(&text, "") {
+                text = replaced;
+                changed = true;
+            }
+        }
+        let junk = (changed && text.chars().all(|c| !c.is_alphanumeric()))
+            || (self.ad_heuristics && is_ad_line(&text));
+        if junk {
+            stats.ad_lines += 1;
+            return None;
+        }
+        if changed {
+            stats.ad_fragments += 1;
+            text = text.trim().to_string();
+        }
+        Some(text)
+    }
+
+    /// 段落去掉空白后与某个场景分隔符完全相同
+    fn is_scene_break(&self, para: &str) -> bool {
+        let compact: String = para.chars().filter(|c| !c.is_whitespace()).collect();
+        !compact.is_empty() && self.scene_break_patterns.contains(&compact)
+    }
+}
+
+impl Transform for Cleaner {
+    fn summary(&self) -> Option<String> {
+        let stats = &self.stats;
+        Some(format!(
+            "正文清洗完成: {} 段，解码实体 {} 处，还原形近字 {} 处，场景分隔 {} 处，移除重复标题 {} 段，移除插入广告 {} 段，移除广告行 {} 段，删去广告片段 {} 段",
+            stats.paragraphs, stats.entities_decoded, stats.substituted, stats.scene_breaks, stats.title_duplicates, stats.injected,
+            stats.ad_lines, stats.ad_fragments
+        ))
+    }
+
+    fn apply(&mut self, result: &mut ChapterResult) {
+        // 统计先取出来，让闭包只需不可变地借用 self
+        let mut stats = std::mem::take(&mut self.stats);
+        result.title = self.decode(std::mem::take(&mut result.title), &mut stats);
+        let title = &result.title;
+        let content = std::mem::take(&mut result.content);
+        let mut is_first = true;
+        let mut last_empty = true;
+        let mut cleaned: Vec<String> = content.into_iter()
+            .filter_map(|para| {
+                let para = self.decode(para, &mut stats);
+                if para.trim().is_empty() {
+                    return match self.empty_paragraphs {
+                        EmptyParagraphs::Drop => None,
+                        EmptyParagraphs::Keep => Some(String::new()),
+                        EmptyParagraphs::Collapse if last_empty => None,
+                        EmptyParagraphs::Collapse => {
+                            last_empty = true;
+                            Some(String::new())
+                        }
+                    };
+                }
+                stats.paragraphs += 1;
+                last_empty = false;
+                // 场景分隔符是作者有意的排版，不参与去重和广告过滤
+                if self.is_scene_break(&para) {
+                    stats.scene_breaks += 1;
+                    is_first = false;
+                    return Some(if self.scene_break_marker.is_empty() { para } else { self.scene_break_marker.clone() });
+                }
+                let para = self.strip_ads(para, &mut stats)?;
+                if std::mem::replace(&mut is_first, false) && self.dedupe_title && is_duplicate_title(title, &para) {
+                    stats.title_duplicates += 1;
+                    return None;
+                }
+                if !self.injected.is_empty() && similarity_fingerprint(&para).is_some_and(|key| self.injected.contains(&key)) {
+                    stats.injected += 1;
+                    return None;
+                }
+                Some(para)
+            })
+            .collect();
+        if self.empty_paragraphs == EmptyParagraphs::Collapse {
+            while cleaned.last().is_some_and(|p| p.is_empty()) {
+                cleaned.pop();
+            }
+        }
+        result.content = cleaned;
+        self.stats = stats;
+    }
+}
+
+const HISTOGRAM_BUCKETS: usize = 20;
+/// 字数低于 中位数 - SUSPECT_SIGMA × 标准差 的章节视为疑似截断
+const SUSPECT_SIGMA: f64 = 3.0;
+
+#[derive(Serialize)]
+struct HistogramBucket {
+    from: usize,
+    to: usize,
+    count: usize,
+}
+
+#[derive(Serialize)]
+struct SuspectChapter {
+    index: usize,
+    title: String,
+    url: String,
+    chars: usize,
+}
+
+/// 成功章节的字数分布统计
+#[derive(Serialize, Default)]
+struct LengthReport {
+    median: usize,
+    mean: f64,
+    std_dev: f64,
+    min: usize,
+    max: usize,
+    threshold: f64,
+    histogram: Vec<HistogramBucket>,
+    suspects: Vec<SuspectChapter>,
+}
+
+/// 从标题解析出的章节序号中发现的缺号与重号
+#[derive(Serialize, Default)]
+struct NumberingReport {
+    numbered: usize,
+    missing: Vec<u64>,
+    duplicates: Vec<u64>,
+    /// 相邻序号之间超过 MAX_NUMBERING_GAP 的跳跃 (前一个序号, 后一个序号)，其间的序号不计为缺失
+    jumps: Vec<(u64, u64)>,
+}
+
+/// 单个分卷的抓取进度
+#[derive(Serialize)]
+struct VolumeReport {
+    name: String,
+    chapters: usize,
+    success: usize,
+    chars: usize,
+}
+
+/// 等待并发许可与实际请求耗时的对比，用于判断提高并发是否有意义
+#[derive(Serialize, Default)]
+struct TimingReport {
+    avg_wait_ms: u64,
+    p95_wait_ms: u64,
+    avg_fetch_ms: u64,
+    p95_fetch_ms: u64,
+    /// 并发槽利用率：请求耗时总和 / (并发数 × 抓取阶段时长)
+    slot_utilization: f64,
+}
+
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn analyze_timing(results: &[ChapterResult], concurrency: usize, phase_ms: u64) -> TimingReport {
+    let fetched: Vec<_> = results.iter().filter(|r| !r.resumed).collect();
+    if fetched.is_empty() {
+        return TimingReport::default();
+    }
+    let mut waits: Vec<u64> = fetched.iter().map(|r| r.wait_ms).collect();
+    let mut fetches: Vec<u64> = fetched.iter().map(|r| r.duration_ms).collect();
+    waits.sort_unstable();
+    fetches.sort_unstable();
+    let total_fetch: u64 = fetches.iter().sum();
+    let capacity = concurrency.max(1) as f64 * phase_ms.max(1) as f64;
+    TimingReport {
+        avg_wait_ms: waits.iter().sum::<u64>() / waits.len() as u64,
+        p95_wait_ms: percentile(&waits, 0.95),
+        avg_fetch_ms: total_fetch / fetches.len() as u64,
+        p95_fetch_ms: percentile(&fetches, 0.95),
+        slot_utilization: (total_fetch as f64 / capacity).min(1.0),
+    }
+}
+
+#[derive(Serialize)]
+struct QualityReport {
+    passed: bool,
+    violations: Vec<String>,
+}
+
+/// 按 [quality] 门槛检查本次运行结果，返回所有不达标项
+fn check_quality(config: &QualityConfig, total_chapters: usize, failed: usize, lengths: &LengthReport) -> QualityReport {
+    let mut violations = Vec::new();
+    if let Some(max) = config.max_failure_percent {
+        let percent = if total_chapters > 0 { failed as f64 * 100.0 / total_chapters as f64 } else { 0.0 };
+        if percent > max {
+            violations.push(format!("失败率 {:.1}% 超过上限 {}%", percent, max));
+        }
+    }
+    if let Some(min) = config.min_avg_chapter_chars && lengths.mean < min as f64 {
+        violations.push(format!("平均章节字数 {:.0} 低于下限 {}", lengths.mean, min));
+    }
+    if let Some(max) = config.max_suspect_chapters && lengths.suspects.len() > max {
+        violations.push(format!("疑似截断章节 {} 章超过上限 {}", lengths.suspects.len(), max));
+    }
+    QualityReport { passed: violations.is_empty(), violations }
+}
+
+#[derive(Serialize)]
+struct Report {
+    generated_at: String,
+    total_chapters: usize,
+    success: usize,
+    failed: usize,
+    paywalled: usize,
+    /// 失败原因 -> 章节数
+    failures: BTreeMap<String, usize>,
+    not_found: Vec<NotFoundChapter>,
+    volumes: Vec<VolumeReport>,
+    lengths: LengthReport,
+    numbering: NumberingReport,
+    timing: TimingReport,
+    phases: PhaseReport,
+    network: BTreeMap<String, HostNetStats>,
+    quality: QualityReport,
+}
+
+/// 软 404 的章节，index 从1开始
+#[derive(Serialize)]
+struct NotFoundChapter {
+    index: usize,
+    url: String,
+    error: String,
+}
+
+/// 各阶段耗时；解析是各章解析页面的耗时之和，并发抓取时与抓取阶段重叠
+#[derive(Debug, Serialize)]
+struct PhaseReport {
+    catalog_ms: u64,
+    /// 目录之后到全部章节抓完，含登录
+    fetch_ms: u64,
+    parse_ms: u64,
+    /// 清洗并写出输出文件
+    write_ms: u64,
+    total_ms: u64,
+}
+
+/// 解析中文数字（支持 〇一二…九十百千万 与"两"），如 "一千零二十三" -> 1023
+fn parse_chinese_number(text: &str) -> Option<u64> {
+    let mut total = 0u64;
+    let mut section = 0u64;
+    let mut digit = 0u64;
+    for c in text.chars() {
+        let value = match c {
+            '零' | '〇' => Some(0),
+            '一' => Some(1),
+            '二' | '两' => Some(2),
+            '三' => Some(3),
+            '四' => Some(4),
+            '五' => Some(5),
+            '六' => Some(6),
+            '七' => Some(7),
+            '八' => Some(8),
+            '九' => Some(9),
+            _ => None,
+        };
+        if let Some(value) = value {
+            digit = value;
+            continue;
+        }
+        match c {
+            '十' => section += digit.max(1) * 10,
+            '百' => section += digit * 100,
+            '千' => section += digit * 1000,
+            '万' => {
+                total += (section + digit) * 10000;
+                section = 0;
+            }
+            _ => return None,
+        }
+        digit = 0;
+    }
+    Some(total + section + digit)
+}
+
+/// 从 "第1023章"、"第一卷 第一千零二十三章" 之类的标题中解析章节序号
+fn parse_chapter_number(title: &str) -> Option<u64> {
+    title.match_indices('第').find_map(|(pos, _)| parse_number_after(&title[pos + '第'.len_utf8()..]))
+}
+
+fn parse_number_after(rest: &str) -> Option<u64> {
+    let end = rest.find(['章', '回', '节', '話', '话'])?;
+    let number = rest[..end].trim();
+    if number.is_empty() {
+        return None;
+    }
+    if number.chars().all(|c| c.is_ascii_digit()) {
+        return number.parse().ok();
+    }
+    let fullwidth: String = number.chars()
+        .map(|c| if ('０'..='９').contains(&c) { char::from(b'0' + (c as u32 - '０' as u32) as u8) } else { c })
+        .collect();
+    if fullwidth.chars().all(|c| c.is_ascii_digit()) {
+        return fullwidth.parse().ok();
+    }
+    parse_chinese_number(number)
+}
+
+/// 按分卷统计章节数、成功数和字数，分卷按首次出现的顺序排列；没有分卷信息时为空
+fn analyze_volumes(results: &[ChapterResult]) -> Vec<VolumeReport> {
+    let mut volumes: Vec<VolumeReport> = Vec::new();
+    for result in results {
+        let Some(name) = &result.volume else {
+            continue;
+        };
+        let index = match volumes.iter().position(|v| &v.name == name) {
+            Some(index) => index,
+            None => {
+                volumes.push(VolumeReport { name: name.clone(), chapters: 0, success: 0, chars: 0 });
+                volumes.len() - 1
+            }
+        };
+        let volume = &mut volumes[index];
+        volume.chapters += 1;
+        if result.success {
+            volume.success += 1;
+            volume.chars += result.chars;
+        }
+    }
+    volumes
+}
+
+/// 相邻两个序号相差超过此值时视为编号跳跃（标题中的年份、误解析的数字、换了一套编号），不把中间的序号列为缺失
+const MAX_NUMBERING_GAP: u64 = 1000;
+
+/// 检查标题序号的连续性，列出缺失和重复的章节号
+fn analyze_numbering(results: &[ChapterResult]) -> NumberingReport {
+    let mut numbers: Vec<u64> = results.iter().filter(|r| r.success).filter_map(|r| parse_chapter_number(&r.title)).collect();
+    numbers.sort_unstable();
+    let mut report = NumberingReport { numbered: numbers.len(), ..NumberingReport::default() };
+    for pair in numbers.windows(2) {
+        let (prev, next) = (pair[0], pair[1]);
+        if prev == next {
+            if report.duplicates.last() != Some(&prev) {
+                report.duplicates.push(prev);
+            }
+        } else if next - prev > MAX_NUMBERING_GAP {
+            report.jumps.push((prev, next));
+        } else {
+            report.missing.extend(prev + 1..next);
+        }
+    }
+    report
+}
+
+/// 把序号列表压缩成 "3, 7-9, 12" 这样的区间表示，便于在日志中阅读
+fn format_number_ranges(numbers: &[u64]) -> String {
+    let mut ranges: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < numbers.len() {
+        let start = numbers[i];
+        let mut end = start;
+        while i + 1 < numbers.len() && numbers[i + 1] == end + 1 {
+            i += 1;
+            end = numbers[i];
+        }
+        ranges.push(if start == end { start.to_string() } else { format!("{}-{}", start, end) });
+        i += 1;
+    }
+    ranges.join(", ")
+}
+
+fn content_chars(content: &[String]) -> usize {
+    content.iter().map(|p| p.chars().count()).sum()
+}
+
+/// 统计成功章节的字数直方图，并把明显低于中位数的章节标记为疑似截断
+fn analyze_lengths(results: &[ChapterResult]) -> LengthReport {
+    let lengths: Vec<(usize, &ChapterResult)> = results.iter()
+        .filter(|r| r.success)
+        .map(|r| (r.chars, r))
+        .collect();
+    if lengths.is_empty() {
+        return LengthReport::default();
+    }
+    let mut sorted: Vec<usize> = lengths.iter().map(|(chars, _)| *chars).collect();
+    sorted.sort_unstable();
+    let n = sorted.len();
+    let median = sorted[n / 2];
+    let (min, max) = (sorted[0], sorted[n - 1]);
+    let mean = sorted.iter().sum::<usize>() as f64 / n as f64;
+    let std_dev = (sorted.iter().map(|&c| (c as f64 - mean).powi(2)).sum::<f64>() / n as f64).sqrt();
+    let threshold = median as f64 - SUSPECT_SIGMA * std_dev;
+
+    let width = (max / HISTOGRAM_BUCKETS + 1).max(1);
+    let mut histogram: Vec<HistogramBucket> = (0..HISTOGRAM_BUCKETS)
+        .map(|i| HistogramBucket { from: i * width, to: (i + 1) * width, count: 0 })
+        .collect();
+    for &chars in &sorted {
+        histogram[(chars / width).min(HISTOGRAM_BUCKETS - 1)].count += 1;
+    }
+
+    let suspects = lengths.iter()
+        .filter(|(chars, _)| *chars == 0 || (*chars as f64) < threshold)
+        .map(|(chars, r)| SuspectChapter { index: r.index + 1, title: r.title.clone(), url: r.url.clone(), chars: *chars })
+        .collect();
+    LengthReport { median, mean, std_dev, min, max, threshold, histogram, suspects }
+}
+
+/// 时间线上的一行：相对抓取开始的排队、开始和结束时刻（毫秒）
+struct TimelineRow<'a> {
+    result: &'a ChapterResult,
+    queued_ms: i64,
+    started_ms: i64,
+    finished_ms: i64,
+}
+
+fn timeline_rows(results: &[ChapterResult]) -> Vec<TimelineRow<'_>> {
+    let queued = |r: &ChapterResult| r.started_at - chrono::Duration::milliseconds(r.wait_ms as i64);
+    let fetched = || results.iter().filter(|r| !r.resumed);
+    let Some(origin) = fetched().map(queued).min() else {
+        return Vec::new();
+    };
+    fetched()
+        .map(|r| TimelineRow {
+            result: r,
+            queued_ms: (queued(r) - origin).num_milliseconds(),
+            started_ms: (r.started_at - origin).num_milliseconds(),
+            finished_ms: (r.completed_at - origin).num_milliseconds(),
+        })
+        .collect()
+}
+
+fn result_status(result: &ChapterResult) -> &'static str {
+    if result.paywalled {
+        "paywalled"
+    } else if result.success && result.partial {
+        "partial"
+    } else if result.success {
+        "success"
+    } else if result.not_found {
+        "not_found"
+    } else {
+        "failed"
+    }
+}
+
+/// 请求超时的失败信息都带有此标记，汇总和失败章节列表中单独归为"请求超时"
+const TIMED_OUT: &str = "Timed out";
+/// 软 404 的失败信息都带有此标记
+const SOFT_404: &str = "Soft 404";
+/// 断点续抓时仍在退避期内、本次未重试的章节，失败信息带有此标记
+const BACKING_OFF: &str = "退避中";
+
+/// 失败章节的原因分类，区分被封禁、限流、服务端故障和页面解析失败
+fn failure_reason(result: &ChapterResult) -> String {
+    if result.not_found {
+        return "页面不存在（软 404）".to_string();
+    }
+    match result.http_status {
+        Some(code) if code >= 400 => format!("HTTP {}", code),
+        _ if result.error_msg.as_deref().is_some_and(|msg| msg.contains(TIMED_OUT)) => "请求超时".to_string(),
+        None if result.error_msg.as_deref().is_some_and(|msg| msg.contains(BACKING_OFF)) => "退避中（本次未重试）".to_string(),
+        Some(_) => "页面解析失败".to_string(),
+        None => "网络错误".to_string(),
+    }
+}
+
+/// 按原因统计失败章节（不含付费章节）
+fn failure_reasons(results: &[ChapterResult]) -> BTreeMap<String, usize> {
+    let mut reasons = BTreeMap::new();
+    for result in results.iter().filter(|r| !r.success && !r.paywalled) {
+        *reasons.entry(failure_reason(result)).or_insert(0) += 1;
+    }
+    reasons
+}
+
+/// 失败章节列表中的一条记录；--retry-failures 只用到 url，其余字段可以手工删改
+#[derive(Serialize, Deserialize)]
+struct FailureRecord {
+    /// 章节序号，从1开始
+    #[serde(default)]
+    index: usize,
+    url: String,
+    #[serde(default)]
+    title: String,
+    /// 失败原因分类，同汇总中的「失败原因」
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    http_status: Option<u16>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// 写出失败章节列表（不含付费章节）；本次没有失败章节时删除上次留下的列表，返回写出的章节数
+fn write_failures(path: &str, results: &[ChapterResult]) -> std::io::Result<usize> {
+    let records: Vec<FailureRecord> = results.iter()
+        .filter(|r| !r.success && !r.paywalled)
+        .map(|r| FailureRecord {
+            index: r.index + 1,
+            url: r.url.clone(),
+            title: r.title.clone(),
+            status: failure_reason(r),
+            http_status: r.http_status,
+            error: r.error_msg.clone(),
+        })
+        .collect();
+    if records.is_empty() {
+        match std::fs::remove_file(output_path(path)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => return Ok(0),
+        }
+    }
+    std::fs::write(output_path(path), serde_json::to_string_pretty(&records)?)?;
+    Ok(records.len())
+}
+
+/// 读取失败章节列表中的地址
+fn load_failures(path: &str) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+    let json = std::fs::read_to_string(output_path(path)).map_err(|e| format!("无法读取失败章节列表 {}: {}", path, e))?;
+    let records: Vec<FailureRecord> = serde_json::from_str(&json).map_err(|e| format!("失败章节列表解析失败 {}: {}", path, e))?;
+    Ok(records.into_iter().map(|record| record.url).collect())
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn render_timeline_csv(rows: &[TimelineRow]) -> String {
+    let mut out = String::from("index,title,url,status,queued_ms,started_ms,finished_ms,wait_ms,duration_ms\n");
+    for row in rows {
+        let r = row.result;
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{}\n",
+            r.index + 1, csv_field(&r.title), csv_field(&r.url), result_status(r),
+            row.queued_ms, row.started_ms, row.finished_ms, r.wait_ms, r.duration_ms
+        ));
+    }
+    out
+}
+
+/// 生成自包含的甘特图 HTML：灰色为等待并发许可，彩色为请求耗时，鼠标悬停查看详情
+fn render_timeline_html(rows: &[TimelineRow]) -> String {
+    const WIDTH: f64 = 1200.0;
+    let span = rows.iter().map(|row| row.finished_ms).max().unwrap_or(0).max(1) as f64;
+    let scale = |ms: i64| ms as f64 / span * WIDTH;
+    let mut bars = String::new();
+    for row in rows {
+        let r = row.result;
+        let color = match result_status(r) {
+            "success" => "#4caf50",
+            "partial" => "#ffb300",
+            "paywalled" => "#7e57c2",
+            _ => "#e53935",
+        };
+        bars.push_str(&format!(
+            "<div class=\"row\" title=\"[{}] {} | {} | 等待 {}ms | 请求 {}ms\"><span class=\"wait\" style=\"left:{:.1}px;width:{:.1}px\"></span><span class=\"run\" style=\"left:{:.1}px;width:{:.1}px;background:{}\"></span></div>\n",
+            r.index + 1, html_escape(&r.title), result_status(r), r.wait_ms, r.duration_ms,
+            scale(row.queued_ms), scale(row.started_ms - row.queued_ms),
+            scale(row.started_ms), scale(row.finished_ms - row.started_ms).max(1.0), color
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>抓取时间线</title><style>\n\
+         body{{font-family:sans-serif;margin:16px}}\n\
+         .chart{{width:{WIDTH}px;border-left:1px solid #999}}\n\
+         .row{{position:relative;height:4px;margin-bottom:1px}}\n\
+         .row:hover{{background:#eee}}\n\
+         .row span{{position:absolute;top:0;height:4px}}\n\
+         .wait{{background:#ccc}}\n\
+         </style></head><body>\n<h3>抓取时间线（共 {} 章，{:.1}s）</h3>\n<div class=\"chart\">\n{}</div>\n</body></html>\n",
+        rows.len(), span / 1000.0, bars
+    )
+}
+
+/// 按扩展名导出时间线：.html 为甘特图，其余为 CSV
+fn write_timeline(path: &str, results: &[ChapterResult]) -> std::io::Result<()> {
+    let mut rows = timeline_rows(results);
+    rows.sort_by_key(|row| row.result.index);
+    let content = if path.to_ascii_lowercase().ends_with(".html") {
+        render_timeline_html(&rows)
+    } else {
+        render_timeline_csv(&rows)
+    };
+    std::fs::write(output_path(path), content)
+}
+
+/// 从 Content-Type 头或 `<meta>` 标签声明的字符集中取出编码名
+fn charset_from_content_type(content_type: &str) -> Option<&str> {
+    let lower = content_type.to_ascii_lowercase();
+    let pos = lower.find("charset=")?;
+    let value = content_type[pos + 8..].trim_start_matches(['"', '\'']);
+    let end = value.find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_')).unwrap_or(value.len());
+    (end > 0).then(|| &value[..end])
+}
+
+/// 在页面开头查找 `<meta charset=...>` 或 `<meta http-equiv="Content-Type" content="...; charset=...">`
+fn charset_from_meta(bytes: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+    // 按规范字符集声明须出现在前 1024 字节内，放宽一些以兼容不规范的页面
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(4096)]);
+    static META_CHARSET: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?i)<meta[^>]+charset\s*=\s*["']?\s*([\w-]+)"#).unwrap());
+    let label = META_CHARSET.captures(&head)?.get(1)?.as_str().to_string();
+    encoding_rs::Encoding::for_label(label.as_bytes())
+}
+
+/// 简繁体中文里最常见的一批字，用来判断哪种解码结果更像正常的中文
+const COMMON_HANZI: &str = "的一是不了在人有我他这這个個中大来來上们們到说說时時要就出会會也你对對生能而子那得于於着著下自之年过過发發后後作里裡用道行所然家种種事成方多经經么麼去法学學如都同现現当當没沒动動面起看定天分还還进進";
+
+/// 没有任何声明时猜测编码：能按 UTF-8 解码就用 UTF-8；否则在 GBK 和 Big5 中
+/// 取没有解码错误、且常用字最多的一个（同一段字节往往两种都能"成功"解码）
+fn sniff_encoding(bytes: &[u8]) -> &'static encoding_rs::Encoding {
+    if std::str::from_utf8(bytes).is_ok() {
+        return encoding_rs::UTF_8;
+    }
+    let score = |text: &str| text.chars().filter(|c| COMMON_HANZI.contains(*c)).count();
+    [encoding_rs::GBK, encoding_rs::BIG5]
+        .into_iter()
+        .filter_map(|encoding| {
+            let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+            (!had_errors).then(|| (encoding, score(&text)))
+        })
+        .rev()
+        .max_by_key(|(_, score)| *score)
+        .map_or(encoding_rs::GBK, |(encoding, _)| encoding)
+}
+
+/// 读取响应正文并解码为字符串：依次按 Content-Type、`<meta>` 声明和内容猜测确定编码，
+/// 避免 GBK/GB2312/Big5 页面被当成 UTF-8 解成乱码
+async fn read_html(resp: reqwest::Response) -> Result<String, BodyError> {
+    read_html_filtered(resp, None).await
+}
+
+/// 读取响应正文时的错误
+#[derive(Debug)]
+enum BodyError {
+    Request(reqwest::Error),
+    /// 正文中的 gzip 数据解压后超过上限（字节）
+    TooLarge(u64),
+}
+
+impl std::fmt::Display for BodyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyError::Request(e) => e.fmt(f),
+            BodyError::TooLarge(limit) => write!(f, "gzip body exceeds {} bytes after decompression", limit),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+impl From<reqwest::Error> for BodyError {
+    fn from(e: reqwest::Error) -> Self {
+        BodyError::Request(e)
+    }
+}
+
+/// gzip 数据的开头两个字节
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// 正文中 gzip 数据解压后的上限，防止几 KB 的压缩炸弹展开成几 GB 占满内存
+const MAX_GUNZIP_BYTES: u64 = 64 * 1024 * 1024;
+
+/// 解压被当作普通正文发送的 gzip 数据（有的镜像站 Content-Encoding 写错或漏写，直接解码会得到乱码）；
+/// 解压失败时原样返回，解压后超过 limit 字节时返回错误
+fn gunzip_body(url: &str, raw: Vec<u8>, limit: u64) -> Result<Vec<u8>, BodyError> {
+    let mut body = Vec::new();
+    match flate2::read::MultiGzDecoder::new(raw.as_slice()).take(limit + 1).read_to_end(&mut body) {
+        Ok(_) if body.len() as u64 > limit => Err(BodyError::TooLarge(limit)),
+        Ok(_) => {
+            debug!("正文是 gzip 压缩数据，已解压 ({} -> {} 字节): {}", raw.len(), body.len(), url);
+            Ok(body)
+        }
+        Err(e) => {
+            debug!("正文以 gzip 标记开头但解压失败，按原样解码: {} ({})", url, e);
+            Ok(raw)
+        }
+    }
+}
+
+/// 同 read_html，给出预过滤规则时边接收边丢弃正文容器以外的标记，再对保留的部分确定编码并解码
+async fn read_html_filtered(mut resp: reqwest::Response, prefilter: Option<&HtmlPrefilter>) -> Result<String, BodyError> {
+    let declared = resp.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(charset_from_content_type)
+        .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()));
+    let url = resp.url().to_string();
+    // 第一块可能只有一个字节，凑够 gzip 标记的长度再判断
+    let mut first = Vec::new();
+    while first.len() < GZIP_MAGIC.len() && let Some(chunk) = resp.chunk().await? {
+        first.extend_from_slice(&chunk);
+    }
+    let mut received = first.len();
+    let bytes = if first.starts_with(&GZIP_MAGIC) {
+        // 压缩数据无法边收边过滤，收完解压后再整体交给预过滤
+        let mut raw = first;
+        while let Some(chunk) = resp.chunk().await? {
+            raw.extend_from_slice(&chunk);
+        }
+        received = raw.len();
+        let body = gunzip_body(&url, raw, MAX_GUNZIP_BYTES)?;
+        match prefilter {
+            Some(prefilter) => {
+                let mut run = prefilter.start();
+                run.feed(&body);
+                run.finish()
+            }
+            None => body,
+        }
+    } else {
+        match prefilter {
+            Some(prefilter) => {
+                let mut run = prefilter.start();
+                run.feed(&first);
+                while let Some(chunk) = resp.chunk().await? {
+                    received += chunk.len();
+                    run.feed(&chunk);
+                }
+                run.finish()
+            }
+            None => {
+                let mut body = first;
+                while let Some(chunk) = resp.chunk().await? {
+                    body.extend_from_slice(&chunk);
+                }
+                received = body.len();
+                body
+            }
+        }
+    };
+    record_download(&url, received as u64);
+    let encoding = declared
+        .or_else(|| charset_from_meta(&bytes))
+        .unwrap_or_else(|| sniff_encoding(&bytes));
+    let (html, _, _) = encoding.decode(&bytes);
+    Ok(html.into_owned())
+}
+
+/// crawl.prefilter_html 开启时按章节页用到的选择器建立预过滤规则；
+/// 内嵌 JSON 提取需要完整的脚本、拟人模式要从整页中找页面资源，这两种情况不过滤
+fn build_prefilter(config: &Config, selectors: &SelectorsConfig, stats: &Arc<PrefilterStats>) -> Option<HtmlPrefilter> {
+    if !config.crawl.prefilter_html || config.human.enabled || !selectors.json_state_pattern.is_empty() {
+        return None;
+    }
+    let used = [
+        selectors.title_selector.as_str(),
+        selectors.content_selector.as_str(),
+        selectors.note_selector.as_str(),
+        selectors.content_next_page_selector.as_str(),
+    ];
+    // 软 404 的提示语通常不在正文元素里，和付费标记一样要在过滤后保留下来
+    let markers: Vec<String> = config.crawl.paywall_markers.iter().chain(&config.crawl.soft_404_markers).cloned().collect();
+    HtmlPrefilter::new(&used, &markers, stats.clone())
+}
+
+/// 章节抓取任务共享的只读上下文
+struct FetchContext {
+    identities: Arc<IdentityManager>,
+    extractor: Box<dyn Extract>,
+    note_separator: String,
+    min_paragraphs: usize,
+    accept_partial: bool,
+    soft_404_markers: Vec<String>,
+    catalog_url: String,
+    human: Option<HumanConfig>,
+    book_id_pattern: Option<Regex>,
+    max_retries: usize,
+    retry_backoff_ms: u64,
+    content_next_sel: Option<scraper::Selector>,
+    prefilter: Option<HtmlPrefilter>,
+    cache: Option<ResponseCache>,
+    request_timeout: Option<Duration>,
+    /// 各章解析页面的耗时累计（微秒）
+    parse_us: AtomicU64,
+    /// 目录中的全部章节地址，跟随正文分页时遇到它们说明已经翻到下一章
+    chapter_urls: HashSet<String>,
+    hosts: Arc<HostOverrides>,
+}
+
+impl FetchContext {
+    /// 在 request_timeout 内等待请求的一个阶段（发出请求、读取正文）；客户端自身的超时之外再兜底，
+    /// 卡住的连接不会一直占着并发许可
+    async fn deadline<T>(&self, stage: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+        match self.request_timeout {
+            Some(limit) => timeout(limit, stage).await.unwrap_or_else(|_| Err(format!("{} after {}s", TIMED_OUT, limit.as_secs()))),
+            None => stage.await,
+        }
+    }
+
+    /// 解析一个页面并计入解析耗时
+    fn extract(&self, extractor: &dyn Extract, html: &str, page_url: &reqwest::Url) -> PageOutcome {
+        let start = Instant::now();
+        let outcome = extractor.extract(html, page_url);
+        self.parse_us.fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        outcome
+    }
+
+    /// 页面所在域名的解析规则：正文提取方式、正文分页链接和 HTML 预过滤
+    fn page_rules(&self, url: &str) -> (&dyn Extract, Option<&scraper::Selector>, Option<&HtmlPrefilter>) {
+        match self.hosts.pages(url) {
+            Some(pages) => (pages.extractor.as_ref(), pages.content_next_sel.as_ref(), pages.prefilter.as_ref()),
+            None => (self.extractor.as_ref(), self.content_next_sel.as_ref(), self.prefilter.as_ref()),
+        }
+    }
+
+    /// 配置了响应缓存时取出地址对应、且按同样的预过滤规则保存的页面
+    fn cached_page(&self, url: &str) -> Option<CachedPage> {
+        let filter = self.page_rules(url).2.map_or("", HtmlPrefilter::key);
+        self.cache.as_ref()?.get(url, filter)
+    }
+
+    /// 读取章节页正文：304 时使用缓存的页面，其余成功响应连同校验头存入缓存
+    async fn read_page(&self, resp: reqwest::Response, url: &str, cached: Option<CachedPage>, prefilter: Option<&HtmlPrefilter>) -> Result<String, String> {
+        if resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+            let (Some(cache), Some(page)) = (&self.cache, cached) else {
+                return Err("HTTP 304 without a cached copy".to_string());
+            };
+            cache.record_hit();
+            return Ok(page.html);
+        }
+        let headers = resp.headers().clone();
+        let html = read_html_filtered(resp, prefilter).await.map_err(|e| match e {
+            BodyError::Request(e) => request_error(e),
+            e => e.to_string(),
+        })?;
+        if let Some(cache) = &self.cache && let Err(e) = cache.store(url, &headers, prefilter.map_or("", HtmlPrefilter::key), &html) {
+            warn!("响应缓存写入失败 {}: {}", url, e);
+        }
+        Ok(html)
+    }
+}
+
+/// 请求错误转为失败信息，超时加上 TIMED_OUT 标记
+fn request_error(e: reqwest::Error) -> String {
+    if e.is_timeout() {
+        format!("{}: {}", TIMED_OUT, e)
+    } else {
+        e.to_string()
+    }
+}
+
+/// 单章最多跟随的正文分页数，防止下一页链接成环或指向无关页面时无限翻页
+const MAX_CONTENT_PAGES: usize = 50;
+
+/// 正文分页中的下一页地址
+fn content_next_page(html: &str, page_url: &reqwest::Url, sel: &scraper::Selector) -> Option<reqwest::Url> {
+    let document = scraper::Html::parse_document(html);
+    let href = document.select(sel).next()?.value().attr("href")?;
+    page_url.join(href).ok()
+}
+
+/// 第 attempt 次重试前的等待时间：指数退避，并在 [一半, 全部] 之间随机抖动，避免所有任务同时重试
+fn retry_delay(backoff_ms: u64, attempt: usize) -> Duration {
+    let max_ms = backoff_ms.saturating_mul(1 << attempt.min(16));
+    Duration::from_millis(rand::thread_rng().gen_range(max_ms / 2..=max_ms))
+}
+
+/// Retry-After 的上限，防止站点给出几小时的等待把抓取卡住
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+/// 解析 Retry-After 响应头，支持秒数和 HTTP 日期两种写法
+fn retry_after(resp: &reqwest::Response) -> Option<Duration> {
+    let value = resp.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => {
+            let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            (at.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or(Duration::ZERO)
+        }
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+/// 用 book_id_pattern 的第一个捕获组从地址中提取书籍ID
+fn extract_book_id<'a>(pattern: &Regex, url: &'a str) -> Option<&'a str> {
+    pattern.captures(url).and_then(|caps| caps.get(1)).map(|m| m.as_str())
+}
+
+/// 单个章节页面的解析结果
+enum PageOutcome {
+    /// 标题、正文段落、作者注释段落
+    Chapter(String, Vec<String>, Vec<String>),
+    Paywalled(String),
+    Redirect(reqwest::Url),
+    TitleMissing,
+}
+
+/// 软 404 判断，返回判断依据：章节标题就是提示语（忽略标点），或页面没有正文（thin）时含有提示语、页面标题（<title>）像 404 页面。
+/// 有正文时不在页面中查找提示语，避免正文恰好提到"页面不存在"而误判
+fn find_soft_404(html: &str, title: Option<&str>, thin: bool, markers: &[String]) -> Option<String> {
+    static PAGE_TITLE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+    let compact = |text: &str| text.chars().filter(|c| c.is_alphanumeric()).collect::<String>();
+    let markers: Vec<&str> = markers.iter().map(String::as_str).filter(|marker| !marker.is_empty()).collect();
+    if let Some(title) = title.map(compact)
+        && let Some(marker) = markers.iter().find(|marker| compact(marker) == title)
+    {
+        return Some(format!("章节标题为提示语「{}」", marker));
+    }
+    if !thin {
+        return None;
+    }
+    if let Some(marker) = markers.iter().find(|marker| html.contains(*marker)) {
+        return Some(format!("页面含提示语「{}」", marker));
+    }
+    let page_title = PAGE_TITLE.captures(html)?.get(1)?.as_str().trim();
+    let lower = page_title.to_lowercase();
+    (lower.contains("404") || lower.contains("not found")).then(|| format!("页面标题为「{}」", page_title))
+}
+
+/// 某些聚合站用 meta refresh 或 canonical 链接代替 HTTP 跳转，返回应跟随的地址
+fn find_html_redirect(document: &scraper::Html, page_url: &reqwest::Url) -> Option<reqwest::Url> {
+    let refresh_sel = scraper::Selector::parse("meta[http-equiv]").unwrap();
+    let refresh_target = document.select(&refresh_sel)
+        .filter(|meta| meta.value().attr("http-equiv").is_some_and(|v| v.eq_ignore_ascii_case("refresh")))
+        .filter_map(|meta| meta.value().attr("content"))
+        .find_map(|content| {
+            let lower = content.to_ascii_lowercase();
+            let pos = lower.find("url=")?;
+            Some(content[pos + 4..].trim().trim_matches(|c| c == '\'' || c == '"').to_string())
+        });
+    let canonical_sel = scraper::Selector::parse("link[rel=canonical]").unwrap();
+    let target = refresh_target.or_else(|| {
+        document.select(&canonical_sel)
+            .filter_map(|link| link.value().attr("href"))
+            .map(|href| href.to_string())
+            .next()
+    })?;
+    let target = page_url.join(&target).ok()?;
+    if target == *page_url { None } else { Some(target) }
+}
+
+/// 按 [selectors] 配置的 CSS 选择器提取章节
+struct SelectorExtractor {
+    title_sel: scraper::Selector,
+    content_sel: scraper::Selector,
+    note_sel: Option<scraper::Selector>,
+    paywall_markers: Vec<String>,
+}
+
+impl Extract for SelectorExtractor {
+    fn extract(&self, html: &str, page_url: &reqwest::Url) -> PageOutcome {
+        if let Some(marker) = find_paywall_marker(html, &self.paywall_markers) {
+            return PageOutcome::Paywalled(marker.to_string());
+        }
+        let document = scraper::Html::parse_document(html);
+        let select_texts = |sel: &scraper::Selector| -> Vec<String> {
+            document
+                .select(sel)
+                .map(|p| p.text().collect::<Vec<_>>().join(""))
+                .collect()
+        };
+        match document.select(&self.title_sel).next() {
+            Some(title_elem) => {
+                let chapter_title = title_elem.text().collect::<Vec<_>>().join("");
+                let paragraphs = select_texts(&self.content_sel);
+                // 正文中的空段落留给清洗阶段按 empty_paragraphs 处理
+                let mut notes = self.note_sel.as_ref().map(select_texts).unwrap_or_default();
+                notes.retain(|note| !note.trim().is_empty());
+                PageOutcome::Chapter(chapter_title, paragraphs, notes)
+            }
+            None => match find_html_redirect(&document, page_url) {
+                Some(target) => PageOutcome::Redirect(target),
+                None => PageOutcome::TitleMissing,
+            },
+        }
+    }
+}
+
+/// 从 `<script>` 内嵌的 JSON 状态（如 Next.js 的 __NEXT_DATA__）中提取章节，无需无头浏览器
+struct JsonStateExtractor {
+    pattern: Regex,
+    title_pointer: String,
+    content_pointer: String,
+    paywall_markers: Vec<String>,
+}
+
+/// 正文字段可以是字符串数组（每项一段）、纯文本（按换行分段）或 HTML 片段（取各 `<p>` 的文本）
+fn json_paragraphs(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::Array(items) => items.iter().flat_map(json_paragraphs).collect(),
+        serde_json::Value::String(text) if text.contains('<') => {
+            let fragment = scraper::Html::parse_fragment(text);
+            let p_sel = scraper::Selector::parse("p").unwrap();
+            let paragraphs: Vec<String> = fragment.select(&p_sel).map(|p| p.text().collect::<String>()).collect();
+            if paragraphs.is_empty() {
+                fragment.root_element().text().collect::<String>().lines().map(str::to_string).collect()
+            } else {
+                paragraphs
+            }
+        }
+        serde_json::Value::String(text) => text.lines().map(str::to_string).collect(),
+        serde_json::Value::Null => Vec::new(),
+        other => vec![other.to_string()],
+    }
+}
+
+impl Extract for JsonStateExtractor {
+    fn extract(&self, html: &str, page_url: &reqwest::Url) -> PageOutcome {
+        if let Some(marker) = find_paywall_marker(html, &self.paywall_markers) {
+            return PageOutcome::Paywalled(marker.to_string());
+        }
+        let state = self.pattern.captures(html)
+            .and_then(|caps| caps.get(1))
+            .and_then(|blob| serde_json::from_str::<serde_json::Value>(blob.as_str()).ok());
+        let title = state.as_ref()
+            .and_then(|state| state.pointer(&self.title_pointer))
+            .and_then(|title| title.as_str())
+            .filter(|title| !title.is_empty());
+        match (title, &state) {
+            (Some(title), Some(state)) => {
+                let paragraphs = state.pointer(&self.content_pointer).map(json_paragraphs).unwrap_or_default();
+                PageOutcome::Chapter(title.to_string(), paragraphs, Vec::new())
+            }
+            _ => match find_html_redirect(&scraper::Html::parse_document(html), page_url) {
+                Some(target) => PageOutcome::Redirect(target),
+                None => PageOutcome::TitleMissing,
+            },
+        }
+    }
+}
+
+const MAX_HTML_REDIRECTS: usize = 3;
+
+/// 收集页面引用的图片、样式表和脚本地址，随机取至多 max 个
+fn find_page_assets(html: &str, page_url: &reqwest::Url, max: usize) -> Vec<reqwest::Url> {
+    let document = scraper::Html::parse_document(html);
+    let asset_sel = scraper::Selector::parse("img[src], script[src], link[rel=stylesheet][href]").unwrap();
+    let mut assets: Vec<reqwest::Url> = document.select(&asset_sel)
+        .filter_map(|elem| elem.value().attr("src").or_else(|| elem.value().attr("href")))
+        .filter_map(|href| page_url.join(href).ok())
+        .filter(|url| url.scheme().starts_with("http"))
+        .collect();
+    assets.shuffle(&mut rand::thread_rng());
+    assets.truncate(max);
+    assets
+}
+
+/// 拟人浏览：像浏览器一样加载少量页面资源，然后在本章停留一段随机时间
+async fn browse_like_human(human: &HumanConfig, identity: &Identity, page_url: &reqwest::Url, html: &str) {
+    for asset in find_page_assets(html, page_url, human.max_assets) {
+        if let Ok(resp) = identity.get_with_accept(asset.as_str(), "*/*").header("Referer", page_url.as_str()).send().await {
+            let _ = resp.bytes().await;
+        }
+    }
+    let dwell_ms = rand::thread_rng().gen_range(human.dwell_min_ms..=human.dwell_max_ms.max(human.dwell_min_ms));
+    tokio::time::sleep(Duration::from_millis(dwell_ms)).await;
+}
+
+async fn fetch_chapter(index: usize, url: String, referer: Option<String>, ctx: &FetchContext) -> ChapterResult {
+    let fetch_start = Instant::now();
+    let completed_at = chrono::Utc::now();
+    let identity = ctx.identities.next();
+    let mut target = url.clone();
+    let mut last_status = None;
+
+    if let Some(human) = &ctx.human {
+        let revisit = rand::thread_rng().gen_bool(human.catalog_revisit_chance.clamp(0.0, 1.0));
+        if revisit {
+            debug!("[{}] 回到目录页浏览", index + 1);
+            identity.throttle(&ctx.catalog_url).await;
+            if let Ok(resp) = identity.get(&ctx.catalog_url).send().await {
+                let _ = resp.bytes().await;
+            }
+        }
+    }
+
+    for _ in 0..=MAX_HTML_REDIRECTS {
+        identity.throttle(&target).await;
+        let mut request = identity.get(&target);
+        if let Some(referer) = &referer {
+            request = request.header("Referer", referer.as_str());
+        }
+        let cached = ctx.cached_page(&target);
+        if let Some(page) = &cached {
+            request = ResponseCache::conditional(page, request);
+        }
+        let resp = match ctx.deadline(async { request.send().await.map_err(request_error) }).await {
+            Ok(resp) => {
+                identity.report_proxy(resp.status() != reqwest::StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+                resp
+            }
+            Err(e) => {
+                identity.report_proxy(false);
+                return ChapterResult::transient_failure(index, url, format!("Send failed: {}", e), fetch_start.elapsed().as_millis() as u64, completed_at);
+            }
+        };
+        let status = resp.status();
+        last_status = Some(status.as_u16());
+        if status.is_client_error() || status.is_server_error() {
+            let duration_ms = fetch_start.elapsed().as_millis() as u64;
+            let mut result = if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                let mut result = ChapterResult::transient_failure(index, url, format!("HTTP {} (rate limited)", status), duration_ms, completed_at);
+                result.retry_after = retry_after(&resp);
+                identity.report_throttled(result.retry_after);
+                result
+            } else if status.is_server_error() {
+                ChapterResult::transient_failure(index, url, format!("HTTP {} (server error)", status), duration_ms, completed_at)
+            } else if status == reqwest::StatusCode::FORBIDDEN {
+                // 403 多半是被封禁，短时间内重试只会加重封禁
+                ChapterResult::failure(index, url, format!("HTTP {} (access denied, possibly banned)", status), duration_ms, completed_at)
+            } else {
+                ChapterResult::failure(index, url, format!("HTTP {}", status), duration_ms, completed_at)
+            };
+            result.http_status = last_status;
+            return result;
+        }
+        let page_url = resp.url().clone();
+        if let Some(pattern) = &ctx.book_id_pattern {
+            // 聚合站有时把失效章节跳转到另一本书，落地页的书籍ID与请求地址（或目录页）不一致时按失败处理
+            let expected = extract_book_id(pattern, &url).or_else(|| extract_book_id(pattern, &ctx.catalog_url));
+            let landed = extract_book_id(pattern, page_url.as_str());
+            if let (Some(expected), Some(landed)) = (expected, landed) && expected != landed {
+                let mut result = ChapterResult::failure(index, url.clone(), format!("Redirected to a different book: {} -> {}", expected, landed), fetch_start.elapsed().as_millis() as u64, completed_at);
+                result.http_status = last_status;
+                return result;
+            }
+        }
+        // 镜像站的章节按落地页所在域名的规则解析
+        let (extractor, content_next_sel, prefilter) = ctx.page_rules(page_url.as_str());
+        let html = match ctx.deadline(ctx.read_page(resp, &target, cached, prefilter)).await {
+            Ok(html) => html,
+            Err(e) => return ChapterResult::transient_failure(index, url, format!("Request failed: {}", e), fetch_start.elapsed().as_millis() as u64, completed_at),
+        };
+        match ctx.extract(extractor, &html, &page_url) {
+            PageOutcome::Chapter(title, mut paragraphs, mut notes) => {
+                if let Some(next_sel) = content_next_sel {
+                    // 依次抓取本章的后续分页，正文和作者注释分别接在前一页之后
+                    let mut visited = HashSet::from([page_url.to_string()]);
+                    let mut prev_url = page_url.clone();
+                    let mut next = content_next_page(&html, &page_url, next_sel);
+                    while let Some(next_url) = next.take() {
+                        if visited.len() >= MAX_CONTENT_PAGES || ctx.chapter_urls.contains(next_url.as_str()) || !visited.insert(next_url.to_string()) {
+                            break;
+                        }
+                        identity.throttle(next_url.as_str()).await;
+                        let cached = ctx.cached_page(next_url.as_str());
+                        let mut request = identity.get(next_url.as_str()).header("Referer", prev_url.as_str());
+                        if let Some(page) = &cached {
+                            request = ResponseCache::conditional(page, request);
+                        }
+                        let page_html = match ctx.deadline(async { request.send().await.map_err(request_error) }).await {
+                            Ok(resp) if resp.status().is_success() || resp.status() == reqwest::StatusCode::NOT_MODIFIED => {
+                                ctx.deadline(ctx.read_page(resp, next_url.as_str(), cached, prefilter)).await
+                            }
+                            Ok(resp) => {
+                                let mut result = ChapterResult::failure(index, url, format!("Content page {} returned HTTP {}", visited.len(), resp.status()), fetch_start.elapsed().as_millis() as u64, completed_at);
+                                result.http_status = Some(resp.status().as_u16());
+                                return result;
+                            }
+                            Err(e) => Err(e),
+                        };
+                        let page_html = match page_html {
+                            Ok(page_html) => page_html,
+                            Err(e) => return ChapterResult::transient_failure(index, url, format!("Content page {} failed: {}", visited.len(), e), fetch_start.elapsed().as_millis() as u64, completed_at),
+                        };
+                        match ctx.extract(extractor, &page_html, &next_url) {
+                            PageOutcome::Chapter(_, more_paragraphs, more_notes) => {
+                                paragraphs.extend(more_paragraphs);
+                                notes.extend(more_notes);
+                            }
+                            PageOutcome::Paywalled(marker) => {
+                                let mut result = ChapterResult::paywalled(index, url, &marker, fetch_start.elapsed().as_millis() as u64, completed_at);
+                                result.http_status = last_status;
+                                return result;
+                            }
+                            // 不是正文页，说明分页已经结束
+                            PageOutcome::Redirect(_) | PageOutcome::TitleMissing => break,
+                        }
+                        next = content_next_page(&page_html, &next_url, next_sel);
+                        prev_url = next_url;
+                    }
+                    if visited.len() > 1 {
+                        debug!("[{}] 正文共 {} 页", index + 1, visited.len());
+                    }
+                }
+                let duration_ms = fetch_start.elapsed().as_millis() as u64;
+                if let Some(human) = &ctx.human {
+                    browse_like_human(human, &identity, &page_url, &html).await;
+                }
+                let paragraph_count = paragraphs.iter().filter(|p| !p.trim().is_empty()).count();
+                let thin = paragraph_count < ctx.min_paragraphs.max(1);
+                if let Some(evidence) = find_soft_404(&html, Some(&title), thin, &ctx.soft_404_markers) {
+                    let mut result = ChapterResult::not_found(index, url, &evidence, duration_ms, completed_at);
+                    result.http_status = last_status;
+                    return result;
+                }
+                if !notes.is_empty() {
+                    paragraphs.push(ctx.note_separator.clone());
+                    paragraphs.extend(notes);
+                }
+                let mut result = if paragraph_count >= ctx.min_paragraphs {
+                    ChapterResult::success(index, title, url, paragraphs, duration_ms, completed_at)
+                } else if !ctx.accept_partial {
+                    ChapterResult::failure(index, url, format!("Content too short ({} paragraphs)", paragraph_count), duration_ms, completed_at)
+                } else {
+                    let mut result = ChapterResult::success(index, title, url, paragraphs, duration_ms, completed_at);
+                    result.partial = true;
+                    result
+                };
+                result.http_status = last_status;
+                return result;
+            }
+            PageOutcome::Paywalled(marker) => {
+                let mut result = ChapterResult::paywalled(index, url, &marker, fetch_start.elapsed().as_millis() as u64, completed_at);
+                result.http_status = last_status;
+                return result;
+            }
+            PageOutcome::Redirect(next) => {
+                debug!("[{}] 跟随页面内跳转: {}", index + 1, next);
+                target = next.to_string();
+            }
+            PageOutcome::TitleMissing => {
+                if let Some(evidence) = find_soft_404(&html, None, true, &ctx.soft_404_markers) {
+                    let mut result = ChapterResult::not_found(index, url, &evidence, fetch_start.elapsed().as_millis() as u64, completed_at);
+                    result.http_status = last_status;
+                    return result;
+                }
+                break;
+            }
+        }
+    }
+    let status = last_status.map(|code| format!(" (HTTP {})", code)).unwrap_or_default();
+    let mut result = ChapterResult::failure(index, url, format!("Chapter title not found{}", status), fetch_start.elapsed().as_millis() as u64, completed_at);
+    result.http_status = last_status;
+    result
+}
+
+/// 内容过短但仍被接受的章节，在输出中紧跟标题写入此标记
+const PARTIAL_MARKER: &str = "【本章内容可能不完整】";
+
+/// 判断段落是否只是重复了章节标题（忽略空白和标点，允许少量差异）
+fn is_duplicate_title(title: &str, paragraph: &str) -> bool {
+    let normalize = |s: &str| s.chars().filter(|c| c.is_alphanumeric()).collect::<Vec<_>>();
+    let title = normalize(title);
+    let para = normalize(paragraph);
+    if title.is_empty() || para.is_empty() {
+        return false;
+    }
+    let (shorter, longer) = if title.len() <= para.len() { (&title, &para) } else { (&para, &title) };
+    if shorter.len() * 5 < longer.len() * 4 {
+        return false;
+    }
+    longer.windows(shorter.len()).any(|window| window == shorter.as_slice())
+}
+
+/// 按配置选择章节页的解析方式：CSS 选择器，或配置了 json_state_pattern 时改用内嵌 JSON
+fn build_extractor(selectors: &SelectorsConfig, paywall_markers: &[String]) -> Result<Box<dyn Extract>, Box<dyn std::error::Error>> {
+    if selectors.json_state_pattern.is_empty() {
+        Ok(Box::new(SelectorExtractor {
+            title_sel: parse_optional_selector(&selectors.title_selector)?.ok_or("title_selector 不能为空")?,
+            content_sel: parse_optional_selector(&selectors.content_selector)?.ok_or("content_selector 不能为空")?,
+            note_sel: parse_optional_selector(&selectors.note_selector)?,
+            paywall_markers: paywall_markers.to_vec(),
+        }))
+    } else {
+        Ok(Box::new(JsonStateExtractor {
+            pattern: Regex::new(&selectors.json_state_pattern)?,
+            title_pointer: selectors.json_title_pointer.clone(),
+            content_pointer: selectors.json_content_pointer.clone(),
+            paywall_markers: paywall_markers.to_vec(),
+        }))
+    }
+}
+
+/// output.spill 开启（或配置了正文仓库）时的正文转存目录，固定为输出文件旁的 <file>.chapters，续抓时能找回上次转存的章节
+fn spill_store(config: &Config) -> Option<SpillStore> {
+    let blobs = (!config.output.store_dir.is_empty()).then(|| BlobStore::new(output_path(&config.output.store_dir)));
+    (config.output.spill || blobs.is_some()).then(|| SpillStore::new(output_path(&format!("{}.chapters", config.output.file)), blobs))
+}
+
+/// 目录页链接的常见文字，未配置 catalog_link_selector 时按此在书籍主页上查找
+static CATALOG_LINK_TEXT: LazyLock<Regex> = LazyLock::new(|| Regex::new("目录|章节列表|全部章节|章节目录|查看全部").unwrap());
+
+/// 从书籍主页找到目录页：按 catalog_link_selector 取第一个链接，未配置时找文字像"目录""全部章节"的链接；
+/// 都找不到但主页本身就有章节链接时，把主页当作目录页
+async fn discover_catalog_url(config: &Config, identities: &IdentityManager, link_sel: &scraper::Selector) -> Result<String, Box<dyn std::error::Error>> {
+    let book_url = &config.urls.book_url;
+    info!("从书籍主页查找目录页: {}", book_url);
+    let identity = identities.next();
+    identity.throttle(book_url).await;
+    let resp = identity.get(book_url).send().await?;
+    if !resp.status().is_success() {
+        return Err(format!("书籍主页返回 HTTP {}: {}", resp.status(), book_url).into());
+    }
+    let page_url = resp.url().clone();
+    let html = read_html(resp).await?;
+    let document = scraper::Html::parse_document(&html);
+    let href = match parse_optional_selector(&config.selectors.catalog_link_selector)? {
+        Some(sel) => document.select(&sel).find_map(|link| link.value().attr("href")),
+        None => {
+            let link_sel = scraper::Selector::parse("a[href]").unwrap();
+            document.select(&link_sel)
+                .filter(|link| link.text().collect::<String>().chars().count() <= 12)
+                .find(|link| CATALOG_LINK_TEXT.is_match(&link.text().collect::<String>()))
+                .and_then(|link| link.value().attr("href"))
+        }
+    };
+    let catalog_url = match href.and_then(|href| page_url.join(href).ok()) {
+        Some(url) => url,
+        None if document.select(link_sel).next().is_some() => {
+            info!("书籍主页上没有目录页链接，但已列出章节，直接作为目录页");
+            page_url
+        }
+        None => return Err(format!("书籍主页上没有找到目录页链接，请配置 selectors.catalog_link_selector 或直接指定 urls.catalog_url: {}", book_url).into()),
+    };
+    info!("找到目录页: {}", catalog_url);
+    Ok(catalog_url.to_string())
+}
+
+/// fetch 阶段：每章先后取域名和全局的并发许可再抓取，临时性失败按退避重试，退避期间归还许可
+struct ChapterFetcher {
+    ctx: Arc<FetchContext>,
+    semaphore: Arc<Semaphore>,
+    run: Arc<RunControl>,
+    progress: Option<ProgressDisplay>,
+}
+
+impl ConcurrentStage for ChapterFetcher {
+    type Input = ChapterJob;
+    type Output = ChapterResult;
+
+    /// 抓取被取消或停止时没有结果
+    async fn process(&self, job: ChapterJob) -> Option<ChapterResult> {
+        let ChapterJob { index, url, referer } = job;
+        let ctx = &self.ctx;
+        let queued_at = Instant::now();
+        // 先取域名的并发许可再取全局许可，排队等待某个镜像站时不占用全局并发
+        let mut host_permit = ctx.hosts.acquire(&url).await;
+        let mut permit = self.semaphore.acquire().await.unwrap();
+        let mut wait_ms = queued_at.elapsed().as_millis() as u64;
+        // 所有章节一开始就全部排队，暂停要在拿到许可、即将发请求时检查才能拦住后续章节
+        if !self.run.proceed().await {
+            return None;
+        }
+        let started_at = chrono::Utc::now();
+        self.run.emit(CrawlEvent::ChapterStarted { index, url: url.clone() });
+        let in_flight = self.progress.as_ref().map(ProgressDisplay::request);
+        let mut result = fetch_chapter(index, url.clone(), referer.clone(), ctx).await;
+        drop(in_flight);
+        for attempt in 0..ctx.max_retries {
+            if !result.transient {
+                break;
+            }
+            // 退避期间归还并发许可，让其他章节继续抓取
+            drop(permit);
+            drop(host_permit);
+            let delay = result.retry_after.unwrap_or_else(|| retry_delay(ctx.retry_backoff_ms, attempt));
+            debug!(
+                "[{}] {}，{}ms 后第 {} 次重试",
+                index + 1, result.error_msg.as_deref().unwrap_or_default(), delay.as_millis(), attempt + 1
+            );
+            // 退避期间收到停止信号不必等满
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.run.stop.cancelled() => return None,
+            }
+            let queued_at = Instant::now();
+            host_permit = ctx.hosts.acquire(&url).await;
+            permit = self.semaphore.acquire().await.unwrap();
+            wait_ms += queued_at.elapsed().as_millis() as u64;
+            if !self.run.proceed().await {
+                return None;
+            }
+            self.run.emit(CrawlEvent::ChapterStarted { index, url: url.clone() });
+            let in_flight = self.progress.as_ref().map(ProgressDisplay::request);
+            result = fetch_chapter(index, url.clone(), referer.clone(), ctx).await;
+            drop(in_flight);
+        }
+        drop(permit);
+        drop(host_permit);
+        result.wait_ms = wait_ms;
+        result.started_at = started_at;
+        result.completed_at = chrono::Utc::now();
+        Some(result)
+    }
+}
+
+/// 从目录页获取章节列表并并发抓取所有章节，返回 (抓取结果, 目录章节总数)
+async fn crawl_catalog(config: &Config, control: &ConcurrencyControl, identities: &Arc<IdentityManager>, run: &Arc<RunControl>) -> Result<(Vec<ChapterResult>, usize), Box<dyn std::error::Error>> {
+    let concurrent_limit = config.concurrent_limit();
+    let ramp_up_secs = config.crawl.ramp_up_secs;
+    let initial_permits = control.semaphore.available_permits();
+    let base_url = &config.urls.base_url;
+    let chapter_link_selector = &config.selectors.chapter_link_selector;
+
+    let catalog_start = Instant::now();
+    let link_sel = parse_optional_selector(chapter_link_selector)?.ok_or("chapter_link_selector 不能为空")?;
+    let discovered;
+    let catalog_url = if config.urls.book_url.is_empty() {
+        &config.urls.catalog_url
+    } else {
+        discovered = discover_catalog_url(config, identities, &link_sel).await?;
+        &discovered
+    };
+    info!("开始获取章节列表...");
+    let next_sel = parse_optional_selector(&config.selectors.catalog_next_page_selector)?;
+    let volume_sel = parse_optional_selector(&config.selectors.volume_selector)?;
+    let template = &config.urls.catalog_page_template;
+    let title_patterns = config.clean.catalog_title_patterns.iter()
+        .map(|pattern| Regex::new(pattern).map_err(|e| format!("clean.catalog_title_patterns 中的正则无效 {}: {}", pattern, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    // 目录中出现的所有章节链接（含重复）及其所属分卷
+    let mut links_found: Vec<CatalogLink> = Vec::new();
+    let mut current_volume: Option<String> = None;
+    let mut seen_urls = HashSet::new();
+    let mut visited_pages = HashSet::new();
+    let mut page_url = reqwest::Url::parse(catalog_url)?;
+    let robots = config.crawl.respect_robots_txt.then(|| identities.enable_robots());
+    for page in 1..=config.urls.catalog_max_pages.max(1) {
+        if !visited_pages.insert(page_url.clone()) {
+            break;
+        }
+        if !run.proceed().await {
+            info!("抓取已取消");
+            return Ok((Vec::new(), 0));
+        }
+        let identity = identities.next();
+        if let Some(robots) = &robots {
+            robots.load(&identity, &page_url).await;
+            if !robots.allows(page_url.as_str()) {
+                if page == 1 {
+                    return Err(format!("robots.txt 禁止抓取目录页 {}", page_url).into());
+                }
+                info!("robots.txt 禁止抓取目录第 {} 页，目录读取到此为止: {}", page, page_url);
+                break;
+            }
+        }
+        identity.throttle(page_url.as_str()).await;
+        let resp = identity.get(page_url.as_str()).send().await?;
+        // 第一页必须成功；按模板翻页时越过最后一页通常返回 404，视为目录结束
+        if page > 1 && !resp.status().is_success() {
+            break;
+        }
+        let final_url = resp.url().clone();
+        let catalog_html = read_html(resp).await?;
+        let (links, next) = parse_catalog_page(&catalog_html, &final_url, base_url, &config.urls.strip_query_params, &link_sel, volume_sel.as_ref(), next_sel.as_ref());
+        let before = seen_urls.len();
+        for mut link in links {
+            if link.volume.is_some() {
+                current_volume = link.volume;
+            }
+            link.volume = current_volume.clone();
+            link.text = clean_catalog_title(&link.text, &title_patterns);
+            seen_urls.insert(link.url.clone());
+            links_found.push(link);
+        }
+        if page > 1 {
+            debug!("目录第 {} 页: 新增 {} 章", page, seen_urls.len() - before);
+            if seen_urls.len() == before {
+                break;
+            }
+        }
+        page_url = match next {
+            Some(next) => next,
+            None if !template.is_empty() => reqwest::Url::parse(&template.replace("{page}", &(page + 1).to_string()))?,
+            None => break,
+        };
+    }
+    let catalog_duration = catalog_start.elapsed().as_millis();
+    run.catalog_ms.store(catalog_duration as u64, Ordering::Relaxed);
+    let duplicate_links = links_found.len() - seen_urls.len();
+    let (mut chapter_urls, catalog) = dedupe_catalog_links(links_found);
+    if duplicate_links > 0 {
+        info!("目录中有 {} 个重复的章节地址（如\"最新章节\"区块），保留完整列表中的那一次", duplicate_links);
+    }
+    if !config.urls.sort_key_pattern.is_empty() {
+        sort_chapter_urls(&mut chapter_urls, &Regex::new(&config.urls.sort_key_pattern)?);
+    }
+    let (start, end) = (config.crawl.start_chapter, config.crawl.end_chapter);
+    if start > 0 || end > 0 {
+        // 章节序号按目录中的位置计，在按 robots.txt 过滤之前截取
+        let catalog_total = chapter_urls.len();
+        let first = start.max(1);
+        let last = if end == 0 { catalog_total } else { end.min(catalog_total) };
+        if first > last {
+            return Err(format!("章节范围 {}..{} 超出目录（共 {} 章）", start, end, catalog_total).into());
+        }
+        chapter_urls.truncate(last);
+        chapter_urls.drain(..first - 1);
+        info!("按章节范围只抓取目录中的第 {} 至 {} 章（目录共 {} 章）", first, last, catalog_total);
+    }
+    if let Some(robots) = &robots {
+        // 章节可能分布在镜像站上，每个站点各读一次 robots.txt
+        let identity = identities.next();
+        for url in &chapter_urls {
+            if let Ok(url) = reqwest::Url::parse(url) {
+                robots.load(&identity, &url).await;
+            }
+        }
+        let before = chapter_urls.len();
+        chapter_urls.retain(|url| {
+            let allowed = robots.allows(url);
+            if !allowed {
+                debug!("robots.txt 禁止抓取，已跳过: {}", url);
+            }
+            allowed
+        });
+        if chapter_urls.len() < before {
+            info!("按 robots.txt 跳过 {} 章", before - chapter_urls.len());
+        }
+    }
+    let total_chapters = chapter_urls.len();
+    run.set_total(total_chapters);
+    info!("章节列表获取成功，共 {} 章 ({}ms)", total_chapters, catalog_duration);
+    if volume_sel.is_some() {
+        let volumes: Vec<&String> = chapter_urls.iter().filter_map(|url| catalog[url].volume.as_ref()).collect();
+        let volume_count = volumes.iter().collect::<HashSet<_>>().len();
+        info!("识别到 {} 个分卷，{} 章未归入任何分卷", volume_count, total_chapters - volumes.len());
+    }
+
+    // 重抓失败章节要在断点文件恢复出的章节基础上合并写出
+    let retry_urls = if config.crawl.retry_failures.is_empty() {
+        None
+    } else if config.crawl.state_file.is_empty() {
+        return Err("重新抓取失败章节需要断点文件与已抓到的章节合并，crawl.state_file 不能为空".into());
+    } else {
+        Some(load_failures(&config.crawl.retry_failures)?)
+    };
+    let resume = config.crawl.resume || retry_urls.is_some();
+    let mut checkpoint = (!config.crawl.state_file.is_empty()).then(|| {
+        Checkpoint::open(&config.crawl.state_file, catalog_url, resume)
+            .with_backoff(config.crawl.resume_backoff_secs, config.crawl.resume_backoff_max_secs)
+            .with_save_every(config.crawl.state_save_every)
+    });
+    let spill = spill_store(config);
+    if let Some(spill) = &spill {
+        spill.prepare(checkpoint.is_some() && resume)?;
+    }
+    let mut chapter_results = Vec::new();
+    if let Some(checkpoint) = checkpoint.as_mut() {
+        for (index, url) in chapter_urls.iter().enumerate() {
+            if let Some(mut result) = checkpoint.restore(index, url) {
+                // 断点里只记了元数据的章节，转存文件不在了就只能重新抓
+                if result.spilled && !spill.as_ref().is_some_and(|spill| spill.contains(index)) {
+                    continue;
+                }
+                catalog[url].annotate(&mut result);
+                run.record(&result);
+                chapter_results.push(result);
+            }
+        }
+        if !chapter_results.is_empty() {
+            info!("从断点文件恢复 {} 章，剩余 {} 章待抓取", chapter_results.len(), total_chapters - chapter_results.len());
+        }
+        // 反复失败的章节在退避期内不再请求，仍记为失败写出；断点中的失败次数保持不变
+        let restored: HashSet<usize> = chapter_results.iter().map(|r| r.index).collect();
+        let mut backing_off = 0;
+        // 站点明确表示不存在的章节（软 404）续抓时也不再请求，只有 --retry-failures 点名时才重抓
+        let mut not_found = 0;
+        for (index, url) in chapter_urls.iter().enumerate().filter(|(index, _)| !restored.contains(index)) {
+            let mut result = if let Some((failed_runs, retry_at)) = checkpoint.backing_off(index, url) {
+                backing_off += 1;
+                let error = format!("连续 {} 次抓取失败，{}，{} 后再重试", failed_runs, BACKING_OFF, format_time_rfc3339(retry_at));
+                ChapterResult::failure(index, url.clone(), error, 0, chrono::Utc::now())
+            } else if checkpoint.not_found(index, url) && !retry_urls.as_ref().is_some_and(|urls| urls.contains(url)) {
+                not_found += 1;
+                ChapterResult::not_found(index, url.clone(), "上次抓取时站点表示章节不存在，续抓时不再请求", 0, chrono::Utc::now())
+            } else {
+                continue;
+            };
+            catalog[url].annotate(&mut result);
+            run.record(&result);
+            chapter_results.push(result);
+        }
+        if backing_off > 0 {
+            info!("{} 章之前抓取失败，仍在退避期内，本次不重试", backing_off);
+        }
+        if not_found > 0 {
+            info!("{} 章上次为软 404（页面不存在），本次不重试，可用 --retry-failures 强制重抓", not_found);
+        }
+    }
+    let restored: HashSet<usize> = chapter_results.iter().map(|r| r.index).collect();
+    let scheduled: Vec<usize> = (0..total_chapters)
+        .filter(|i| !restored.contains(i))
+        .filter(|i| retry_urls.as_ref().is_none_or(|urls| urls.contains(&chapter_urls[*i])))
+        .collect();
+    if let Some(urls) = &retry_urls {
+        // 断点文件丢失时只会写出重抓的几章，覆盖掉原有的完整输出
+        if restored.is_empty() && scheduled.len() < total_chapters {
+            return Err(format!("断点文件 {} 中没有已抓到的章节，无法与重抓结果合并，请去掉 --retry-failures 重新完整抓取", config.crawl.state_file).into());
+        }
+        info!("按失败章节列表重新抓取 {} 章（列表共 {} 个地址）", scheduled.len(), urls.len());
+        let skipped = total_chapters - restored.len() - scheduled.len();
+        if skipped > 0 {
+            info!("{} 章既未抓到也不在失败章节列表中，本次不抓取", skipped);
+        }
+    }
+    if initial_permits < concurrent_limit {
+        info!("开始并发爬取（慢启动: {} -> {}，{}s 内完成）", initial_permits, concurrent_limit, ramp_up_secs);
+        control.spawn_ramp_up(initial_permits, concurrent_limit, ramp_up_secs);
+    } else {
+        info!("开始并发爬取（并发数: {}）", concurrent_limit);
+    }
+    if config.crawl.stdin_control {
+        info!("运行中输入数字并回车可调整并发数");
+        control.spawn_stdin_control();
+    }
+
+    let chapter_urls_arc = Arc::new(chapter_urls);
+    let fetch_ctx = Arc::new(FetchContext {
+        identities: identities.clone(),
+        extractor: build_extractor(&config.selectors, &config.crawl.paywall_markers)?,
+        note_separator: config.output.note_separator.clone(),
+        min_paragraphs: config.crawl.min_paragraphs,
+        accept_partial: config.crawl.accept_partial,
+        soft_404_markers: config.crawl.soft_404_markers.clone(),
+        catalog_url: catalog_url.to_string(),
+        human: config.human.enabled.then(|| config.human.clone()),
+        book_id_pattern: if config.urls.book_id_pattern.is_empty() { None } else { Some(Regex::new(&config.urls.book_id_pattern)?) },
+        max_retries: config.crawl.max_retries,
+        retry_backoff_ms: config.crawl.retry_backoff_ms,
+        content_next_sel: parse_optional_selector(&config.selectors.content_next_page_selector)?,
+        prefilter: build_prefilter(config, &config.selectors, &identities.hosts.prefilter_stats),
+        cache: (!config.http.cache_dir.is_empty()).then(|| ResponseCache::new(output_path(&config.http.cache_dir))),
+        request_timeout: config.http.request_timeout(),
+        parse_us: AtomicU64::new(0),
+        chapter_urls: chapter_urls_arc.iter().cloned().collect(),
+        hosts: identities.hosts.clone(),
+    });
+    if config.crawl.prefilter_html && fetch_ctx.prefilter.is_none() {
+        info!("选择器不是简单的 标签/#id/.类名 形式（或使用了内嵌 JSON 提取、拟人模式），章节页不做 HTML 预过滤");
+    }
+    let progress = if config.log.progress { ProgressDisplay::new(restored.len() + scheduled.len(), restored.len()) } else { None };
+    let jobs: Vec<ChapterJob> = scheduled.iter()
+        .map(|&index| ChapterJob {
+            index,
+            url: chapter_urls_arc[index].clone(),
+            referer: fetch_ctx.human.as_ref().map(|_| {
+                if index == 0 { catalog_url.to_string() } else { chapter_urls_arc[index - 1].clone() }
+            }),
+        })
+        .collect();
+    let fetcher = ChapterFetcher {
+        ctx: fetch_ctx.clone(),
+        semaphore: control.semaphore.clone(),
+        run: run.clone(),
+        progress: progress.clone(),
+    };
+    let mut fetched = Flow::from_iter(jobs).then_concurrent(fetcher);
+
+    let mut pending_count = scheduled.len();
+
+    info!("等待爬取结果...");
+    let mut waiting_time = 0;
+    let mut failure_streak = 0;
+    let abort_after = config.crawl.abort_after_consecutive_failures;
+    let mut ordered_log = config.log.ordered.then(|| OrderedLog::new(restored.clone()));
+    let mut stopping = false;
+    while pending_count > 0 {
+        let received = tokio::select! {
+            received = timeout(Duration::from_secs(30), fetched.recv()) => received,
+            _ = run.cancel.cancelled() => {
+                fetched.abort();
+                info!("抓取已取消，{} 章未抓取", pending_count);
+                break;
+            }
+            _ = run.stop.cancelled(), if !stopping => {
+                stopping = true;
+                info!("收到中断信号，不再发出新请求，等待进行中的请求完成（再按一次 Ctrl-C 立即退出）...");
+                continue;
+            }
+        };
+        match received {
+            Ok(Some(mut result)) => {
+                if let Some(link) = catalog.get(&result.url) {
+                    link.annotate(&mut result);
+                }
+                run.record(&result);
+                match ordered_log.as_mut() {
+                    Some(ordered_log) => ordered_log.push(result.index, result.log_entry()),
+                    None => result.log(),
+                }
+                if let Some(progress) = &progress {
+                    progress.chapter_done();
+                }
+                if let Some(spill) = &spill && let Err(e) = spill.stash(&mut result) {
+                    warn!("[{}] 正文转存失败，保留在内存中: {}", result.index + 1, e);
+                }
+                if let Some(checkpoint) = checkpoint.as_mut() {
+                    checkpoint.record(&result);
+                }
+                // 付费章节是站点的正常响应，不算失败，也不打断连续失败计数
+                if result.success {
+                    failure_streak = 0;
+                } else if !result.paywalled {
+                    failure_streak += 1;
+                }
+                chapter_results.push(result);
+                if abort_after > 0 && failure_streak >= abort_after {
+                    fetched.abort();
+                    if let Some(ordered_log) = ordered_log.as_mut() {
+                        ordered_log.flush();
+                    }
+                    if let Some(progress) = &progress {
+                        progress.finish();
+                    }
+                    let hint = match checkpoint.as_mut() {
+                        Some(checkpoint) => {
+                            checkpoint.save();
+                            format!("，进度已保存到 {}，恢复后可用 --resume 继续", config.crawl.state_file)
+                        }
+                        None => String::new(),
+                    };
+                    return Err(format!("连续 {} 章抓取失败，站点可能已宕机或封禁了本机 IP，中止抓取{}", failure_streak, hint).into());
+                }
+                pending_count -= 1;
+                waiting_time = 0;
+                if progress.is_none() && pending_count.is_multiple_of(100) && pending_count > 0 {
+                    info!("剩余 {} 章待处理...", pending_count);
+                }
+            }
+            Ok(None) if run.is_stopped() => {
+                info!("进行中的请求已完成，{} 章未抓取", pending_count);
+                break;
+            }
+            Ok(None) => {
+                info!("通道已关闭，但还有 {} 章未完成", pending_count);
+                break;
+            }
+            // 暂停期间收不到结果是正常的，不计入等待时间
+            Err(_) if run.is_paused() => {}
+            Err(_) => {
+                waiting_time += 30;
+                info!("等待超时 ({}s)，剩余 {} 章...", waiting_time, pending_count);
+                if waiting_time > 300 {
+                    info!("等待时间过长，放弃等待未完成的章节");
+                    break;
+                }
+            }
+        }
+    }
+    if let Some(ordered_log) = ordered_log.as_mut() {
+        ordered_log.flush();
+    }
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    if let Some(checkpoint) = checkpoint.as_mut() {
+        checkpoint.save();
+    }
+    if let Some((written, reused)) = spill.as_ref().and_then(SpillStore::blob_stats) {
+        info!("正文仓库: 新写入 {} 章，{} 章与已有内容相同未重复保存", written, reused);
+    }
+    run.parse_ms.store(fetch_ctx.parse_us.load(Ordering::Relaxed) / 1000, Ordering::Relaxed);
+    if let Some(cache) = &fetch_ctx.cache {
+        let (revalidated, stored) = cache.stats();
+        info!("响应缓存: {} 个页面未变化、使用缓存，{} 个页面写入缓存", revalidated, stored);
+    }
+    if let Some((received, kept)) = identities.hosts.prefilter_stats.totals() {
+        info!(
+            "HTML 预过滤: 章节页共下载 {:.1} MB，交给解析的 {:.1} MB ({:.0}%)",
+            received as f64 / 1048576.0, kept as f64 / 1048576.0, kept as f64 * 100.0 / received as f64
+        );
+    }
+    if !run.is_stopped() {
+        info!("所有结果已接收 (共 {} 章)，开始写入文件...", chapter_results.len());
+    }
+    Ok((chapter_results, total_chapters))
+}
+
+/// 按章节顺序输出完成日志：先完成的后续章节暂存，前面的章节都完成后再依次输出
+struct OrderedLog {
+    next: usize,
+    pending: BTreeMap<usize, ChapterLog>,
+    /// 从断点恢复、不会产生日志的章节
+    skipped: HashSet<usize>,
+}
+
+impl OrderedLog {
+    fn new(skipped: HashSet<usize>) -> Self {
+        OrderedLog { next: 0, pending: BTreeMap::new(), skipped }
+    }
+
+    fn push(&mut self, index: usize, entry: ChapterLog) {
+        self.pending.insert(index, entry);
+        loop {
+            if self.skipped.contains(&self.next) {
+                self.next += 1;
+            } else if let Some(entry) = self.pending.remove(&self.next) {
+                entry.emit();
+                self.next += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// 抓取结束或中止时输出剩余的日志，缺失的章节不再等待
+    fn flush(&mut self) {
+        for entry in std::mem::take(&mut self.pending).into_values() {
+            entry.emit();
+        }
+    }
+}
+
+/// 同一章节地址在目录中出现多次时（如"最新章节"区块，分页目录每页都重复）只保留一次：
+/// 按地址中最后一段数字划分连续递增段，保留位于最长递增段（通常是完整列表）中的那一次，
+/// 倒序的"最新章节"区块无论在列表前后都不会打乱顺序；一样长或地址中没有数字时保留第一次。
+/// 返回去重后的地址和 章节地址 -> 保留的那一次链接（分卷、链接文字），按地址而非位置记录，sort_key_pattern 重排后仍然对应
+fn dedupe_catalog_links(links: Vec<CatalogLink>) -> (Vec<String>, HashMap<String, CatalogLink>) {
+    let keys: Vec<Option<u64>> = links.iter().map(|link| last_number(&link.url)).collect();
+    let mut run_start = vec![0; links.len()];
+    for i in 1..links.len() {
+        let ascending = matches!((keys[i - 1], keys[i]), (Some(prev), Some(next)) if next > prev);
+        run_start[i] = if ascending { run_start[i - 1] } else { i };
+    }
+    let mut run_len = vec![0; links.len()];
+    for i in (0..links.len()).rev() {
+        run_len[i] = if i + 1 < links.len() && run_start[i + 1] == run_start[i] { run_len[i + 1] } else { i - run_start[i] + 1 };
+    }
+    // 地址 -> 保留的位置
+    let mut kept: HashMap<&str, usize> = HashMap::new();
+    for (i, link) in links.iter().enumerate() {
+        let best = kept.entry(&link.url).or_insert(i);
+        if run_len[i] > run_len[*best] {
+            *best = i;
+        }
+    }
+    let keep: HashSet<usize> = kept.into_values().collect();
+    let mut catalog = HashMap::new();
+    let urls = links.into_iter()
+        .enumerate()
+        .filter(|(i, _)| keep.contains(i))
+        .map(|(_, link)| {
+            let url = link.url.clone();
+            catalog.insert(url.clone(), link);
+            url
+        })
+        .collect();
+    (urls, catalog)
+}
+
+/// 清理目录页链接文字：删去 patterns 匹配的片段（"最新："前缀、日期、阅读数等），合并多余的空白
+fn clean_catalog_title(text: &str, patterns: &[Regex]) -> String {
+    let mut title = Cow::Borrowed(text);
+    for pattern in patterns {
+        if let Cow::Owned(replaced) = pattern.replace_all(&title, "") {
+            title = Cow::Owned(replaced);
+        }
+    }
+    title.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// 地址中最后一段数字，如 /book/12/3456.html 中的 3456
+fn last_number(url: &str) -> Option<u64> {
+    let end = url.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = url[..end].rfind(|c: char| !c.is_ascii_digit()).map_or(0, |i| i + 1);
+    url[start..end].parse().ok()
+}
+
+/// 去掉地址中的跟踪参数（如 utm_*、from），同一章节不会因参数不同被当成多个章节；
+/// 没有参数被去掉时原样返回，不重新编码其余参数
+fn strip_query_params(url: &str, patterns: &[String]) -> String {
+    let matches = |name: &str| patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    });
+    let Ok(mut parsed) = reqwest::Url::parse(url) else {
+        return url.to_string();
+    };
+    if patterns.is_empty() || !parsed.query_pairs().any(|(name, _)| matches(&name)) {
+        return url.to_string();
+    }
+    let kept: Vec<(String, String)> = parsed.query_pairs()
+        .filter(|(name, _)| !matches(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if kept.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(kept);
+    }
+    parsed.to_string()
+}
+
+/// 按地址中提取出的序号重排章节，目录页顺序有误但地址里的 ID 连续递增时使用；
+/// 提取不到序号的地址保持原有相对顺序排在最后
+fn sort_chapter_urls(urls: &mut [String], pattern: &Regex) {
+    let key = |url: &str| pattern.captures(url)
+        .and_then(|caps| caps.get(1))
+        .and_then(|m| m.as_str().parse::<u64>().ok());
+    let unmatched = urls.iter().filter(|url| key(url).is_none()).count();
+    urls.sort_by_cached_key(|url| key(url).map_or((1, 0), |n| (0, n)));
+    if unmatched > 0 {
+        info!("{} 个章节地址未匹配 sort_key_pattern，排在最后", unmatched);
+    }
+}
+
+/// 目录页中的一个章节链接
+#[derive(Clone)]
+struct CatalogLink {
+    url: String,
+    /// 所属分卷；解析单页时只有本页出现过分卷标题才有，由调用方沿用上一页的分卷
+    volume: Option<String>,
+    /// 链接文字
+    text: String,
+}
+
+impl CatalogLink {
+    /// 把分卷和目录中的章节名记到该章的结果上
+    fn annotate(&self, result: &mut ChapterResult) {
+        result.volume = self.volume.clone();
+        result.catalog_title = Some(self.text.clone()).filter(|text| !text.is_empty());
+    }
+}
+
+/// 解析一页目录，返回章节链接和下一页地址；相对链接拼接在 base_url 之后
+fn parse_catalog_page(
+    html: &str,
+    page_url: &reqwest::Url,
+    base_url: &str,
+    strip_params: &[String],
+    link_sel: &scraper::Selector,
+    volume_sel: Option<&scraper::Selector>,
+    next_sel: Option<&scraper::Selector>,
+) -> (Vec<CatalogLink>, Option<reqwest::Url>) {
+    let document = scraper::Html::parse_document(html);
+    // 按文档顺序同时匹配分卷标题和章节链接，链接归入它前面最近的分卷；
+    // 本页出现第一个分卷标题之前的链接分卷为空，由调用方沿用上一页的分卷
+    let mut volume = None;
+    let mut links = Vec::new();
+    for elem in document.root_element().descendants().filter_map(scraper::ElementRef::wrap) {
+        if let Some(volume_sel) = volume_sel && volume_sel.matches(&elem) {
+            let name = elem.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ");
+            if !name.is_empty() {
+                volume = Some(name);
+            }
+            continue;
+        }
+        if !link_sel.matches(&elem) {
+            continue;
+        }
+        let Some(href) = elem.value().attr("href") else {
+            continue;
+        };
+        let url = if href.starts_with("http") {
+            href.to_string()
+        } else {
+            format!("{}{}", base_url, href.trim_start_matches('/'))
+        };
+        let text = elem.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ");
+        links.push(CatalogLink { url: strip_query_params(&url, strip_params), volume: volume.clone(), text });
+    }
+    let next = next_sel
+        .and_then(|sel| document.select(sel).next())
+        .and_then(|a| a.value().attr("href"))
+        .and_then(|href| page_url.join(href).ok());
+    (links, next)
+}
+
+/// 论坛帖子中的一条回帖
+struct ForumPost {
+    author: String,
+    title: Option<String>,
+    paragraphs: Vec<String>,
+}
+
+fn parse_optional_selector(selector: &str) -> Result<Option<scraper::Selector>, Box<dyn std::error::Error>> {
+    if selector.is_empty() {
+        return Ok(None);
+    }
+    // xpath: 开头的选择器先转换为等价的 CSS 选择器
+    let css = match selector.strip_prefix(xpath::XPATH_PREFIX) {
+        Some(path) => Cow::Owned(xpath::to_css(path.trim())?),
+        None => Cow::Borrowed(selector),
+    };
+    scraper::Selector::parse(&css)
+        .map(Some)
+        .map_err(|e| format!("无效的选择器 {}: {}", selector, e).into())
+}
+
+/// 解析一页帖子，返回该页所有回帖和下一页地址
+fn parse_forum_page(
+    html: &str,
+    page_url: &reqwest::Url,
+    post_sel: &scraper::Selector,
+    author_sel: &scraper::Selector,
+    title_sel: Option<&scraper::Selector>,
+    content_sel: &scraper::Selector,
+    next_sel: Option<&scraper::Selector>,
+) -> (Vec<ForumPost>, Option<reqwest::Url>) {
+    let document = scraper::Html::parse_document(html);
+    let element_text = |elem: scraper::ElementRef| elem.text().collect::<String>().trim().to_string();
+    let posts = document.select(post_sel)
+        .map(|post| ForumPost {
+            author: post.select(author_sel).next().map(element_text).unwrap_or_default(),
+            title: title_sel.and_then(|sel| post.select(sel).next()).map(element_text).filter(|t| !t.is_empty()),
+            paragraphs: post.select(content_sel)
+                .flat_map(|content| content.text())
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string())
+                .collect(),
+        })
+        .collect();
+    let next = next_sel
+        .and_then(|sel| document.select(sel).next())
+        .and_then(|a| a.value().attr("href"))
+        .and_then(|href| page_url.join(href).ok());
+    (posts, next)
+}
+
+/// 论坛连载模式：沿分页逐页读取帖子，把作者本人的回帖依次作为章节，返回 (章节结果, 章节总数)。
+/// 未配置 author 时以首帖作者为准
+async fn crawl_forum_thread(config: &Config, identities: &IdentityManager, run: &RunControl) -> Result<(Vec<ChapterResult>, usize), Box<dyn std::error::Error>> {
+    let forum = &config.forum;
+    let post_sel = parse_optional_selector(&forum.post_selector)?.ok_or("论坛模式需要配置 forum.post_selector")?;
+    let author_sel = parse_optional_selector(&forum.author_selector)?.ok_or("论坛模式需要配置 forum.author_selector")?;
+    let content_sel = parse_optional_selector(&forum.post_content_selector)?.ok_or("论坛模式需要配置 forum.post_content_selector")?;
+    let title_sel = parse_optional_selector(&forum.post_title_selector)?;
+    let next_sel = parse_optional_selector(&forum.next_page_selector)?;
+
+    let mut author = if forum.author.is_empty() { None } else { Some(forum.author.clone()) };
+    let mut page_url = reqwest::Url::parse(&config.urls.catalog_url)?;
+    let mut visited = HashSet::new();
+    let mut results = Vec::new();
+    let robots = config.crawl.respect_robots_txt.then(|| identities.enable_robots());
+
+    info!("论坛模式：开始逐页读取帖子...");
+    for page in 1..=forum.max_pages.max(1) {
+        if !visited.insert(page_url.clone()) {
+            break;
+        }
+        if !run.proceed().await {
+            info!("抓取已取消");
+            break;
+        }
+        let fetch_start = Instant::now();
+        let identity = identities.next();
+        if let Some(robots) = &robots {
+            robots.load(&identity, &page_url).await;
+            if !robots.allows(page_url.as_str()) {
+                info!("robots.txt 禁止抓取第{}页，停止翻页: {}", page, page_url);
+                break;
+            }
+        }
+        identity.throttle(page_url.as_str()).await;
+        let resp = identity.get(page_url.as_str()).send().await?;
+        let final_url = resp.url().clone();
+        let html = read_html(resp).await?;
+        let (posts, next) = parse_forum_page(&html, &final_url, &post_sel, &author_sel, title_sel.as_ref(), &content_sel, next_sel.as_ref());
+        let author_name = author.get_or_insert_with(|| posts.first().map(|p| p.author.clone()).unwrap_or_default()).clone();
+
+        let before = results.len();
+        for post in posts.into_iter().filter(|p| p.author == author_name && !p.paragraphs.is_empty()) {
+            let index = results.len();
+            let title = post.title.unwrap_or_else(|| format!("第{}章", index + 1));
+            let result = ChapterResult::success(index, title, final_url.to_string(), post.paragraphs, fetch_start.elapsed().as_millis() as u64, chrono::Utc::now());
+            result.log();
+            run.record(&result);
+            results.push(result);
+        }
+        run.set_total(results.len());
+        debug!("第{}页: 作者 {} 的帖子 {} 条 ({}ms)", page, author_name, results.len() - before, fetch_start.elapsed().as_millis());
+
+        match next {
+            Some(next) => page_url = next,
+            None => break,
+        }
+    }
+    let total = results.len();
+    info!("论坛帖子读取完成，共 {} 章", total);
+    Ok((results, total))
+}
+
+/// 嵌入方对一次抓取的控制：暂停开关、取消令牌和进度计数，所有抓取任务共享
+struct RunControl {
+    paused: tokio::sync::watch::Sender<bool>,
+    cancel: CancellationToken,
+    /// 停止发出新请求，进行中的请求照常完成；取消时同样触发
+    stop: CancellationToken,
+    total: AtomicUsize,
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+    paywalled: AtomicUsize,
+    /// 各阶段耗时，抓取结束后写入汇总
+    catalog_ms: AtomicU64,
+    parse_ms: AtomicU64,
+    /// 抓取事件，没有订阅者时直接丢弃
+    events: tokio::sync::broadcast::Sender<CrawlEvent>,
+}
+
+/// 订阅者来不及接收时最多积压的事件数，更早的事件被丢弃，订阅者收到 Lagged
+const EVENT_CAPACITY: usize = 256;
+
+impl RunControl {
+    fn new() -> Self {
+        let cancel = CancellationToken::new();
+        RunControl {
+            events: tokio::sync::broadcast::Sender::new(EVENT_CAPACITY),
+            paused: tokio::sync::watch::Sender::new(false),
+            stop: cancel.child_token(),
+            cancel,
+            total: AtomicUsize::new(0),
+            succeeded: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            paywalled: AtomicUsize::new(0),
+            catalog_ms: AtomicU64::new(0),
+            parse_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// 发起请求前调用：暂停时等到继续或停止，返回 false 表示已停止或取消、不应再发请求
+    async fn proceed(&self) -> bool {
+        let mut paused = self.paused.subscribe();
+        tokio::select! {
+            _ = paused.wait_for(|paused| !paused) => !self.stop.is_cancelled(),
+            _ = self.stop.cancelled() => false,
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop.is_cancelled()
+    }
+
+    fn emit(&self, event: CrawlEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// 章节总数已知（论坛模式每读完一页更新一次）
+    fn set_total(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+        self.emit(CrawlEvent::Progress(self.progress()));
+    }
+
+    /// 一章有了结果（含从断点恢复的章节）
+    fn record(&self, result: &ChapterResult) {
+        let counter = if result.success {
+            &self.succeeded
+        } else if result.paywalled {
+            &self.paywalled
+        } else {
+            &self.failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.emit(if result.success || result.paywalled {
+            CrawlEvent::ChapterFinished { index: result.index, title: result.title.clone(), paywalled: result.paywalled }
+        } else {
+            CrawlEvent::ChapterFailed {
+                index: result.index,
+                url: result.url.clone(),
+                error: result.error_msg.clone().unwrap_or_default(),
+            }
+        });
+        self.emit(CrawlEvent::Progress(self.progress()));
+    }
+
+    fn progress(&self) -> CrawlProgress {
+        CrawlProgress {
+            total: self.total.load(Ordering::Relaxed),
+            succeeded: self.succeeded.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            paywalled: self.paywalled.load(Ordering::Relaxed),
+            paused: self.is_paused(),
+            cancelled: self.cancel.is_cancelled(),
+        }
+    }
+}
+
+/// 抓取过程中的事件，供嵌入方自行显示进度或统计，不必解析日志。章节序号从0开始，同 [`ChapterResult::index`]
+#[derive(Debug, Clone)]
+pub enum CrawlEvent {
+    /// 即将请求章节页，重试时每次都会发出
+    ChapterStarted { index: usize, url: String },
+    /// 章节抓取成功，或站点表示为付费章节
+    ChapterFinished { index: usize, title: String, paywalled: bool },
+    ChapterFailed { index: usize, url: String, error: String },
+    /// 章节总数或计数有变化后的进度快照
+    Progress(CrawlProgress),
+}
+
+/// 抓取进度快照
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrawlProgress {
+    /// 目录中的章节总数，目录解析完成前为 0；论坛模式随翻页增长
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub paywalled: usize,
+    pub paused: bool,
+    pub cancelled: bool,
+}
+
+impl CrawlProgress {
+    pub fn completed(&self) -> usize {
+        self.succeeded + self.failed + self.paywalled
+    }
+}
+
+/// [`Crawler::start`] 在后台开始的一次抓取，供图形界面等嵌入方在不发送进程信号的情况下控制抓取
+pub struct CrawlHandle {
+    control: Arc<RunControl>,
+    task: tokio::task::JoinHandle<Result<Vec<ChapterResult>, String>>,
+}
+
+impl CrawlHandle {
+    /// 暂停：已发出的请求照常完成，之后的章节（包括重试）等到继续后再抓
+    pub fn pause(&self) {
+        self.control.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.control.paused.send_replace(false);
+    }
+
+    /// 取消：不再发出新请求，进行中的请求被中止，[`CrawlHandle::join`] 返回已抓到的章节
+    pub fn cancel(&self) {
+        self.control.cancel.cancel();
+    }
+
+    /// 停止：不再发出新请求，进行中的请求照常完成，[`CrawlHandle::join`] 返回已抓到的章节
+    pub fn stop(&self) {
+        self.control.stop.cancel();
+    }
+
+    pub fn progress(&self) -> CrawlProgress {
+        self.control.progress()
+    }
+
+    /// 订阅之后发生的抓取事件；要从头接收所有事件，在 [`Crawler::start`] 之前调用 [`Crawler::subscribe`]
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<CrawlEvent> {
+        self.control.events.subscribe()
+    }
+
+    /// 等待抓取结束，结果同 [`Crawler::run`]
+    pub async fn join(self) -> Result<Vec<ChapterResult>, Box<dyn std::error::Error>> {
+        match self.task.await {
+            Ok(result) => result.map_err(Into::into),
+            Err(e) => Err(format!("抓取任务异常退出: {}", e).into()),
+        }
+    }
+}
+
+/// 以代码方式配置一次抓取，未设置的项取与配置文件相同的默认值
+#[derive(Default)]
+pub struct CrawlerBuilder {
+    config: Config,
+    client: Option<reqwest::Client>,
+}
+
+impl CrawlerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 以完整配置为起点，之后调用的各设置项覆盖其中对应的值
+    pub fn from_config(config: Config) -> Self {
+        CrawlerBuilder { config, client: None }
+    }
+
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.config.urls.base_url = url.into();
+        self
+    }
+
+    pub fn catalog_url(mut self, url: impl Into<String>) -> Self {
+        self.config.urls.catalog_url = url.into();
+        self
+    }
+
+    /// 只给出书籍主页，抓取时从中找到目录页
+    pub fn book_url(mut self, url: impl Into<String>) -> Self {
+        self.config.urls.book_url = url.into();
+        self
+    }
+
+    pub fn title_selector(mut self, selector: impl Into<String>) -> Self {
+        self.config.selectors.title_selector = selector.into();
+        self
+    }
+
+    pub fn content_selector(mut self, selector: impl Into<String>) -> Self {
+        self.config.selectors.content_selector = selector.into();
+        self
+    }
+
+    pub fn chapter_link_selector(mut self, selector: impl Into<String>) -> Self {
+        self.config.selectors.chapter_link_selector = selector.into();
+        self
+    }
+
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        self.config.crawl.concurrent_limit = limit;
+        self
+    }
+
+    /// 所有请求改用调用方提供的客户端发出，Cookie、代理、超时等由该客户端负责，
+    /// [identity] 的身份模式与 [http] 的代理池、预置 Cookie 以及 [auth] 的预置 Cookie 不再生效（登录表单仍经该客户端提交）
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn build(self) -> Result<Crawler, Box<dyn std::error::Error>> {
+        let CrawlerBuilder { config, client } = self;
+        let concurrent_limit = config.concurrent_limit();
+        let initial_permits = if config.crawl.ramp_up_secs > 0 {
+            config.crawl.ramp_up_initial.clamp(1, concurrent_limit.max(1))
+        } else {
+            concurrent_limit
+        };
+        let mut identities = IdentityManager::new(
+            &config.identity,
+            &config.http,
+            RequestSigner::new(&config.signing)?,
+            RateLimiter::new(config.crawl.min_delay_ms, config.crawl.max_delay_ms),
+            HostOverrides::new(&config)?,
+            auth::session_jar(&config.auth, &config.http, config.urls.entry_url())?,
+        )?;
+        if let Some(client) = client {
+            identities = identities.with_client(client);
+        }
+        Ok(Crawler {
+            control: ConcurrencyControl::new(initial_permits),
+            identities: Arc::new(identities),
+            run_control: Arc::new(RunControl::new()),
+            config,
+        })
+    }
+}
+
+/// 配置好的一次抓取
+pub struct Crawler {
+    config: Config,
+    control: ConcurrencyControl,
+    identities: Arc<IdentityManager>,
+    run_control: Arc<RunControl>,
+}
+
+impl Crawler {
+    pub fn builder() -> CrawlerBuilder {
+        CrawlerBuilder::new()
+    }
+
+    /// 抓取目录（或论坛帖子）中的全部章节，返回按章节顺序排列的结果，失败和付费章节也包含在内
+    pub async fn run(&self) -> Result<Vec<ChapterResult>, Box<dyn std::error::Error>> {
+        let mut results = self.crawl().await?.0;
+        // 调用方拿到的结果总是带正文
+        if let Some(spill) = spill_store(&self.config) {
+            for result in &mut results {
+                spill.load(result)?;
+            }
+        }
+        Ok(results)
+    }
+
+    /// 配置了 login_url 时先登录，登录后的会话 Cookie 由所有身份共用
+    async fn login(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.config.auth.login_url.is_empty() {
+            return Ok(());
+        }
+        auth::login(&self.config.auth, &self.identities.next()).await
+    }
+
+    /// 订阅抓取事件，在 [`Crawler::run`] 或 [`Crawler::start`] 之前调用可以收到全部事件
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<CrawlEvent> {
+        self.run_control.events.subscribe()
+    }
+
+    /// 在后台开始抓取，立即返回可暂停、继续、取消、查询进度和订阅事件的句柄
+    pub fn start(self) -> CrawlHandle {
+        let control = self.run_control.clone();
+        let task = tokio::spawn(async move { self.run().await.map_err(|e| e.to_string()) });
+        CrawlHandle { control, task }
+    }
+
+    /// 同 [`Crawler::run`]，另外返回目录中的章节总数
+    async fn crawl(&self) -> Result<(Vec<ChapterResult>, usize), Box<dyn std::error::Error>> {
+        let config = &self.config;
+        self.login().await?;
+        let (mut chapter_results, total_chapters) = if config.forum.enabled {
+            if config.crawl.resume || !config.crawl.retry_failures.is_empty() {
+                info!("论坛模式按页顺序抓取，不支持断点续抓和重抓失败章节，将从头开始");
+            }
+            crawl_forum_thread(config, &self.identities, &self.run_control).await?
+        } else {
+            crawl_catalog(config, &self.control, &self.identities, &self.run_control).await?
+        };
+        chapter_results.sort_by_key(|r| r.index);
+        Ok((chapter_results, total_chapters))
+    }
+}
+
+/// record-fixture 子命令：按配置中的身份、限速和解析规则抓取单个页面并保存为测试夹具
+pub async fn record_fixture(config: Config, url: &str, name: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let crawler = CrawlerBuilder::from_config(config).build()?;
+    crawler.login().await?;
+    let identity = crawler.identities.next();
+    fixture::record(&crawler.config, &identity, url, name).await
+}
+
+/// 命令行入口：运行配置向导。请求沿用当前配置中的请求头、代理等设置
+pub async fn init(config: Config, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let crawler = CrawlerBuilder::from_config(config).build()?;
+    let identity = crawler.identities.next();
+    init::wizard(&identity, path).await
+}
+
+/// Ctrl-C 时停止发出新请求，等进行中的请求完成后把已抓到的章节写出到临时文件并保存断点。
+/// 再按一次是否立即退出由调用方决定（命令行会退出进程）。返回值丢弃时撤销处理，批量模式下不会作用到后面的书
+fn spawn_interrupt_handler(control: Arc<RunControl>) -> InterruptHandler {
+    InterruptHandler(tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            control.stop.cancel();
+        }
+    }))
+}
+
+struct InterruptHandler(tokio::task::JoinHandle<()>);
+
+impl Drop for InterruptHandler {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// 一本书抓取结束后的概况，决定运行结局，批量模式据此打印汇总表
+struct BookOutcome {
+    total_chapters: usize,
+    success: usize,
+    failed: usize,
+    paywalled: usize,
+    duration: Duration,
+    quality_passed: bool,
+    interrupted: bool,
+}
+
+/// 一次运行的结局，命令行据此决定退出码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    Completed,
+    /// 未通过质量检查，输出保留为 .part 文件
+    QualityRejected,
+    /// 被 Ctrl-C 中断，已抓到的章节写出到 .part 临时文件
+    Interrupted,
+}
+
+/// 命令行入口：抓取、清洗、写出并打印汇总。配置了 [[books]] 时按批量模式抓取每一本书
+pub async fn run(config: Config) -> Result<RunStatus, Box<dyn std::error::Error>> {
+    if !config.books.is_empty() {
+        return run_batch(config).await;
+    }
+    let outcome = run_book(config).await?;
+    Ok(if !outcome.quality_passed {
+        RunStatus::QualityRejected
+    } else if outcome.interrupted {
+        RunStatus::Interrupted
+    } else {
+        RunStatus::Completed
+    })
+}
+
+/// 批量模式：按 batch.parallel_books 同时抓取若干本书，一本书出错不影响其他书，最后打印每本书的汇总表。
+/// 被 Ctrl-C 中断后不再开始新的书。有书被中断时结局为中断，否则有书未通过质量检查时为未通过，其次才报告出错的书
+async fn run_batch(mut config: Config) -> Result<RunStatus, Box<dyn std::error::Error>> {
+    use futures::StreamExt;
+
+    let mut books = std::mem::take(&mut config.books);
+    let total = books.len();
+    let parallel = config.batch.parallel_books.clamp(1, total);
+    if parallel > 1 {
+        // 多本书的进度条会互相覆盖，改为逐行日志
+        for book in &mut books {
+            book.log.progress = false;
+        }
+    }
+    let labels: Vec<String> = books.iter().map(|book| book.output.file.clone()).collect();
+    let output_dir = std::mem::take(&mut config.batch.output_dir);
+    let entries: Vec<library::LibraryEntry> = books.iter()
+        .map(|book| library::LibraryEntry {
+            title: book_title(book),
+            author: book.output.author.clone(),
+            dir: library::dir_name(&book_title(book)),
+            source: book.urls.entry_url().to_string(),
+            tags: book.output.tags.clone(),
+            chapters: 0,
+            success: 0,
+            updated_at: String::new(),
+            formats: Vec::new(),
+            stem: std::path::Path::new(&book.output.file).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+        })
+        .collect();
+    let units = UnitFormat::for_language(&config.output.language);
+    let interrupted = AtomicBool::new(false);
+    let mut outcomes: Vec<(usize, Option<Result<BookOutcome, String>>)> = futures::stream::iter(books.into_iter().enumerate())
+        .map(|(i, book)| {
+            let (interrupted, labels) = (&interrupted, &labels);
+            async move {
+                if interrupted.load(Ordering::Relaxed) {
+                    return (i, None);
+                }
+                info!("===== 第 {}/{} 本: {} <- {} =====", i + 1, total, book.output.file, book.urls.entry_url());
+                if parallel == 1 {
+                    // 逐本抓取时连接统计只算这本书；同时抓取时各书共用，汇总中为所有书合计
+                    NET_STATS.lock().unwrap().clear();
+                }
+                let outcome = run_book(book).await.map_err(|e| e.to_string());
+                match &outcome {
+                    Ok(outcome) if outcome.interrupted => interrupted.store(true, Ordering::Relaxed),
+                    Err(e) => warn!("第 {} 本 {} 抓取出错: {}", i + 1, labels[i], e),
+                    _ => {}
+                }
+                (i, Some(outcome))
+            }
+        })
+        .buffer_unordered(parallel)
+        .collect()
+        .await;
+    outcomes.sort_by_key(|(i, _)| *i);
+    if !output_dir.is_empty() {
+        // 只记录本次写出了最终文件的书
+        let now = format_time_rfc3339(chrono::Utc::now());
+        let published: Vec<library::LibraryEntry> = entries.into_iter()
+            .zip(&outcomes)
+            .filter_map(|(entry, (_, outcome))| match outcome {
+                Some(Ok(outcome)) if outcome.quality_passed && !outcome.interrupted => Some(library::LibraryEntry {
+                    chapters: outcome.total_chapters,
+                    success: outcome.success,
+                    updated_at: now.clone(),
+                    ..entry
+                }),
+                _ => None,
+            })
+            .collect();
+        let count = published.len();
+        if count > 0 {
+            let index_path = std::path::Path::new(&output_dir).join(library::INDEX_FILE);
+            match library::update(std::path::Path::new(&output_dir), published) {
+                Ok(()) => info!("书库索引已更新: {} ({} 本)", index_path.display(), count),
+                Err(e) => warn!("书库索引更新失败: {}", e),
+            }
+        }
+    }
+
+    info!("=========================================");
+    info!("批量抓取汇总:");
+    let (mut errors, mut rejected) = (0, 0);
+    for (i, outcome) in &outcomes {
+        let line = match outcome {
+            Some(Ok(outcome)) => {
+                let result = if !outcome.quality_passed {
+                    rejected += 1;
+                    "未通过质量检查"
+                } else if outcome.interrupted {
+                    "已中断"
+                } else {
+                    "完成"
+                };
+                format!(
+                    "总章节 {} | 成功 {} | 失败 {} | 付费 {} | 耗时 {} | {}",
+                    units.count(outcome.total_chapters), units.count(outcome.success), units.count(outcome.failed),
+                    units.count(outcome.paywalled), units.duration(outcome.duration), result
+                )
+            }
+            Some(Err(e)) => {
+                errors += 1;
+                format!("出错: {}", e)
+            }
+            None => "未开始（已中断）".to_string(),
+        };
+        info!("  [{}] {}: {}", i + 1, labels[*i], line);
+    }
+    info!("=========================================");
+    if interrupted.load(Ordering::Relaxed) {
+        return Ok(RunStatus::Interrupted);
+    }
+    if rejected > 0 {
+        return Ok(RunStatus::QualityRejected);
+    }
+    if errors > 0 {
+        return Err(format!("{}/{} 本书抓取出错", errors, total).into());
+    }
+    Ok(RunStatus::Completed)
+}
+
+/// 抓取、清洗、写出一本书并打印汇总
+async fn run_book(config: Config) -> Result<BookOutcome, Box<dyn std::error::Error>> {
+    let crawler = CrawlerBuilder::from_config(config).build()?;
+    let _interrupt = spawn_interrupt_handler(crawler.run_control.clone());
+    write_book(&crawler).await
+}
+
+/// 用配置好的抓取写出一本书。先写入 .part 临时文件，通过质量检查且没有被中断时才重命名为最终输出
+async fn write_book(crawler: &Crawler) -> Result<BookOutcome, Box<dyn std::error::Error>> {
+    let start_time = Instant::now();
+    let config = &crawler.config;
+    let concurrent_limit = config.concurrent_limit();
+    let output_file_path = &config.output.file;
+
+    let substitutions = Substitutions::load(&config.clean.substitutions_file)?;
+    let remove_patterns = config.clean.remove_patterns.iter()
+        .map(|pattern| Regex::new(pattern).map_err(|e| format!("clean.remove_patterns 中的正则无效 {}: {}", pattern, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let annotations = Annotations::load(&config.output.annotations_file, config.output.annotation_style)?;
+
+    // 先写入临时文件，通过质量检查后才重命名为最终输出，避免自动化流程发布残缺的书
+    let part_file_path = format!("{}.part", output_file_path);
+    if let Some(dir) = std::path::Path::new(output_file_path).parent() && !dir.as_os_str().is_empty() {
+        std::fs::create_dir_all(output_path(&dir.to_string_lossy()))?;
+    }
+    let output_file = File::create(output_path(&part_file_path))?;
+    // 在抓取前创建输出，封面等配置有误时尽早失败
+    let title = book_title(config);
+    let index = ChapterIndex::new(&config.output.index_file, output_file_path);
+    if index.is_some() && !matches!(config.output.format, OutputFormat::Txt | OutputFormat::Markdown) {
+        info!("章节索引只用于 txt 和 markdown 输出，不生成 {}", config.output.index_file);
+    }
+    let output_sink: Box<dyn Sink> = match config.output.format {
+        OutputFormat::Txt => Box::new(TextSink(FileOutput { file: output_file, fsync: config.output.fsync, index })),
+        OutputFormat::Markdown => Box::new(MarkdownSink::create(FileOutput { file: output_file, fsync: config.output.fsync, index }, &title)?),
+        OutputFormat::Html => {
+            let body_path = output_path(&format!("{}.body", part_file_path));
+            Box::new(HtmlSink::create(output_file, body_path, title, config.output.fsync)?)
+        }
+        OutputFormat::Epub => {
+            let metadata = EpubMetadata {
+                title,
+                author: config.output.author.clone(),
+                tags: config.output.tags.clone(),
+                language: config.output.language.clone(),
+                source: config.urls.entry_url().to_string(),
+            };
+            Box::new(EpubSink::create(output_file, metadata, &config.output.cover, config.output.fsync)?)
+        }
+        OutputFormat::Json => Box::new(JsonSink::create(output_file, false, config.output.fsync)?),
+        OutputFormat::Ndjson => Box::new(JsonSink::create(output_file, true, config.output.fsync)?),
+    };
+    // 结构化输出要包含每一章的结果，失败和付费章节总是写出一条记录
+    let records_failures = matches!(config.output.format, OutputFormat::Json | OutputFormat::Ndjson);
+
+    let fetch_phase_start = Instant::now();
+    let (mut chapter_results, mut total_chapters) = match crawler.crawl().await {
+        Ok(crawled) => crawled,
+        Err(e) => {
+            // 还没有写出任何章节，临时文件没有用处；已抓到的进度在断点文件中
+            drop(output_sink);
+            let _ = std::fs::remove_file(output_path(&part_file_path));
+            let _ = std::fs::remove_file(output_path(&format!("{}.body", part_file_path)));
+            info!("抓取中止，已删除临时文件 {}", part_file_path);
+            return Err(e);
+        }
+    };
+    let fetch_phase_ms = fetch_phase_start.elapsed().as_millis() as u64;
+
+    let spill = spill_store(config);
+    let injected = detect_injected_paragraphs(&chapter_results, spill.as_ref(), config.clean.injected_min_chapters, config.clean.dedupe_title);
+    if !injected.is_empty() {
+        info!("检测到 {} 种疑似插入广告段落:", injected.len());
+        for (count, sample) in injected.values() {
+            info!("  ({}章) {}", count, sample);
+        }
+        if !config.clean.strip_injected {
+            info!("如需移除，请在配置中设置 [clean] strip_injected = true");
+        }
+    }
+
+    let injected = if config.clean.strip_injected { injected.into_keys().collect() } else { HashSet::new() };
+
+    let duplicates = detect_duplicate_chapters(&chapter_results, spill.as_ref(), config.clean.duplicate_titles);
+    if !duplicates.is_empty() {
+        let title_of = |index: usize| chapter_results.iter().find(|r| r.index == index).map_or("", |r| r.title.as_str());
+        info!("检测到 {} 章与前面的章节重复:", duplicates.len());
+        for duplicate in &duplicates {
+            info!(
+                "  [{}] {} 与 [{}] {} {}",
+                duplicate.index + 1, title_of(duplicate.index), duplicate.original + 1, title_of(duplicate.original),
+                if duplicate.same_content { "正文相同" } else { "标题相同" }
+            );
+        }
+        if config.clean.drop_duplicate_chapters {
+            let dropped: HashSet<usize> = duplicates.iter().map(|d| d.index).collect();
+            chapter_results.retain(|r| !dropped.contains(&r.index));
+            total_chapters -= dropped.len();
+            info!("已移除 {} 章重复章节，不写入输出", dropped.len());
+        } else {
+            info!("如需移除，请在配置中设置 [clean] drop_duplicate_chapters = true");
+        }
+    }
+    let mut pipeline = Pipeline::default().transform(Cleaner::new(&config.clean, substitutions, remove_patterns, injected));
+    // 批注在清洗之后并入，不会被当成重复段落或广告处理
+    if let Some(annotations) = annotations {
+        pipeline = pipeline.transform(annotations);
+    }
+    let mut pipeline = pipeline.sink(output_sink);
+    if let Some(digest) = DigestSink::open(&config.output.digest_file, config.output.digest_granularity) {
+        pipeline = pipeline.sink(digest);
+    }
+    let pipeline = pipeline
+        .placeholders(config.output.placeholders || records_failures)
+        .spill(spill.clone());
+    let write_start = Instant::now();
+    info!("开始清洗并写入 {} 章到文件...", chapter_results.len());
+    let (chapter_results, sink_stats) = pipeline.run(chapter_results).await.map_err(|e| {
+        warn!("写出出错，已写出的部分保留在临时文件 {}", part_file_path);
+        e as Box<dyn std::error::Error>
+    })?;
+    let (success_count, fail_count, paywalled_count) = (sink_stats.success, sink_stats.failed, sink_stats.paywalled);
+    let write_duration = write_start.elapsed().as_millis();
+    info!("文件写入完成 ({}ms)", write_duration);
+
+    let total_duration = start_time.elapsed();
+    let units = UnitFormat::for_language(&config.output.language);
+    let catalog_ms = crawler.run_control.catalog_ms.load(Ordering::Relaxed);
+    let phases = PhaseReport {
+        catalog_ms,
+        fetch_ms: fetch_phase_ms.saturating_sub(catalog_ms),
+        parse_ms: crawler.run_control.parse_ms.load(Ordering::Relaxed),
+        write_ms: write_duration as u64,
+        total_ms: total_duration.as_millis() as u64,
+    };
+    info!("=========================================");
+    info!("爬取完成");
+    info!(
+        "总章节: {} | 成功: {} | 失败: {} | 付费: {}",
+        units.count(total_chapters), units.count(success_count), units.count(fail_count), units.count(paywalled_count)
+    );
+    let failures = failure_reasons(&chapter_results);
+    if !failures.is_empty() {
+        let summary: Vec<String> = failures.iter().map(|(reason, count)| format!("{} {}章", reason, count)).collect();
+        info!("失败原因: {}", summary.join(" | "));
+    }
+    let partial_results: Vec<_> = chapter_results.iter().filter(|r| r.success && r.partial).collect();
+    if !partial_results.is_empty() {
+        info!("内容过短（已写入并标记）: {} 章", partial_results.len());
+        for result in partial_results {
+            info!("  [{}] {} ({}字) {}", result.index + 1, result.title, result.chars, result.url);
+        }
+    }
+    let not_found: Vec<NotFoundChapter> = chapter_results.iter()
+        .filter(|r| r.not_found)
+        .map(|r| NotFoundChapter { index: r.index + 1, url: r.url.clone(), error: r.error_msg.clone().unwrap_or_default() })
+        .collect();
+    if !not_found.is_empty() {
+        info!("页面不存在（软 404）的章节:");
+        for chapter in &not_found {
+            info!("  [{}] {} ({})", chapter.index, chapter.url, chapter.error);
+        }
+    }
+    if paywalled_count > 0 {
+        info!("付费章节列表:");
+        for result in chapter_results.iter().filter(|r| r.paywalled) {
+            info!("  [{}] {}", result.index + 1, result.url);
+        }
+    }
+    let lengths = analyze_lengths(&chapter_results);
+    info!(
+        "章节字数: 中位数 {} | 平均 {} | 标准差 {} | 最少 {} | 最多 {}",
+        units.count(lengths.median), units.count(lengths.mean.round() as usize), units.count(lengths.std_dev.round() as usize),
+        units.count(lengths.min), units.count(lengths.max)
+    );
+    if !lengths.suspects.is_empty() {
+        info!("疑似截断章节（字数低于 {:.0}）: {} 章", lengths.threshold.max(0.0), lengths.suspects.len());
+        for suspect in &lengths.suspects {
+            info!("  [{}] {} ({}字) {}", suspect.index, suspect.title, suspect.chars, suspect.url);
+        }
+    }
+    let volumes = analyze_volumes(&chapter_results);
+    for volume in &volumes {
+        info!("分卷 {}: 成功 {}/{} 章 | {}字", volume.name, volume.success, volume.chapters, units.count(volume.chars));
+    }
+    let numbering = analyze_numbering(&chapter_results);
+    if !numbering.missing.is_empty() {
+        info!("章节序号缺失 {} 个: {}", numbering.missing.len(), format_number_ranges(&numbering.missing));
+    }
+    if !numbering.duplicates.is_empty() {
+        info!("章节序号重复 {} 个: {}", numbering.duplicates.len(), format_number_ranges(&numbering.duplicates));
+    }
+    for (prev, next) in &numbering.jumps {
+        info!("章节序号从 {} 跳到 {}，中间的序号不计为缺失", prev, next);
+    }
+    if !config.output.failures_file.is_empty() {
+        match write_failures(&config.output.failures_file, &chapter_results) {
+            Ok(0) => {}
+            Ok(count) => info!(
+                "失败章节列表: {} ({} 章)，可用 --retry-failures {} 只重抓这些章节",
+                config.output.failures_file, count, config.output.failures_file
+            ),
+            Err(e) => warn!("失败章节列表写入失败: {}", e),
+        }
+    }
+    if !config.output.timeline_file.is_empty() {
+        match write_timeline(&config.output.timeline_file, &chapter_results) {
+            Ok(_) => info!("时间线文件: {}", config.output.timeline_file),
+            Err(e) => warn!("时间线写入失败: {}", e),
+        }
+    }
+    let timing = analyze_timing(&chapter_results, concurrent_limit, fetch_phase_ms);
+    info!(
+        "请求耗时: 平均 {} (P95 {}) | 等待并发许可: 平均 {} (P95 {}) | 并发槽利用率 {:.0}%",
+        units.duration_ms(timing.avg_fetch_ms), units.duration_ms(timing.p95_fetch_ms),
+        units.duration_ms(timing.avg_wait_ms), units.duration_ms(timing.p95_wait_ms), timing.slot_utilization * 100.0
+    );
+    if timing.slot_utilization > 0.9 {
+        info!("并发槽几乎一直占满，瓶颈在并发数，提高 concurrent_limit 可能加快速度");
+    } else {
+        info!("并发槽未被占满，瓶颈在站点响应或限速，提高 concurrent_limit 帮助有限");
+    }
+    info!(
+        "阶段耗时: 目录 {} | 抓取 {} | 解析 {}（各章累计） | 清洗写入 {}",
+        units.duration_ms(phases.catalog_ms), units.duration_ms(phases.fetch_ms),
+        units.duration_ms(phases.parse_ms), units.duration_ms(phases.write_ms)
+    );
+    let network = NET_STATS.lock().unwrap().clone();
+    for (host, stats) in &network {
+        info!(
+            "连接 {}: 请求 {} | 下载 {} | 新建连接 {} | 复用率 {:.0}% | DNS 解析 {} | TLS 握手 {}",
+            host, units.count(stats.requests), units.bytes(stats.bytes), stats.connections, stats.reuse_rate() * 100.0,
+            stats.dns_lookups, stats.tls_handshakes
+        );
+    }
+    let quality = check_quality(&config.quality, total_chapters, fail_count, &lengths);
+    for violation in &quality.violations {
+        info!("质量检查未通过: {}", violation);
+    }
+    let quality_passed = quality.passed;
+    // 中断时未抓取的章节不计入失败率，书不完整，同样只保留临时文件
+    let interrupted = crawler.run_control.is_stopped();
+    let published = quality_passed && !interrupted;
+    if published {
+        std::fs::rename(output_path(&part_file_path), output_path(output_file_path))?;
+        // 全部章节都已抓到时断点文件没有用处了，有缺失则保留以便 --resume 补抓
+        if success_count == total_chapters && !config.crawl.state_file.is_empty() {
+            let _ = std::fs::remove_file(output_path(&config.crawl.state_file));
+            if let Some(spill) = &spill {
+                spill.remove();
+            }
+        }
+    }
+    // 没有断点文件就无法续抓，转存的正文留着也用不上
+    if config.crawl.state_file.is_empty() && let Some(spill) = &spill {
+        spill.remove();
+    }
+    if !config.output.report_file.is_empty() {
+        let report = Report {
+            generated_at: format_time_rfc3339(chrono::Utc::now()),
+            total_chapters,
+            success: success_count,
+            failed: fail_count,
+            paywalled: paywalled_count,
+            failures,
+            not_found,
+            volumes,
+            lengths,
+            numbering,
+            timing,
+            phases,
+            network,
+            quality,
+        };
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => match std::fs::write(output_path(&config.output.report_file), json) {
+                Ok(_) => info!("报告文件: {}", config.output.report_file),
+                Err(e) => warn!("报告写入失败: {}", e),
+            },
+            Err(e) => warn!("报告序列化失败: {}", e),
+        }
+    }
+    info!("总耗时: {}", units.duration(total_duration));
+    info!(
+        "平均每章: {}",
+        units.duration_ms(if success_count > 0 { total_duration.as_millis() as u64 / success_count as u64 } else { 0 })
+    );
+    if published {
+        match std::fs::metadata(output_path(output_file_path)) {
+            Ok(meta) => info!("输出文件: {} ({})", output_file_path, units.bytes(meta.len())),
+            Err(_) => info!("输出文件: {}", output_file_path),
+        }
+    } else if !quality_passed {
+        info!("未通过质量检查，结果保留在临时文件: {}", part_file_path);
+    } else {
+        info!("抓取未完成，结果保留在临时文件: {}", part_file_path);
+    }
+    if interrupted {
+        let hint = if config.crawl.state_file.is_empty() || config.forum.enabled {
+            String::new()
+        } else {
+            format!("，进度已保存到 {}，可用 --resume 继续", config.crawl.state_file)
+        };
+        info!("抓取被中断，已写出 {}/{} 章{}", chapter_results.len(), total_chapters, hint);
+    }
+    info!("=========================================");
+    Ok(BookOutcome {
+        total_chapters,
+        success: success_count,
+        failed: fail_count,
+        paywalled: paywalled_count,
+        duration: total_duration,
+        quality_passed,
+        interrupted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn chapter(index: usize, title: &str, content: &[&str]) -> ChapterResult {
+        let content = content.iter().map(|para| para.to_string()).collect();
+        ChapterResult::success(index, title.to_string(), format!("https://example.com/{}.html", index), content, 0, chrono::Utc::now())
+    }
+
+    #[test]
+    fn quality_gate_checks_each_threshold() {
+        let long = "字".repeat(1000);
+        let mut results: Vec<ChapterResult> = (0..20).map(|i| chapter(i, "章", &[long.as_str()])).collect();
+        let lengths = analyze_lengths(&results);
+        let unset = QualityConfig::default();
+        assert!(check_quality(&unset, 0, 0, &LengthReport::default()).passed);
+
+        let failures = QualityConfig { max_failure_percent: Some(5.0), ..Default::default() };
+        assert!(check_quality(&failures, 20, 1, &lengths).passed);
+        let report = check_quality(&failures, 20, 2, &lengths);
+        assert!(!report.passed);
+        assert_eq!(report.violations, ["失败率 10.0% 超过上限 5%"]);
+        assert!(check_quality(&failures, 0, 0, &LengthReport::default()).passed);
+
+        let chars = QualityConfig { min_avg_chapter_chars: Some(1000), ..Default::default() };
+        assert!(check_quality(&chars, 20, 0, &lengths).passed);
+        let chars = QualityConfig { min_avg_chapter_chars: Some(1001), ..Default::default() };
+        assert_eq!(check_quality(&chars, 20, 0, &lengths).violations, ["平均章节字数 1000 低于下限 1001"]);
+
+        results[3] = chapter(3, "截断", &[]);
+        let lengths = analyze_lengths(&results);
+        let suspects = QualityConfig { max_suspect_chapters: Some(1), ..Default::default() };
+        assert!(check_quality(&suspects, 20, 0, &lengths).passed);
+        let suspects = QualityConfig { max_suspect_chapters: Some(0), ..Default::default() };
+        assert_eq!(check_quality(&suspects, 20, 0, &lengths).violations, ["疑似截断章节 1 章超过上限 0"]);
+    }
+
+    #[tokio::test]
+    async fn ramp_up_raises_permits_until_adjusted_by_hand() {
+        let ramped = ConcurrencyControl::new(1);
+        ramped.spawn_ramp_up(1, 3, 1);
+        let adjusted = ConcurrencyControl::new(1);
+        adjusted.spawn_ramp_up(1, 3, 1);
+        ConcurrencyControl::set_concurrency(&adjusted.semaphore, &adjusted.concurrency, 2);
+        assert_eq!(ramped.semaphore.available_permits(), 1);
+
+        tokio::time::sleep(Duration::from_millis(1300)).await;
+        assert_eq!((ramped.semaphore.available_permits(), ramped.concurrency.lock().unwrap().current), (3, 3));
+        assert_eq!((adjusted.semaphore.available_permits(), adjusted.concurrency.lock().unwrap().current), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn raising_concurrency_cancels_pending_shrink() {
+        let control = ConcurrencyControl::new(4);
+        let busy = control.semaphore.clone().acquire_many_owned(4).await.unwrap();
+        ConcurrencyControl::set_concurrency(&control.semaphore, &control.concurrency, 1);
+        tokio::task::yield_now().await;
+        assert!(control.concurrency.lock().unwrap().shrink.is_some());
+
+        // 收回还在等空闲许可时调大，最终许可数应为新的目标，而不是再被减去 3
+        ConcurrencyControl::set_concurrency(&control.semaphore, &control.concurrency, 6);
+        drop(busy);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(control.semaphore.available_permits(), 6);
+
+        ConcurrencyControl::set_concurrency(&control.semaphore, &control.concurrency, 2);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(control.semaphore.available_permits(), 2);
+        assert!(control.concurrency.lock().unwrap().shrink.is_none());
+    }
+
+    #[test]
+    fn injected_detection_skips_title_paragraph_removed_by_dedupe() {
+        let bodies = ["春风吹过山岗", "夏雨落在湖面", "秋叶飘满小径", "冬雪盖住屋顶", "清晨鸟鸣不止"];
+        let results: Vec<ChapterResult> = bodies.iter().enumerate()
+            .map(|(i, body)| chapter(i, "番外篇章", &["番外篇章", body]))
+            .collect();
+        assert_eq!(detect_injected_paragraphs(&results, None, 5, false).len(), 1);
+        assert!(detect_injected_paragraphs(&results, None, 5, true).is_empty());
+    }
+
+    #[test]
+    fn duplicate_detection_reads_spilled_content_and_skips_unreadable_chapters() {
+        let dir = std::env::temp_dir().join(format!("rust_crawler_spill_dup_{}", std::process::id()));
+        let spill = SpillStore::new(dir.clone(), None);
+        spill.prepare(false).unwrap();
+        let mut results = vec![chapter(0, "一", &["相同的正文"]), chapter(1, "二", &["相同的正文"]), chapter(2, "三", &["相同的正文"])];
+        spill.stash(&mut results[0]).unwrap();
+        spill.stash(&mut results[2]).unwrap();
+        std::fs::remove_file(dir.join("00003.json")).unwrap();
+
+        let duplicates = detect_duplicate_chapters(&results, Some(&spill), false);
+        let found: Vec<(usize, usize)> = duplicates.iter().map(|d| (d.index, d.original)).collect();
+        assert_eq!(found, [(1, 0)]);
+        assert_eq!(chapter_content(&results[0], Some(&spill)).unwrap().as_ref(), ["相同的正文"]);
+        assert!(chapter_content(&results[2], Some(&spill)).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn decode_entities_once_decodes_a_single_layer() {
+        assert_eq!(decode_entities_once("&amp;lt;p&amp;gt;"), "&lt;p&gt;");
+        assert_eq!(decode_entities_once("a &lt; b"), "a < b");
+    }
+
+    #[test]
+    fn decode_entities_unwraps_double_encoding() {
+        assert_eq!(decode_entities("他说&amp;nbsp;好"), "他说 好");
+        assert_eq!(decode_entities("&amp;amp;"), "&");
+        assert_eq!(decode_entities("&amp;ldquo;你好&amp;rdquo;"), "“你好”");
+        assert_eq!(decode_entities("&amp;#20013;"), "中");
+    }
+
+    #[test]
+    fn decode_entities_numeric_references() {
+        assert_eq!(decode_entities("&#20013;&#25991;"), "中文");
+        assert_eq!(decode_entities("&#x4E2D;&#x6587;"), "中文");
+        assert_eq!(decode_entities("&#X4e2d;"), "中");
+        assert_eq!(decode_entities("&#8212;&#x2026;"), "—…");
+    }
+
+    #[test]
+    fn decode_entities_keeps_unknown_and_malformed() {
+        for text in ["&foo;", "&#xZZ;", "&#;", "&;", "AT&T", "&amp", "a & b", "&#xD800;", "&#99999999999;", "&verylongentityname;"] {
+            assert_eq!(decode_entities(text), text, "{}", text);
+        }
+        assert_eq!(decode_entities("&foo;&amp;"), "&foo;&");
+    }
+
+    #[test]
+    fn decode_entities_normalizes_spaces_and_strips_zero_width() {
+        assert_eq!(decode_entities("第一章&nbsp;开始"), "第一章 开始");
+        assert_eq!(decode_entities("a\u{00A0}b\u{2003}c&ensp;d"), "a b c d");
+        assert_eq!(decode_entities("正\u{200B}文&#8203;内&zwj;容\u{FEFF}"), "正文内容");
+    }
+
+    #[test]
+    fn decode_entities_borrows_clean_text() {
+        assert!(matches!(decode_entities("没有需要处理的内容"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn numbering_reports_gaps_and_duplicates() {
+        let titles = ["第1章", "第2章", "第2章", "第5章", "第六章"];
+        let results: Vec<ChapterResult> = titles.iter().enumerate().map(|(i, title)| chapter(i, title, &["正文"])).collect();
+        let report = analyze_numbering(&results);
+        assert_eq!(report.numbered, 5);
+        assert_eq!(report.missing, vec![3, 4]);
+        assert_eq!(report.duplicates, vec![2]);
+        assert!(report.jumps.is_empty());
+    }
+
+    #[test]
+    fn numbering_does_not_expand_huge_gaps() {
+        let titles = ["第1章", "第3章", "第99999999章", "第100000001章"];
+        let results: Vec<ChapterResult> = titles.iter().enumerate().map(|(i, title)| chapter(i, title, &["正文"])).collect();
+        let report = analyze_numbering(&results);
+        assert_eq!(report.missing, vec![2, 100000000]);
+        assert_eq!(report.jumps, vec![(3, 99999999)]);
+    }
+
+    /// 清洗流水线的性能基准：一万章、每章六十段、三十条 remove_patterns，与逐条规则生成中间字符串的做法对比。
+    /// 耗时取决于机器，只打印结果不做断言：cargo test --release -- --ignored --nocapture clean_pass_benchmark
+    #[test]
+    #[ignore]
+    #[allow(clippy::print_stdout)]
+    fn clean_pass_benchmark() {
+        const CHAPTERS: usize = 10_000;
+        const PARAGRAPHS: usize = 60;
+        let patterns: Vec<String> = (0..30).map(|i| format!(r"【广告{}[^】]*】|本站域名\d+号", i)).collect();
+        let corpus: Vec<ChapterResult> = (0..CHAPTERS)
+            .map(|i| {
+                let content: Vec<String> = (0..PARAGRAPHS)
+                    .map(|j| match j % 10 {
+                        0 => format!("第{}章 标题", i),
+                        3 => "请收藏本站 www.example.com".to_string(),
+                        5 => format!("他说&amp;nbsp;“好的”&#8203;，然后走了{}步。【广告{}点击】", j, j % 30),
+                        _ => format!("这是第{}章第{}段的正文内容，主角在山间小路上慢慢走着，心里想着昨天发生的事情。", i, j),
+                    })
+                    .collect();
+                ChapterResult::success(i, format!("第{}章 标题", i), String::new(), content, 0, chrono::Utc::now())
+            })
+            .collect();
+        let paragraphs = CHAPTERS * PARAGRAPHS;
+        let regexes: Vec<Regex> = patterns.iter().map(|p| Regex::new(p).unwrap()).collect();
+        let config = CleanConfig { dedupe_title: true, remove_patterns: patterns, ..CleanConfig::default() };
+
+        let mut chapters: Vec<ChapterResult> = corpus.iter()
+            .map(|r| ChapterResult::success(r.index, r.title.clone(), String::new(), r.content.clone(), 0, chrono::Utc::now()))
+            .collect();
+        let mut cleaner = Cleaner::new(&config, None, regexes.clone(), HashSet::new());
+        let start = Instant::now();
+        for chapter in &mut chapters {
+            cleaner.apply(chapter);
+        }
+        let streaming = start.elapsed();
+
+        // 对照：每条规则各遍历一次全部段落，每次都生成新的字符串
+        let start = Instant::now();
+        let mut kept = 0;
+        for source in &corpus {
+            let mut content = source.content.clone();
+            content = content.iter().map(|para| decode_entities(para).into_owned()).collect();
+            for regex in &regexes {
+                content = content.iter().map(|para| regex.replace_all(para, "").into_owned()).collect();
+            }
+            content.retain(|para| !is_ad_line(para));
+            if content.first().is_some_and(|first| is_duplicate_title(&source.title, first)) {
+                content.remove(0);
+            }
+            kept += content.len();
+        }
+        let per_rule = start.elapsed();
+        assert!(kept > 0);
+
+        let rate = |elapsed: Duration| paragraphs as f64 / elapsed.as_secs_f64() / 1e6;
+        println!(
+            "{} 章 {} 段: 单次遍历 {:?} ({:.2} 百万段/秒)，逐条规则 {:?} ({:.2} 百万段/秒)",
+            CHAPTERS, paragraphs, streaming, rate(streaming), per_rule, rate(per_rule)
+        );
+    }
+
+    #[test]
+    fn ad_heuristics_are_off_by_default() {
+        assert!(!Config::default().clean.ad_heuristics);
+    }
+
+    #[test]
+    fn ad_heuristics_keep_prose_mentioning_sites() {
+        let config: CleanConfig = toml::from_str("ad_heuristics = true").unwrap();
+        let cleaner = Cleaner::new(&config, None, Vec::new(), HashSet::new());
+        let kept = [
+            "他翻到最新章节，发现作者又断更了。",
+            "这本书可以免费阅读，但她还是买了实体版。",
+            "夜深了，他还在用手机阅读。",
+            "请收藏好这封信，别让任何人看见。",
+            "她在 example.me 上注册了账号，用户名叫 night.co。",
+            "信上只写着一个网址：www.example.com。",
+        ];
+        for para in kept {
+            assert_eq!(cleaner.strip_ads(para.to_string(), &mut CleanStats::default()).as_deref(), Some(para));
+        }
+        let removed = [
+            "一秒记住【笔趣阁 www.biquge.la】，精彩小说无弹窗免费阅读！",
+            "最新章节请到 www.example.com 阅读",
+            "手机用户请浏览 m.example.cc 阅读，更优质的阅读体验。",
+            "请收藏本站：https://example.net",
+        ];
+        for para in removed {
+            assert_eq!(cleaner.strip_ads(para.to_string(), &mut CleanStats::default()), None, "{}", para);
+        }
+    }
+
+    #[test]
+    fn catalog_titles_drop_latest_prefix_dates_and_view_counts() {
+        let patterns: Vec<Regex> = Config::default().clean.catalog_title_patterns.iter().map(|p| Regex::new(p).unwrap()).collect();
+        let clean = |text| clean_catalog_title(text, &patterns);
+        assert_eq!(clean("最新：第10章 风起 (2024-05-01 12:30) 阅读:1.2万"), "第10章 风起");
+        assert_eq!(clean("【最新章节】第3章  归来"), "第3章 归来");
+        assert_eq!(clean("第5章 三千里路 1234次阅读"), "第5章 三千里路");
+        assert_eq!(clean("第2024章 年关"), "第2024章 年关");
+        assert_eq!(clean("2024-05-01"), "");
+        assert_eq!(clean_catalog_title(" 最新：第1章 ", &[]), "最新：第1章");
+    }
+
+    #[test]
+    fn dedupe_catalog_keeps_full_list_order_around_newest_first_block() {
+        let link = |n: u32| CatalogLink { url: format!("https://example.com/book/7/{}.html", n), volume: None, text: String::new() };
+        let full: Vec<_> = (1..=1000).map(link).collect();
+        let latest: Vec<_> = [1000, 999, 998].into_iter().map(link).collect();
+        let expected: Vec<String> = full.iter().map(|link| link.url.clone()).collect();
+
+        let leading = latest.iter().chain(&full).cloned().collect();
+        assert_eq!(dedupe_catalog_links(leading).0, expected);
+        let trailing = full.iter().chain(&latest).cloned().collect();
+        assert_eq!(dedupe_catalog_links(trailing).0, expected);
+
+        // 地址中没有数字时保留第一次出现
+        let links = ["/a", "/b", "/c", "/b"].map(|url| CatalogLink { url: url.to_string(), volume: Some(format!("卷{}", url)), text: String::new() });
+        let (urls, catalog) = dedupe_catalog_links(links.into());
+        assert_eq!(urls, ["/a", "/b", "/c"]);
+        assert_eq!(catalog["/b"].volume.as_deref(), Some("卷/b"));
+        assert_eq!(last_number("https://example.com/book/12/3456.html?p=x"), Some(3456));
+        assert_eq!(last_number("https://example.com/book/"), None);
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn gunzip_body_caps_decompressed_size() {
+        let html = "<p>正文</p>".repeat(1000);
+        let packed = gzip(html.as_bytes());
+        assert_eq!(gunzip_body("u", packed.clone(), html.len() as u64).unwrap(), html.as_bytes());
+        assert!(matches!(gunzip_body("u", packed, html.len() as u64 - 1), Err(BodyError::TooLarge(_))));
+        let bogus = vec![0x1f, 0x8b, 1, 2, 3];
+        assert_eq!(gunzip_body("u", bogus.clone(), MAX_GUNZIP_BYTES).unwrap(), bogus);
+    }
+
+    #[tokio::test]
+    async fn gzip_magic_split_across_first_chunks() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let body = gzip("<html><body><p>压缩的正文</p></body></html>".as_bytes());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.read(&mut [0u8; 1024]).await;
+            let head = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n\r\n", body.len());
+            stream.write_all(head.as_bytes()).await.unwrap();
+            // 第一块只有 gzip 标记的第一个字节
+            stream.write_all(&body[..1]).await.unwrap();
+            stream.flush().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            stream.write_all(&body[1..]).await.unwrap();
+        });
+        let resp = reqwest::Client::builder().no_gzip().build().unwrap().get(&url).send().await.unwrap();
+        assert!(read_html(resp).await.unwrap().contains("压缩的正文"));
+    }
+
+    /// 在本机端口上返回固定页面的最小 HTTP 服务，每个连接只处理一个请求
+    async fn serve(pages: HashMap<&'static str, String>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let pages = pages.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request);
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+                    let (status, body) = match pages.get(path) {
+                        Some(body) => ("200 OK", body.as_str()),
+                        None => ("404 Not Found", ""),
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status, body.len(), body
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        base
+    }
+
+    #[tokio::test]
+    async fn crawl_runs_catalog_through_fetch_stage() {
+        let mut pages = HashMap::new();
+        let links: String = (1..=12).map(|i| format!("<li><a href=\"/{}.html\">第{}章 目录名 <span>2024-05-01</span></a></li>", i, i)).collect();
+        pages.insert("/book/", format!("<html><body><ul class=\"list\">{}</ul></body></html>", links));
+        for (i, path) in ["/1.html", "/2.html", "/3.html", "/4.html", "/5.html", "/6.html", "/7.html", "/8.html", "/9.html", "/10.html", "/11.html"].into_iter().enumerate() {
+            let n = i + 1;
+            pages.insert(path, format!("<html><body><h1>第{}章</h1><div id=\"content\"><p>第{}章正文甲</p><p>第{}章正文乙</p></div></body></html>", n, n, n));
+        }
+        let base = serve(pages).await;
+
+        let config: Config = toml::from_str("[crawl]\nstate_file = \"\"\nmax_retries = 0").unwrap();
+        let results = CrawlerBuilder::from_config(config)
+            .base_url(format!("{}/", base))
+            .catalog_url(format!("{}/book/", base))
+            .chapter_link_selector("ul.list a")
+            .title_selector("h1")
+            .content_selector("#content p")
+            .concurrency(4)
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        let indices: Vec<usize> = results.iter().map(|r| r.index).collect();
+        assert_eq!(indices, (0..12).collect::<Vec<_>>());
+        assert!(results[..11].iter().all(|r| r.success));
+        assert_eq!(results[4].title, "第5章");
+        assert_eq!(results[4].content, ["第5章正文甲", "第5章正文乙"]);
+        assert_eq!(results[4].toc_title(), "第5章 目录名");
+        assert!(!results[11].success);
+        assert_eq!(results[11].catalog_title.as_deref(), Some("第12章 目录名"));
+    }
+
+    #[tokio::test]
+    async fn crawl_events_reach_subscribers() {
+        let mut pages = HashMap::new();
+        let links: String = (1..=3).map(|i| format!("<li><a href=\"/{}.html\">第{}章</a></li>", i, i)).collect();
+        pages.insert("/book/", format!("<html><body><ul class=\"list\">{}</ul></body></html>", links));
+        for (n, path) in [(1, "/1.html"), (2, "/2.html")] {
+            pages.insert(path, format!("<html><body><h1>第{}章</h1><div id=\"content\"><p>正文</p></div></body></html>", n));
+        }
+        let base = serve(pages).await;
+        let config: Config = toml::from_str("[crawl]\nstate_file = \"\"\nmax_retries = 0").unwrap();
+        let crawler = CrawlerBuilder::from_config(config)
+            .base_url(format!("{}/", base))
+            .catalog_url(format!("{}/book/", base))
+            .chapter_link_selector("ul.list a")
+            .title_selector("h1")
+            .content_selector("#content p")
+            .build()
+            .unwrap();
+        let mut events = crawler.subscribe();
+        let handle = crawler.start();
+        let results = handle.join().await.unwrap();
+        assert_eq!(results.len(), 3);
+
+        let (mut started, mut finished, mut failed, mut last_progress) = (HashSet::new(), Vec::new(), Vec::new(), None);
+        while let Ok(event) = events.try_recv() {
+            match event {
+                CrawlEvent::ChapterStarted { index, .. } => {
+                    started.insert(index);
+                }
+                CrawlEvent::ChapterFinished { index, title, paywalled } => {
+                    assert!(!paywalled);
+                    finished.push((index, title));
+                }
+                CrawlEvent::ChapterFailed { index, url, .. } => failed.push((index, url)),
+                CrawlEvent::Progress(progress) => last_progress = Some(progress),
+            }
+        }
+        assert_eq!(started, HashSet::from([0, 1, 2]));
+        finished.sort();
+        assert_eq!(finished, [(0, "第1章".to_string()), (1, "第2章".to_string())]);
+        assert_eq!(failed, [(2, format!("{}/3.html", base))]);
+        let progress = last_progress.unwrap();
+        assert_eq!((progress.total, progress.succeeded, progress.failed, progress.completed()), (3, 2, 1, 3));
+    }
+
+    #[tokio::test]
+    async fn stopped_crawl_keeps_part_file() {
+        let mut pages = HashMap::new();
+        let links: String = (1..=3).map(|i| format!("<li><a href=\"/{}.html\">第{}章</a></li>", i, i)).collect();
+        pages.insert("/book/", format!("<html><body><ul class=\"list\">{}</ul></body></html>", links));
+        pages.insert("/1.html", "<html><body><h1>第1章</h1><div id=\"content\"><p>正文</p></div></body></html>".to_string());
+        let base = serve(pages).await;
+        let output = std::env::temp_dir().join(format!("rust_crawler_stopped_{}.txt", std::process::id()));
+        let output = output.to_string_lossy().into_owned();
+
+        let mut config: Config = toml::from_str("[crawl]\nstate_file = \"\"\nmax_retries = 0\n[log]\nprogress = false").unwrap();
+        config.output.file = output.clone();
+        let crawler = CrawlerBuilder::from_config(config)
+            .base_url(format!("{}/", base))
+            .catalog_url(format!("{}/book/", base))
+            .chapter_link_selector("ul.list a")
+            .title_selector("h1")
+            .content_selector("#content p")
+            .build()
+            .unwrap();
+        crawler.run_control.stop.cancel();
+        let outcome = write_book(&crawler).await.unwrap();
+
+        let part = format!("{}.part", output);
+        assert!(outcome.interrupted);
+        assert!(!std::path::Path::new(&output).exists());
+        assert!(std::path::Path::new(&part).exists());
+        std::fs::remove_file(part).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_crawl_removes_part_file() {
+        // 目录中的章节页都不存在，连续失败达到上限后中止抓取
+        let mut pages = HashMap::new();
+        let links: String = (1..=3).map(|i| format!("<li><a href=\"/{}.html\">第{}章</a></li>", i, i)).collect();
+        pages.insert("/book/", format!("<html><body><ul class=\"list\">{}</ul></body></html>", links));
+        let base = serve(pages).await;
+        let output = std::env::temp_dir().join(format!("rust_crawler_failed_{}.html", std::process::id()));
+        let output = output.to_string_lossy().into_owned();
+        let mut config: Config = toml::from_str("[crawl]\nstate_file = \"\"\nmax_retries = 0\nabort_after_consecutive_failures = 2\n[log]\nprogress = false\n[output]\nformat = \"html\"").unwrap();
+        config.output.file = output.clone();
+        let crawler = CrawlerBuilder::from_config(config)
+            .base_url(format!("{}/", base))
+            .catalog_url(format!("{}/book/", base))
+            .chapter_link_selector("ul.list a")
+            .build()
+            .unwrap();
+        assert!(write_book(&crawler).await.is_err());
+        assert!(!std::path::Path::new(&format!("{}.part", output)).exists());
+        assert!(!std::path::Path::new(&format!("{}.part.body", output)).exists());
+    }
+}
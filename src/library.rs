@@ -0,0 +1,135 @@
+//! 批量模式的书库：每本书写入 output_dir 下以书名命名的子目录，output_dir/library.json 记录各书的
+//! 目录、章节数、更新时间、标签和已有的输出格式，供其他工具（如 OPDS 生成器）读取
+//!
+//! 每次批量运行只更新本次写出的书，索引中的其他书保留不变。
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+pub(crate) const INDEX_FILE: &str = "library.json";
+
+/// 认作输出格式的扩展名，同 output.format
+const FORMATS: [&str; 6] = ["epub", "txt", "md", "html", "json", "ndjson"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct LibraryEntry {
+    pub(crate) title: String,
+    #[serde(default)]
+    pub(crate) author: String,
+    /// 相对 output_dir 的子目录
+    pub(crate) dir: String,
+    /// 书籍主页或目录页地址
+    pub(crate) source: String,
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+    pub(crate) chapters: usize,
+    pub(crate) success: usize,
+    pub(crate) updated_at: String,
+    /// 子目录中已有的输出格式（如 ["epub", "txt"]），更新时重新扫描
+    #[serde(default)]
+    pub(crate) formats: Vec<String>,
+    /// 输出文件名去掉扩展名，只有同名的文件才算这本书的输出，不写入索引
+    #[serde(skip)]
+    pub(crate) stem: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Library {
+    books: Vec<LibraryEntry>,
+}
+
+/// 书名转为子目录名：去掉文件名中不能使用的字符，空书名用 "untitled"
+pub(crate) fn dir_name(title: &str) -> String {
+    let name: String = title.trim()
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect();
+    let name = name.trim_matches('.').trim();
+    if name.is_empty() { "untitled".to_string() } else { name.to_string() }
+}
+
+/// 把本次写出的书合并进 output_dir/library.json，按子目录替换已有条目；索引文件无法解析时报错，不覆盖
+pub(crate) fn update(output_dir: &Path, entries: Vec<LibraryEntry>) -> io::Result<()> {
+    let path = output_dir.join(INDEX_FILE);
+    let mut library: Library = match std::fs::read(&path) {
+        Ok(json) => serde_json::from_slice(&json).map_err(|e| io::Error::other(format!("{} 无法解析: {}", path.display(), e)))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Library::default(),
+        Err(e) => return Err(e),
+    };
+    for mut entry in entries {
+        entry.formats = formats(&output_dir.join(&entry.dir), &entry.stem);
+        match library.books.iter_mut().find(|book| book.dir == entry.dir) {
+            Some(book) => *book = entry,
+            None => library.books.push(entry),
+        }
+    }
+    library.books.sort_by(|a, b| a.dir.cmp(&b.dir));
+    let json = serde_json::to_vec_pretty(&library).map_err(io::Error::other)?;
+    let tmp_path = output_dir.join(format!("{}.tmp", INDEX_FILE));
+    std::fs::write(&tmp_path, json)?;
+    std::fs::rename(&tmp_path, &path)
+}
+
+fn formats(dir: &Path, stem: &str) -> Vec<String> {
+    let Ok(files) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut formats: Vec<String> = files
+        .filter_map(|file| {
+            let path = file.ok()?.path();
+            let ext = path.extension()?.to_str()?;
+            (path.file_stem()? == stem && FORMATS.contains(&ext)).then(|| ext.to_string())
+        })
+        .collect();
+    formats.sort();
+    formats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, chapters: usize) -> LibraryEntry {
+        LibraryEntry {
+            title: title.to_string(),
+            author: String::new(),
+            dir: dir_name(title),
+            source: "https://example.com/book/".to_string(),
+            tags: vec!["仙侠".to_string()],
+            chapters,
+            success: chapters,
+            updated_at: "2026-01-01T00:00:00Z".to_string(),
+            formats: Vec::new(),
+            stem: "book".to_string(),
+        }
+    }
+
+    #[test]
+    fn dir_names_drop_path_characters() {
+        assert_eq!(dir_name("诡秘之主"), "诡秘之主");
+        assert_eq!(dir_name("a/b: c?"), "a_b_ c_");
+        assert_eq!(dir_name(" .. "), "untitled");
+    }
+
+    #[test]
+    fn update_merges_entries_and_scans_formats() {
+        let dir = std::env::temp_dir().join(format!("rust_crawler_library_{}", std::process::id()));
+        let book_dir = dir.join("第一本");
+        std::fs::create_dir_all(&book_dir).unwrap();
+        for file in ["book.epub", "book.txt", "book.epub.failures.json", "other.md"] {
+            std::fs::write(book_dir.join(file), "").unwrap();
+        }
+        update(&dir, vec![entry("第一本", 10), entry("第二本", 5)]).unwrap();
+        update(&dir, vec![entry("第一本", 12)]).unwrap();
+
+        let library: Library = serde_json::from_slice(&std::fs::read(dir.join(INDEX_FILE)).unwrap()).unwrap();
+        let books: Vec<(&str, usize, &[String])> = library.books.iter().map(|b| (b.dir.as_str(), b.chapters, b.formats.as_slice())).collect();
+        assert_eq!(books, [("第一本", 12, &["epub".to_string(), "txt".to_string()][..]), ("第二本", 5, &[][..])]);
+        assert_eq!(library.books[0].tags, ["仙侠"]);
+
+        std::fs::write(dir.join(INDEX_FILE), "{").unwrap();
+        assert!(update(&dir, vec![entry("第三本", 1)]).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+//! 日志输出：程序中的日志一律通过 tracing 记录，这里负责把事件输出到控制台和日志文件
+//!
+//! - 级别：debug 为每个请求的细节（重试、解压、缓存等诊断信息），info 为抓取进度和汇总，warn / error 为问题，
+//!   按 log.level（命令行 --log-level）过滤
+//! - 控制台：info 及以下输出到标准输出，warn 和 error 输出到标准错误；显示进度条时打印在进度条上方
+//! - 格式：默认每行"时间 消息"，时间按 log.timezone 和 log.time_format 显示；log.json 开启时每行一个 JSON 对象，
+//!   附带章节序号、地址等结构化字段
+//! - 日志文件：log.file 非空时同时写入，按 log.rotation 滚动
+//!
+//! 读取配置前就要输出日志（找到哪个配置文件、配置解析失败等），因此先按默认设置输出到控制台，
+//! 加载配置后再替换为配置的输出方式
+
+use crate::progress;
+use crate::{LogConfig, LogRotation, format_time, format_time_rfc3339, get_timestamp};
+use std::io::Write;
+use std::sync::OnceLock;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Metadata, Subscriber, warn};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, Registry, reload};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+static HANDLE: OnceLock<reload::Handle<BoxedLayer, Registry>> = OnceLock::new();
+
+/// 事件中表示实际发生时间的字段（RFC 3339），按章节顺序延后输出的日志用它显示章节完成的时间
+const COMPLETED_AT: &str = "completed_at";
+
+/// 按默认设置输出到控制台，在读取配置前调用
+pub(crate) fn init() {
+    let (layer, handle) = reload::Layer::new(filter(LevelFilter::INFO).and_then(console_layer(false)).boxed());
+    if tracing_subscriber::registry().with(layer).try_init().is_ok() {
+        let _ = HANDLE.set(handle);
+    }
+}
+
+/// 按 [log] 配置替换输出方式
+pub(crate) fn configure(config: &LogConfig) {
+    let mut level = config.level.parse::<LevelFilter>().unwrap_or_else(|_| {
+        warn!("日志级别无效，使用 info: {}（可选 error、warn、info、debug、trace）", config.level);
+        LevelFilter::INFO
+    });
+    if config.verbose && level < LevelFilter::DEBUG {
+        level = LevelFilter::DEBUG;
+    }
+    let file = if config.file.is_empty() {
+        None
+    } else {
+        file_appender(&config.file, config.rotation, config.max_files)
+            .inspect_err(|e| warn!("无法创建日志文件，只输出到控制台: {} ({})", config.file, e))
+            .ok()
+            .map(|appender| file_layer(appender, config.json))
+    };
+    // 级别过滤要放在最内层：外层的输出层对所有事件都感兴趣，按组合规则由内层的过滤结果决定是否输出。
+    // 不用 Option 表示可选的文件层：装箱后 None 层会让组合的级别提示变成 OFF，所有事件都被丢弃
+    let console = filter(level).and_then(console_layer(config.json));
+    let layer = match file {
+        Some(file) => console.and_then(file).boxed(),
+        None => console.boxed(),
+    };
+    if let Some(handle) = HANDLE.get() && let Err(e) = handle.reload(layer) {
+        warn!("日志设置失败: {}", e);
+    }
+}
+
+/// 本程序的日志按配置的级别输出；依赖库（HTTP 连接等）的 debug 日志过于琐碎，最多输出到 warn
+fn filter(level: LevelFilter) -> Targets {
+    Targets::new().with_default(level.min(LevelFilter::WARN)).with_target(env!("CARGO_CRATE_NAME"), level)
+}
+
+fn console_layer(json: bool) -> BoxedLayer {
+    let layer = tracing_subscriber::fmt::layer().with_writer(Console).with_ansi(false);
+    if json {
+        layer.json().flatten_event(true).with_current_span(false).with_span_list(false).with_timer(Rfc3339).boxed()
+    } else {
+        layer.event_format(PlainFormat).boxed()
+    }
+}
+
+fn file_layer(appender: RollingFileAppender, json: bool) -> BoxedLayer {
+    let layer = tracing_subscriber::fmt::layer().with_writer(appender).with_ansi(false);
+    if json {
+        layer.json().flatten_event(true).with_current_span(false).with_span_list(false).with_timer(Rfc3339).boxed()
+    } else {
+        layer.event_format(PlainFormat).boxed()
+    }
+}
+
+/// path 拆成目录、文件名前缀和扩展名：logs/crawler.log 按天滚动时写入 logs/crawler.2024-05-01.log
+fn file_appender(path: &str, rotation: LogRotation, max_files: usize) -> Result<RollingFileAppender, tracing_appender::rolling::InitError> {
+    let path = std::path::Path::new(path);
+    let directory = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(std::path::Path::new("."));
+    let mut builder = RollingFileAppender::builder().rotation(match rotation {
+        LogRotation::Never => Rotation::NEVER,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+    });
+    if let Some(stem) = path.file_stem() {
+        builder = builder.filename_prefix(stem.to_string_lossy());
+    }
+    if let Some(extension) = path.extension() {
+        builder = builder.filename_suffix(extension.to_string_lossy());
+    }
+    if max_files > 0 {
+        builder = builder.max_log_files(max_files);
+    }
+    builder.build(directory)
+}
+
+/// 纯文本格式："时间 消息"，warn、error、debug 在消息前注明级别；结构化字段只在 JSON 格式中输出
+struct PlainFormat;
+
+impl<S, N> FormatEvent<S, N> for PlainFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, _ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> std::fmt::Result {
+        let mut fields = PlainFields::default();
+        event.record(&mut fields);
+        let timestamp = fields.completed_at
+            .and_then(|time| chrono::DateTime::parse_from_rfc3339(&time).ok())
+            .map_or_else(get_timestamp, |time| format_time(time.with_timezone(&chrono::Utc)));
+        let level = *event.metadata().level();
+        if level == Level::INFO {
+            writeln!(writer, "{} {}", timestamp, fields.message)
+        } else {
+            writeln!(writer, "{} {} {}", timestamp, level, fields.message)
+        }
+    }
+}
+
+#[derive(Default)]
+struct PlainFields {
+    message: String,
+    completed_at: Option<String>,
+}
+
+impl Visit for PlainFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            COMPLETED_AT => self.completed_at = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{:?}", value),
+            COMPLETED_AT => self.completed_at = Some(format!("{:?}", value)),
+            _ => {}
+        }
+    }
+}
+
+/// JSON 日志中的时间，使用与报告相同的 RFC 3339 格式
+struct Rfc3339;
+
+impl FormatTime for Rfc3339 {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        write!(w, "{}", format_time_rfc3339(chrono::Utc::now()))
+    }
+}
+
+/// 控制台输出：按级别选择标准输出或标准错误，显示进度条时改为打印在进度条上方
+struct Console;
+
+impl<'a> MakeWriter<'a> for Console {
+    type Writer = ConsoleWriter;
+
+    fn make_writer(&'a self) -> ConsoleWriter {
+        ConsoleWriter { stderr: false }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> ConsoleWriter {
+        ConsoleWriter { stderr: *meta.level() <= Level::WARN }
+    }
+}
+
+struct ConsoleWriter {
+    stderr: bool,
+}
+
+impl Write for ConsoleWriter {
+    /// fmt 层每条事件只调用一次 write_all，buf 即完整的一行
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        if !progress::print_above(line.trim_end_matches('\n')) {
+            if self.stderr {
+                std::io::stderr().write_all(buf)?;
+            } else {
+                std::io::stdout().write_all(buf)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.stderr { std::io::stderr().flush() } else { std::io::stdout().flush() }
+    }
+}
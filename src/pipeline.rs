@@ -0,0 +1,538 @@
+//! 抓取流水线：fetch → extract → transform → sink
+//!
+//! 每个阶段是一个独立的异步任务，阶段之间用有界通道传递类型固定的消息，上游最多领先下游 [`CHANNEL_CAPACITY`] 条：
+//!
+//! - fetch：接收 [`ChapterJob`]，按并发许可同时抓取多章，按完成先后产出 [`ChapterResult`]（`ChapterFetcher`）
+//! - extract：[`Extract`] 把一个页面的 HTML 解析为 [`PageOutcome`]。它在 fetch 阶段内逐页调用，
+//!   正文分页、页面内跳转和软 404 都要根据解析结果决定是否继续请求，拆到通道另一端就得把请求状态一并传过去
+//! - transform：每个 [`Transform`] 是一个阶段，按章节顺序就地修改抓取成功的章节
+//! - sink：一个阶段内把每章依次交给所有 [`Sink`]，产出写出后的章节供汇总统计
+//!
+//! 插入广告和重复章节的检测要看到全部章节，所以 fetch 的输出先收集起来（断点、转存、连续失败计数也在这时处理），
+//! 检测完再作为 transform 的输入。论坛模式按页顺序抓取，不经过 fetch 阶段。
+//!
+//! [`Flow`] 把阶段串起来：`Flow::from_iter(..).then(..).then_concurrent(..)`，每一步的输入类型必须与上一步的输出类型一致。
+
+use crate::spill::SpillStore;
+use crate::{ChapterResult, PageOutcome, content_chars};
+use std::error::Error;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+/// 相邻阶段之间通道的容量。正文转存时内存中同时持有的正文章节数也不超过每个阶段各这么多
+pub(crate) const CHANNEL_CAPACITY: usize = 16;
+
+/// 阶段运行在各自的任务中，错误要能跨任务传递
+pub(crate) type StageError = Box<dyn Error + Send + Sync>;
+
+/// 按到达顺序逐条处理消息的阶段
+pub(crate) trait Stage: Send + Sized + 'static {
+    type Input: Send + 'static;
+    type Output: Send + 'static;
+
+    /// 返回 None 表示这条消息不再往下游传；返回错误时整条流水线停止
+    fn process(&mut self, input: Self::Input) -> impl Future<Output = Result<Option<Self::Output>, StageError>> + Send;
+
+    /// 上游的消息全部处理完后调用一次
+    fn finish(self) -> impl Future<Output = Result<(), StageError>> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// 同时处理多条消息的阶段，产出按完成先后排列；同时处理多少条由阶段自己控制（如抓取的并发许可）
+pub(crate) trait ConcurrentStage: Send + Sync + 'static {
+    type Input: Send + 'static;
+    type Output: Send + 'static;
+
+    /// 返回 None 表示这条消息没有结果（如抓取已停止）
+    fn process(&self, input: Self::Input) -> impl Future<Output = Option<Self::Output>> + Send;
+}
+
+/// 已启动的一串阶段，从中读取最后一个阶段的输出。丢弃时中止所有阶段
+pub(crate) struct Flow<T> {
+    output: mpsc::Receiver<T>,
+    tasks: JoinSet<Result<(), StageError>>,
+}
+
+impl<T: Send + 'static> Flow<T> {
+    /// 依次送出 items 的起点
+    pub(crate) fn from_iter<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: Send + 'static,
+    {
+        let (tx, output) = mpsc::channel(CHANNEL_CAPACITY);
+        let items = items.into_iter();
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async move {
+            for item in items {
+                if tx.send(item).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        Flow { output, tasks }
+    }
+
+    /// 接上一个按顺序处理的阶段
+    pub(crate) fn then<S: Stage<Input = T>>(self, mut stage: S) -> Flow<S::Output> {
+        let Flow { output: mut input, mut tasks } = self;
+        let (tx, output) = mpsc::channel(CHANNEL_CAPACITY);
+        tasks.spawn(async move {
+            while let Some(message) = input.recv().await {
+                // 下游已经停止时后面的结果没人要了，也不必收尾
+                if let Some(message) = stage.process(message).await? && tx.send(message).await.is_err() {
+                    return Ok(());
+                }
+            }
+            stage.finish().await
+        });
+        Flow { output, tasks }
+    }
+
+    /// 接上一个并发处理的阶段，每条消息在单独的任务中处理
+    pub(crate) fn then_concurrent<S: ConcurrentStage<Input = T>>(self, stage: S) -> Flow<S::Output> {
+        let Flow { output: mut input, mut tasks } = self;
+        let (tx, output) = mpsc::channel(CHANNEL_CAPACITY);
+        let stage = Arc::new(stage);
+        tasks.spawn(async move {
+            let mut running = JoinSet::new();
+            while let Some(message) = input.recv().await {
+                let (stage, tx) = (stage.clone(), tx.clone());
+                running.spawn(async move {
+                    if let Some(message) = stage.process(message).await {
+                        let _ = tx.send(message).await;
+                    }
+                });
+                while let Some(finished) = running.try_join_next() {
+                    finished?;
+                }
+            }
+            while let Some(finished) = running.join_next().await {
+                finished?;
+            }
+            Ok(())
+        });
+        Flow { output, tasks }
+    }
+
+    /// 取下一条输出，所有阶段结束后返回 None
+    pub(crate) async fn recv(&mut self) -> Option<T> {
+        self.output.recv().await
+    }
+
+    /// 立即中止所有阶段，正在处理的消息被丢弃
+    pub(crate) fn abort(&mut self) {
+        self.tasks.abort_all();
+    }
+
+    /// 收集最后一个阶段的全部输出并等待所有阶段结束，任一阶段出错时返回错误
+    pub(crate) async fn collect(mut self) -> Result<Vec<T>, StageError> {
+        let mut items = Vec::new();
+        while let Some(item) = self.output.recv().await {
+            items.push(item);
+        }
+        while let Some(finished) = self.tasks.join_next().await {
+            finished??;
+        }
+        Ok(items)
+    }
+}
+
+/// fetch 阶段的输入：要抓取的一章
+pub(crate) struct ChapterJob {
+    /// 目录中的序号，从0开始
+    pub(crate) index: usize,
+    pub(crate) url: String,
+    /// 拟人模式下模拟从上一章（或目录页）点进来
+    pub(crate) referer: Option<String>,
+}
+
+/// extract：把一个页面的 HTML 解析为章节内容
+pub(crate) trait Extract: Send + Sync {
+    fn extract(&self, html: &str, page_url: &reqwest::Url) -> PageOutcome;
+}
+
+/// transform：就地修改一个抓取成功的章节
+pub(crate) trait Transform: Send {
+    /// 全部章节处理完后输出的一行统计，None 表示不输出
+    fn summary(&self) -> Option<String> {
+        None
+    }
+
+    fn apply(&mut self, result: &mut ChapterResult);
+}
+
+/// sink：按章节顺序接收抓取成功的章节
+pub(crate) trait Sink: Send {
+    fn write(&mut self, result: &ChapterResult) -> Result<(), StageError>;
+
+    /// 开启占位章节时，在失败或付费章节的位置调用，默认不写入任何内容
+    fn write_placeholder(&mut self, _result: &ChapterResult) -> Result<(), StageError> {
+        Ok(())
+    }
+
+    /// 所有章节写入后调用一次，用于落盘和关闭文件
+    fn finish(self: Box<Self>) -> Result<(), StageError>;
+}
+
+/// 按配置在运行时选择的 sink
+impl Sink for Box<dyn Sink> {
+    fn write(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        (**self).write(result)
+    }
+
+    fn write_placeholder(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        (**self).write_placeholder(result)
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), StageError> {
+        (*self).finish()
+    }
+}
+
+/// 读回已转存的正文，读不回来的章节按失败处理
+struct LoadStage {
+    spill: SpillStore,
+}
+
+impl Stage for LoadStage {
+    type Input = ChapterResult;
+    type Output = ChapterResult;
+
+    async fn process(&mut self, mut result: ChapterResult) -> Result<Option<ChapterResult>, StageError> {
+        if let Err(e) = self.spill.load(&mut result) {
+            warn!("第{}章读回正文失败: {}", result.index + 1, e);
+            result.success = false;
+            result.error_msg = Some(format!("Spilled content unreadable: {}", e));
+        }
+        Ok(Some(result))
+    }
+}
+
+/// 一个 transform 组成的阶段，累计它的耗时，结束时与统计一起输出
+struct TransformStage {
+    transform: Box<dyn Transform>,
+    elapsed: Duration,
+}
+
+impl Stage for TransformStage {
+    type Input = ChapterResult;
+    type Output = ChapterResult;
+
+    async fn process(&mut self, mut result: ChapterResult) -> Result<Option<ChapterResult>, StageError> {
+        if result.success {
+            let start = Instant::now();
+            self.transform.apply(&mut result);
+            self.elapsed += start.elapsed();
+        }
+        Ok(Some(result))
+    }
+
+    async fn finish(self) -> Result<(), StageError> {
+        if let Some(summary) = self.transform.summary() {
+            info!("{} ({}ms)", summary, self.elapsed.as_millis());
+        }
+        Ok(())
+    }
+}
+
+/// sink 阶段的输出：写出之后的章节，written 为 false 表示有 sink 写入失败
+pub(crate) struct Written {
+    pub(crate) result: ChapterResult,
+    pub(crate) written: bool,
+}
+
+struct SinkStage {
+    sinks: Vec<Box<dyn Sink>>,
+    placeholders: bool,
+    spill: Option<SpillStore>,
+    total: usize,
+    done: usize,
+}
+
+impl Stage for SinkStage {
+    type Input = ChapterResult;
+    type Output = Written;
+
+    async fn process(&mut self, mut result: ChapterResult) -> Result<Option<Written>, StageError> {
+        let mut written = true;
+        if result.success {
+            result.chars = content_chars(&result.content);
+            for sink in self.sinks.iter_mut() {
+                if let Err(e) = sink.write(&result) {
+                    warn!("第{}章写入失败: {}", result.index + 1, e);
+                    written = false;
+                }
+            }
+        } else if self.placeholders {
+            for sink in self.sinks.iter_mut() {
+                if let Err(e) = sink.write_placeholder(&result) {
+                    warn!("第{}章占位写入失败: {}", result.index + 1, e);
+                }
+            }
+        }
+        if let Some(spill) = &self.spill {
+            spill.unload(&mut result);
+        }
+        self.done += 1;
+        if self.done.is_multiple_of(100) {
+            info!("已写入 {}/{} 章...", self.done, self.total);
+        }
+        Ok(Some(Written { result, written }))
+    }
+
+    async fn finish(self) -> Result<(), StageError> {
+        for sink in self.sinks {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// sink 阶段的写入统计
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SinkStats {
+    pub(crate) success: usize,
+    pub(crate) failed: usize,
+    pub(crate) paywalled: usize,
+}
+
+/// 抓取之后的 transform 与 sink 阶段
+#[derive(Default)]
+pub(crate) struct Pipeline {
+    transforms: Vec<Box<dyn Transform>>,
+    sinks: Vec<Box<dyn Sink>>,
+    placeholders: bool,
+    spill: Option<SpillStore>,
+}
+
+impl Pipeline {
+    pub(crate) fn transform(mut self, stage: impl Transform + 'static) -> Self {
+        self.transforms.push(Box::new(stage));
+        self
+    }
+
+    pub(crate) fn sink(mut self, stage: impl Sink + 'static) -> Self {
+        self.sinks.push(Box::new(stage));
+        self
+    }
+
+    /// 在缺失章节的原位置写入占位章节，让读者知道此处有内容缺失
+    pub(crate) fn placeholders(mut self, enabled: bool) -> Self {
+        self.placeholders = enabled;
+        self
+    }
+
+    /// 正文已转存到磁盘的章节逐章读回，写出后再释放
+    pub(crate) fn spill(mut self, store: Option<SpillStore>) -> Self {
+        self.spill = store;
+        self
+    }
+
+    /// 按序号排好的抓取结果依次经过所有 transform 与 sink，返回写出后的章节（顺序不变）和写入统计
+    pub(crate) async fn run(self, results: Vec<ChapterResult>) -> Result<(Vec<ChapterResult>, SinkStats), StageError> {
+        let Pipeline { transforms, sinks, placeholders, spill } = self;
+        let total = results.len();
+        let mut flow = Flow::from_iter(results);
+        if let Some(spill) = &spill {
+            flow = flow.then(LoadStage { spill: spill.clone() });
+        }
+        for transform in transforms {
+            flow = flow.then(TransformStage { transform, elapsed: Duration::ZERO });
+        }
+        let written = flow.then(SinkStage { sinks, placeholders, spill, total, done: 0 }).collect().await?;
+
+        let mut stats = SinkStats::default();
+        let results = written.into_iter()
+            .map(|Written { result, written }| {
+                if result.success && written {
+                    stats.success += 1;
+                } else if result.paywalled {
+                    stats.paywalled += 1;
+                } else {
+                    stats.failed += 1;
+                }
+                result
+            })
+            .collect();
+        Ok((results, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct AddOne;
+
+    impl Stage for AddOne {
+        type Input = u32;
+        type Output = u32;
+
+        async fn process(&mut self, input: u32) -> Result<Option<u32>, StageError> {
+            Ok(Some(input + 1))
+        }
+    }
+
+    /// 只放行偶数，结束时把处理过的条数记下来
+    struct EvenOnly {
+        seen: usize,
+        total: Arc<Mutex<usize>>,
+    }
+
+    impl Stage for EvenOnly {
+        type Input = u32;
+        type Output = String;
+
+        async fn process(&mut self, input: u32) -> Result<Option<String>, StageError> {
+            self.seen += 1;
+            Ok(input.is_multiple_of(2).then(|| input.to_string()))
+        }
+
+        async fn finish(self) -> Result<(), StageError> {
+            *self.total.lock().unwrap() = self.seen;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn sequential_stages_keep_order() {
+        let total = Arc::new(Mutex::new(0));
+        let output = Flow::from_iter(0..100u32)
+            .then(AddOne)
+            .then(EvenOnly { seen: 0, total: total.clone() })
+            .collect()
+            .await
+            .unwrap();
+        let expected: Vec<String> = (1..=100u32).filter(|n| n % 2 == 0).map(|n| n.to_string()).collect();
+        assert_eq!(output, expected);
+        assert_eq!(*total.lock().unwrap(), 100);
+    }
+
+    /// 序号越小等得越久，输出按完成先后排列
+    struct SlowFirst;
+
+    impl ConcurrentStage for SlowFirst {
+        type Input = u64;
+        type Output = u64;
+
+        async fn process(&self, input: u64) -> Option<u64> {
+            tokio::time::sleep(Duration::from_millis(50 - input * 10)).await;
+            (input != 2).then_some(input)
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_stage_emits_in_completion_order() {
+        let output = Flow::from_iter(0..5u64).then_concurrent(SlowFirst).collect().await.unwrap();
+        assert_eq!(output, vec![4, 3, 1, 0]);
+    }
+
+    struct FailOn(u32);
+
+    impl Stage for FailOn {
+        type Input = u32;
+        type Output = u32;
+
+        async fn process(&mut self, input: u32) -> Result<Option<u32>, StageError> {
+            if input == self.0 { Err(format!("bad input {}", input).into()) } else { Ok(Some(input)) }
+        }
+    }
+
+    #[tokio::test]
+    async fn stage_error_stops_the_flow() {
+        let error = Flow::from_iter(0..1000u32).then(FailOn(3)).then(AddOne).collect().await.unwrap_err();
+        assert_eq!(error.to_string(), "bad input 3");
+    }
+
+    struct Upper {
+        applied: usize,
+    }
+
+    impl Transform for Upper {
+        fn summary(&self) -> Option<String> {
+            Some(format!("upper {}", self.applied))
+        }
+
+        fn apply(&mut self, result: &mut ChapterResult) {
+            for para in &mut result.content {
+                *para = para.to_uppercase();
+            }
+            self.applied += 1;
+        }
+    }
+
+    /// 记录收到的每一章，write_fails 中的章节写入失败
+    struct Recorder {
+        lines: Arc<Mutex<Vec<String>>>,
+        write_fails: Vec<usize>,
+    }
+
+    impl Sink for Recorder {
+        fn write(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+            if self.write_fails.contains(&result.index) {
+                return Err("disk full".into());
+            }
+            self.lines.lock().unwrap().push(format!("{}:{}", result.index, result.content.join("|")));
+            Ok(())
+        }
+
+        fn write_placeholder(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+            self.lines.lock().unwrap().push(format!("{}:missing", result.index));
+            Ok(())
+        }
+
+        fn finish(self: Box<Self>) -> Result<(), StageError> {
+            self.lines.lock().unwrap().push("finished".to_string());
+            Ok(())
+        }
+    }
+
+    fn chapters() -> Vec<ChapterResult> {
+        let now = chrono::Utc::now();
+        let url = |i: usize| format!("https://example.com/{}.html", i);
+        vec![
+            ChapterResult::success(0, "一".into(), url(0), vec!["a".into(), "b".into()], 0, now),
+            ChapterResult::failure(1, url(1), "HTTP 500".into(), 0, now),
+            ChapterResult::paywalled(2, url(2), "VIP", 0, now),
+            ChapterResult::success(3, "四".into(), url(3), vec!["c".into()], 0, now),
+            ChapterResult::success(4, "五".into(), url(4), vec!["d".into()], 0, now),
+        ]
+    }
+
+    #[tokio::test]
+    async fn pipeline_runs_transforms_and_sinks_in_chapter_order() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let (results, stats) = Pipeline::default()
+            .transform(Upper { applied: 0 })
+            .sink(Recorder { lines: lines.clone(), write_fails: vec![4] })
+            .placeholders(true)
+            .run(chapters())
+            .await
+            .unwrap();
+        assert_eq!(*lines.lock().unwrap(), ["0:A|B", "1:missing", "2:missing", "3:C", "finished"]);
+        assert_eq!(stats, SinkStats { success: 2, failed: 2, paywalled: 1 });
+        let indices: Vec<usize> = results.iter().map(|r| r.index).collect();
+        assert_eq!(indices, [0, 1, 2, 3, 4]);
+        assert_eq!(results[0].content, ["A", "B"]);
+        assert_eq!(results[0].chars, 2);
+    }
+
+    #[tokio::test]
+    async fn pipeline_skips_placeholders_unless_enabled() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        Pipeline::default()
+            .sink(Recorder { lines: lines.clone(), write_fails: Vec::new() })
+            .run(chapters())
+            .await
+            .unwrap();
+        assert_eq!(*lines.lock().unwrap(), ["0:a|b", "3:c", "4:d", "finished"]);
+    }
+}
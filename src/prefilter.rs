@@ -0,0 +1,503 @@
+//! 读取章节页时的 HTML 预过滤：边接收响应边丢弃正文容器以外的标记，只把 `<head>` 和选择器可能命中的元素交给完整解析，
+//! 页面夹带几 MB 广告标记时每个任务的内存峰值也只与正文大小相当
+//!
+//! 只处理"简单"选择器：每个选择器（逗号分隔的每一项）的第一段须为 标签、#id、.类名 或它们的组合，如 `#content`、
+//! `div.read-content p`、`h1.title`；含属性、伪类或兄弟组合符（+ ~）时无法判断元素是否需要保留，不做过滤。
+//! 过滤按字节进行，标签语法都是 ASCII，对 GBK、Big5 等编码同样适用；解码仍在过滤之后按原有规则进行。
+//!
+//! 付费标记原本在整页中查找，被丢弃的部分也会逐块检查，出现时以注释的形式附在保留的内容之后。
+
+use regex::Regex;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 内容按原文保留、不解析其中标签的元素
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+const VOID_ELEMENTS: &[&str] = &["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+
+/// 选择器的第一段：要保留的元素
+#[derive(Debug)]
+struct Target {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl Target {
+    /// 取选择器第一段；不是简单形式时返回 None
+    fn parse(selector: &str) -> Option<Self> {
+        static COMPOUND: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^([a-zA-Z][a-zA-Z0-9]*)?((?:[#.][\w-]+)*)$").unwrap());
+        if selector.contains(['+', '~']) {
+            return None;
+        }
+        let first = selector.split(|c: char| c.is_whitespace() || c == '>').find(|part| !part.is_empty())?;
+        let captures = COMPOUND.captures(first)?;
+        let tag = captures.get(1).map(|tag| tag.as_str().to_ascii_lowercase());
+        let mut target = Target { tag, id: None, classes: Vec::new() };
+        let mut parts = captures.get(2).map_or("", |rest| rest.as_str());
+        while !parts.is_empty() {
+            let kind = parts.as_bytes()[0];
+            let end = parts[1..].find(['#', '.']).map_or(parts.len(), |pos| pos + 1);
+            let name = parts[1..end].to_string();
+            if kind == b'#' { target.id = Some(name) } else { target.classes.push(name) }
+            parts = &parts[end..];
+        }
+        // 只有标签名 html / body 时整页都要保留，过滤没有意义
+        if target.id.is_none() && target.classes.is_empty() && matches!(target.tag.as_deref(), None | Some("html" | "body")) {
+            return None;
+        }
+        Some(target)
+    }
+
+    fn matches(&self, tag: &OpenTag) -> bool {
+        self.tag.as_ref().is_none_or(|name| *name == tag.name)
+            && self.id.as_ref().is_none_or(|id| tag.id.as_deref() == Some(id.as_str()))
+            && self.classes.iter().all(|class| tag.classes.iter().any(|c| c == class))
+    }
+}
+
+/// 全部章节页累计的下载量与保留量
+#[derive(Default)]
+pub(crate) struct PrefilterStats {
+    received: AtomicU64,
+    kept: AtomicU64,
+}
+
+impl PrefilterStats {
+    /// (下载字节数, 保留字节数)，尚未过滤过任何页面时返回 None
+    pub(crate) fn totals(&self) -> Option<(u64, u64)> {
+        let received = self.received.load(Ordering::Relaxed);
+        (received > 0).then(|| (received, self.kept.load(Ordering::Relaxed)))
+    }
+}
+
+/// 一套选择器对应的过滤规则
+pub(crate) struct HtmlPrefilter {
+    targets: Vec<Target>,
+    /// 付费标记按 UTF-8、GBK、Big5 编码后的字节，与标记原文一一对应
+    markers: Vec<Vec<Vec<u8>>>,
+    /// 规则的标识，由选择器和付费标记计算，响应缓存据此判断缓存的页面是否按同样的规则过滤
+    key: String,
+    stats: std::sync::Arc<PrefilterStats>,
+}
+
+impl HtmlPrefilter {
+    /// selectors 为章节页中用到的全部选择器，空字符串跳过；任一不是简单形式时返回 None
+    pub(crate) fn new(selectors: &[&str], paywall_markers: &[String], stats: std::sync::Arc<PrefilterStats>) -> Option<Self> {
+        let mut targets = Vec::new();
+        for selector in selectors.iter().filter(|selector| !selector.trim().is_empty()) {
+            for alternative in selector.split(',') {
+                targets.push(Target::parse(alternative.trim())?);
+            }
+        }
+        if targets.is_empty() {
+            return None;
+        }
+        let markers = paywall_markers.iter()
+            .filter(|marker| !marker.is_empty())
+            .map(|marker| {
+                let mut encoded: Vec<Vec<u8>> = [encoding_rs::UTF_8, encoding_rs::GBK, encoding_rs::BIG5]
+                    .into_iter()
+                    .map(|encoding| encoding.encode(marker).0.into_owned())
+                    .collect();
+                encoded.dedup();
+                encoded
+            })
+            .collect();
+        let key = format!("{:x}", md5::compute(format!("{:?}|{:?}", selectors, paywall_markers)));
+        Some(HtmlPrefilter { targets, markers, key, stats })
+    }
+
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn start(&self) -> PrefilterRun<'_> {
+        PrefilterRun {
+            filter: self,
+            pending: Vec::new(),
+            out: Vec::new(),
+            in_head: true,
+            keep: None,
+            raw: None,
+            found: vec![None; self.markers.len()],
+            marker_tail: Vec::new(),
+            received: 0,
+        }
+    }
+}
+
+/// 开始标签中过滤用得到的部分
+struct OpenTag {
+    name: String,
+    id: Option<String>,
+    classes: Vec<String>,
+    self_closing: bool,
+}
+
+fn tag_name(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| !b.is_ascii_alphanumeric() && *b != b'-').unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_ascii_lowercase()
+}
+
+/// 解析 `<name attr="value" ...>`，tag 含首尾的尖括号
+fn parse_open_tag(tag: &[u8]) -> OpenTag {
+    let inner = &tag[1..tag.len() - 1];
+    let name = tag_name(inner);
+    let mut open = OpenTag { name, id: None, classes: Vec::new(), self_closing: inner.ends_with(b"/") };
+    let mut i = open.name.len();
+    while i < inner.len() {
+        while i < inner.len() && (inner[i].is_ascii_whitespace() || inner[i] == b'/') {
+            i += 1;
+        }
+        let start = i;
+        while i < inner.len() && !inner[i].is_ascii_whitespace() && !matches!(inner[i], b'=' | b'/') {
+            i += 1;
+        }
+        let attr = String::from_utf8_lossy(&inner[start..i]).to_ascii_lowercase();
+        while i < inner.len() && inner[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i >= inner.len() || inner[i] != b'=' {
+            if start == i {
+                i += 1;
+            }
+            continue;
+        }
+        i += 1;
+        while i < inner.len() && inner[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        let value = match inner.get(i) {
+            Some(&quote @ (b'"' | b'\'')) => {
+                let end = inner[i + 1..].iter().position(|b| *b == quote).map_or(inner.len(), |pos| i + 1 + pos);
+                let value = &inner[(i + 1).min(end)..end];
+                i = end + 1;
+                value
+            }
+            _ => {
+                let start = i;
+                while i < inner.len() && !inner[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                &inner[start..i]
+            }
+        };
+        let value = String::from_utf8_lossy(value);
+        match attr.as_str() {
+            "id" => open.id = Some(value.trim().to_string()),
+            "class" => open.classes = value.split_whitespace().map(str::to_string).collect(),
+            _ => {}
+        }
+    }
+    open
+}
+
+/// 在 haystack 中查找 needle（ASCII 不区分大小写）
+fn find_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+/// 标签超过这个长度仍未结束，多半是属性值里的引号不配对，改为在第一个 `>` 处结束
+const MAX_TAG_LEN: usize = 8192;
+
+/// 从 `<` 开始找到标签结尾的 `>`，跳过引号中的内容；数据还不完整时返回 None
+fn tag_end(bytes: &[u8]) -> Option<usize> {
+    let mut quote = None;
+    let mut first_gt = None;
+    for (i, &b) in bytes.iter().enumerate().skip(1) {
+        if b == b'>' && first_gt.is_none() {
+            first_gt = Some(i);
+        }
+        match quote {
+            Some(q) if b == q => quote = None,
+            Some(_) => {}
+            None if b == b'"' || b == b'\'' => quote = Some(b),
+            None if b == b'>' => return Some(i),
+            None => {}
+        }
+    }
+    if bytes.len() > MAX_TAG_LEN { first_gt } else { None }
+}
+
+/// 一个页面的过滤过程，按收到的顺序 feed 数据块，最后 finish 取得保留的内容
+pub(crate) struct PrefilterRun<'a> {
+    filter: &'a HtmlPrefilter,
+    /// 尚未处理完的输入（不完整的标签等）
+    pending: Vec<u8>,
+    out: Vec<u8>,
+    /// 还没遇到 `<body>` 或 `</head>`
+    in_head: bool,
+    /// 正在保留的元素：标签名和同名标签的嵌套层数
+    keep: Option<(String, usize)>,
+    /// 正在原文元素（script 等）中：标签名、是否保留其内容
+    raw: Option<(String, bool)>,
+    /// 各付费标记在页面中出现时的字节形式
+    found: Vec<Option<Vec<u8>>>,
+    /// 上一块末尾的几个字节，标记可能跨两块
+    marker_tail: Vec<u8>,
+    received: u64,
+}
+
+impl PrefilterRun<'_> {
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        self.received += chunk.len() as u64;
+        self.scan_markers(chunk);
+        self.pending.extend_from_slice(chunk);
+        let consumed = self.process();
+        self.pending.drain(..consumed);
+    }
+
+    /// 返回保留下来的字节，尚未处理的残余（通常是被截断的标签）在保留状态下原样附上
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        if self.keep.is_some() || self.in_head {
+            let rest = std::mem::take(&mut self.pending);
+            self.out.extend_from_slice(&rest);
+        }
+        for marker in self.found.iter().flatten() {
+            self.out.extend_from_slice(b"<!--");
+            self.out.extend_from_slice(marker);
+            self.out.extend_from_slice(b"-->");
+        }
+        self.filter.stats.received.fetch_add(self.received, Ordering::Relaxed);
+        self.filter.stats.kept.fetch_add(self.out.len() as u64, Ordering::Relaxed);
+        self.out
+    }
+
+    fn scan_markers(&mut self, chunk: &[u8]) {
+        if self.filter.markers.is_empty() {
+            return;
+        }
+        let mut window = std::mem::take(&mut self.marker_tail);
+        window.extend_from_slice(chunk);
+        for (found, encodings) in self.found.iter_mut().zip(&self.filter.markers) {
+            if found.is_none() {
+                *found = encodings.iter().find(|bytes| window.windows(bytes.len()).any(|w| w == bytes.as_slice())).cloned();
+            }
+        }
+        let longest = self.filter.markers.iter().flatten().map(Vec::len).max().unwrap_or(0);
+        let keep_from = window.len().saturating_sub(longest.saturating_sub(1));
+        self.marker_tail = window.split_off(keep_from);
+    }
+
+    fn emitting(&self) -> bool {
+        self.keep.is_some() || self.in_head
+    }
+
+    /// 处理 pending 中能处理的部分，返回已消耗的字节数
+    fn process(&mut self) -> usize {
+        let mut i = 0;
+        let len = self.pending.len();
+        while i < len {
+            if let Some((name, emit)) = self.raw.clone() {
+                let close = format!("</{}", name);
+                match find_ignore_case(&self.pending[i..], close.as_bytes()) {
+                    Some(pos) => {
+                        if emit {
+                            self.out.extend_from_slice(&self.pending[i..i + pos]);
+                        }
+                        i += pos;
+                        self.raw = None;
+                    }
+                    None => {
+                        // 留下可能是结束标签开头的几个字节，其余直接处理掉
+                        let safe = (len - i).saturating_sub(close.len());
+                        if emit {
+                            self.out.extend_from_slice(&self.pending[i..i + safe]);
+                        }
+                        return i + safe;
+                    }
+                }
+                continue;
+            }
+            let Some(lt) = self.pending[i..].iter().position(|b| *b == b'<') else {
+                if self.emitting() {
+                    self.out.extend_from_slice(&self.pending[i..]);
+                }
+                return len;
+            };
+            if self.emitting() {
+                self.out.extend_from_slice(&self.pending[i..i + lt]);
+            }
+            i += lt;
+            let rest = &self.pending[i..];
+            let Some(&next) = rest.get(1) else {
+                return i;
+            };
+            // 不是标签开头的 < 按普通文字处理
+            if !next.is_ascii_alphabetic() && !matches!(next, b'/' | b'!' | b'?') {
+                if self.emitting() {
+                    self.out.push(b'<');
+                }
+                i += 1;
+                continue;
+            }
+            if rest.starts_with(b"<!--") {
+                let Some(end) = rest.windows(3).skip(4).position(|w| w == b"-->") else {
+                    return i;
+                };
+                let end = end + 4 + 3;
+                if self.keep.is_some() {
+                    self.out.extend_from_slice(&rest[..end]);
+                }
+                i += end;
+                continue;
+            }
+            let Some(end) = tag_end(rest) else {
+                return i;
+            };
+            let tag = rest[..=end].to_vec();
+            i += end + 1;
+            self.handle_tag(&tag);
+        }
+        i
+    }
+
+    fn handle_tag(&mut self, tag: &[u8]) {
+        match tag.get(1) {
+            Some(b'/') => {
+                let name = tag_name(&tag[2..]);
+                if let Some((root, depth)) = self.keep.as_mut() {
+                    self.out.extend_from_slice(tag);
+                    if *root == name {
+                        *depth -= 1;
+                        if *depth == 0 {
+                            self.keep = None;
+                        }
+                    }
+                } else if self.in_head {
+                    if name == "head" {
+                        self.in_head = false;
+                    }
+                    if name != "script" && name != "style" {
+                        self.out.extend_from_slice(tag);
+                    }
+                }
+            }
+            Some(b) if b.is_ascii_alphabetic() => {
+                let open = parse_open_tag(tag);
+                let raw = RAW_TEXT_ELEMENTS.contains(&open.name.as_str()) && !open.self_closing;
+                let container = !open.self_closing && !VOID_ELEMENTS.contains(&open.name.as_str());
+                if let Some((root, depth)) = self.keep.as_mut() {
+                    self.out.extend_from_slice(tag);
+                    if *root == open.name && container {
+                        *depth += 1;
+                    }
+                    if raw {
+                        self.raw = Some((open.name, true));
+                    }
+                    return;
+                }
+                if self.in_head && open.name == "body" {
+                    self.in_head = false;
+                    self.out.extend_from_slice(tag);
+                    return;
+                }
+                if self.in_head {
+                    // 头部的脚本和样式与解析无关，常常正是体积最大的部分
+                    let drop = open.name == "script" || open.name == "style";
+                    if !drop {
+                        self.out.extend_from_slice(tag);
+                    }
+                    if raw {
+                        self.raw = Some((open.name, !drop));
+                    }
+                    return;
+                }
+                if self.filter.targets.iter().any(|target| target.matches(&open)) {
+                    self.out.extend_from_slice(tag);
+                    if container {
+                        self.keep = Some((open.name.clone(), 1));
+                    }
+                    if raw {
+                        self.raw = Some((open.name, true));
+                    }
+                } else if raw {
+                    self.raw = Some((open.name, false));
+                }
+            }
+            // <!DOCTYPE>、<?xml ?> 等声明
+            _ => {
+                if self.emitting() {
+                    self.out.extend_from_slice(tag);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html><head><title>第1章 <b>不是标签</b></title>
+<script>var s = "<div id='content'>";</script><style>.a > p { color: red }</style>
+<meta charset="utf-8"></head>
+<body><div class="nav" data-tip="a > b" title='x"y'>导航</div>
+<!-- <div id="content">注释里的假正文</div> -->
+<script>document.write('<div id="content">脚本里的假正文</div>');</script>
+<h1>第1章 开端</h1>
+<div id="content"><div class="inner"><p>第一段</p></div><!-- 正文内注释 --><p>第二段</p><style>p::after { content: "</div>" }</style><p>第三段</p></div>
+<div class="ad">本章为VIP章节，广告</div>
+</body></html>"#;
+
+    fn prefilter() -> HtmlPrefilter {
+        HtmlPrefilter::new(&["h1", "#content p"], &["VIP章节".to_string()], Default::default()).unwrap()
+    }
+
+    fn run(filter: &HtmlPrefilter, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut run = filter.start();
+        for chunk in chunks {
+            run.feed(chunk);
+        }
+        run.finish()
+    }
+
+    #[test]
+    fn keeps_head_and_target_elements_only() {
+        let out = String::from_utf8(run(&prefilter(), &[PAGE.as_bytes()])).unwrap();
+        assert!(out.contains("<title>第1章 <b>不是标签</b></title>"), "{}", out);
+        assert!(out.contains("<h1>第1章 开端</h1>"));
+        assert!(out.contains(r#"<div id="content"><div class="inner"><p>第一段</p></div><!-- 正文内注释 --><p>第二段</p>"#));
+        assert!(out.contains(r#"<p>第三段</p></div>"#));
+        for dropped in ["var s", "color: red", "导航", "假正文", "广告"] {
+            assert!(!out.contains(dropped), "{} 应被丢弃: {}", dropped, out);
+        }
+        assert!(out.ends_with("<!--VIP章节-->"));
+    }
+
+    #[test]
+    fn any_chunk_boundary_gives_same_output() {
+        let filter = prefilter();
+        let bytes = PAGE.as_bytes();
+        let whole = run(&filter, &[bytes]);
+        for split in 1..bytes.len() {
+            assert_eq!(run(&filter, &[&bytes[..split], &bytes[split..]]), whole, "在第 {} 字节处分块", split);
+        }
+        let single_bytes: Vec<&[u8]> = bytes.chunks(1).collect();
+        assert_eq!(run(&filter, &single_bytes), whole);
+    }
+
+    #[test]
+    fn nested_containers_with_same_name_close_at_matching_tag() {
+        let filter = HtmlPrefilter::new(&["div.read"], &[], Default::default()).unwrap();
+        let page = r#"<body><div class="read"><div><div>甲</div></div><div>乙</div></div><div>丙</div></body>"#;
+        let out = String::from_utf8(run(&filter, &[page.as_bytes()])).unwrap();
+        assert_eq!(out, r#"<body><div class="read"><div><div>甲</div></div><div>乙</div></div>"#);
+    }
+
+    #[test]
+    fn gbk_page_split_inside_multibyte_characters() {
+        let filter = prefilter();
+        let (bytes, _, _) = encoding_rs::GBK.encode(PAGE);
+        let whole = run(&filter, &[&bytes]);
+        for split in (1..bytes.len()).step_by(7) {
+            assert_eq!(run(&filter, &[&bytes[..split], &bytes[split..]]), whole);
+        }
+        let (out, _, _) = encoding_rs::GBK.decode(&whole);
+        assert!(out.contains("<p>第二段</p>"));
+        assert!(!out.contains("导航"));
+        assert!(out.ends_with("<!--VIP章节-->"));
+    }
+}
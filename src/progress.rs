@@ -0,0 +1,82 @@
+//! 终端进度条：抓取阶段显示已完成章节数（含速度和预计剩余时间）与进行中的请求数，
+//! 替代每 100 章输出一次的剩余章节数。章节日志打印在进度条上方，不会被重绘覆盖。
+//!
+//! 输出不是终端（重定向到文件、管道）或指定了 --no-progress 时不显示进度条，日志照常逐行输出。
+
+use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 正在显示的进度条，日志输出时据此打印在进度条上方；批量模式不显示进度条，同一时间最多一个
+static ACTIVE: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+#[derive(Clone)]
+pub(crate) struct ProgressDisplay {
+    chapters: ProgressBar,
+    in_flight: ProgressBar,
+}
+
+impl ProgressDisplay {
+    /// done 为从断点恢复、无需抓取的章节数，不计入速度和预计剩余时间
+    pub(crate) fn new(total: usize, done: usize) -> Option<Self> {
+        if !std::io::stderr().is_terminal() {
+            return None;
+        }
+        let multi = MultiProgress::new();
+        let chapters = multi.add(ProgressBar::new(total as u64).with_position(done as u64));
+        chapters.set_style(
+            ProgressStyle::with_template("{elapsed_precise} [{bar:40.cyan/blue}] {pos}/{len} 章  {rate}  预计剩余 {eta}")
+                .unwrap()
+                .with_key("rate", |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                    let _ = write!(w, "{:.1} 章/秒", state.per_sec());
+                })
+                .progress_chars("=> "),
+        );
+        chapters.reset_eta();
+        let in_flight = multi.add(ProgressBar::new_spinner());
+        in_flight.set_style(ProgressStyle::with_template("{spinner} 进行中的请求: {pos}").unwrap());
+        // 长时间没有章节完成时也要刷新耗时和转圈，表明程序仍在运行
+        chapters.enable_steady_tick(Duration::from_millis(500));
+        in_flight.enable_steady_tick(Duration::from_millis(200));
+        *ACTIVE.lock().unwrap() = Some(multi);
+        Some(ProgressDisplay { chapters, in_flight })
+    }
+
+    /// 一章处理完毕（成功、失败或付费）
+    pub(crate) fn chapter_done(&self) {
+        self.chapters.inc(1);
+    }
+
+    /// 记一个进行中的请求，返回值释放（包括任务被中止）时计数减一
+    pub(crate) fn request(&self) -> InFlight {
+        self.in_flight.inc(1);
+        InFlight(self.in_flight.clone())
+    }
+
+    /// 收起进度条，之后的输出不再需要绕开进度条
+    pub(crate) fn finish(&self) {
+        ACTIVE.lock().unwrap().take();
+        self.chapters.abandon();
+        self.in_flight.finish_and_clear();
+    }
+}
+
+pub(crate) struct InFlight(ProgressBar);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec(1);
+    }
+}
+
+/// 显示进度条时把一行日志打印在进度条上方并返回 true，没有进度条时返回 false
+pub(crate) fn print_above(line: &str) -> bool {
+    match ACTIVE.lock().unwrap().as_ref() {
+        Some(multi) => {
+            let _ = multi.println(line);
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,236 @@
+//! robots.txt 合规模式：按站点的 robots.txt 跳过禁止抓取的地址，并遵守 Crawl-delay
+//!
+//! 规则按 RFC 9309 匹配：取最长的匹配规则，Allow 与 Disallow 一样长时以 Allow 为准；
+//! 支持 `*` 通配和结尾的 `$`。只读取 User-agent 为 `rust_crawler` 的组，没有时读取 `*` 组。
+
+use crate::{Identity, RateLimiter, read_html};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+const USER_AGENT_TOKEN: &str = "rust_crawler";
+
+/// 一条 Allow / Disallow 规则
+struct Rule {
+    allow: bool,
+    /// 原始路径长度，用于比较哪条规则更具体
+    len: usize,
+    pattern: Regex,
+}
+
+/// robots.txt 中以 User-agent 行开头的一组规则
+#[derive(Default)]
+struct Group {
+    agents: Vec<String>,
+    /// (是否为 Allow, 路径)
+    rules: Vec<(bool, String)>,
+    crawl_delay: Option<f64>,
+}
+
+/// 一个站点的 robots.txt 中适用于本爬虫的部分
+struct HostRobots {
+    rules: Vec<Rule>,
+    /// 按 Crawl-delay 对该站点的请求限速
+    limiter: Option<RateLimiter>,
+}
+
+impl HostRobots {
+    fn parse(text: &str) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        let mut in_rules = true;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    // 连续的 User-agent 行属于同一组，规则之后再出现则开始新的一组
+                    if in_rules {
+                        groups.push(Group::default());
+                        in_rules = false;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_ascii_lowercase());
+                    }
+                }
+                "allow" | "disallow" => {
+                    in_rules = true;
+                    // 空的 Disallow 表示不限制
+                    if let Some(group) = groups.last_mut() && !value.is_empty() {
+                        group.rules.push((key.trim().eq_ignore_ascii_case("allow"), value.to_string()));
+                    }
+                }
+                "crawl-delay" => {
+                    in_rules = true;
+                    if let Some(group) = groups.last_mut() {
+                        group.crawl_delay = value.parse::<f64>().ok().filter(|secs| secs.is_finite() && *secs > 0.0);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let specific = groups.iter().any(|group| group.agents.iter().any(|a| a == USER_AGENT_TOKEN));
+        let wanted = if specific { USER_AGENT_TOKEN } else { "*" };
+        let mut rules = Vec::new();
+        let mut crawl_delay = None;
+        for group in groups.into_iter().filter(|group| group.agents.iter().any(|a| a == wanted)) {
+            crawl_delay = crawl_delay.or(group.crawl_delay);
+            rules.extend(group.rules.into_iter().filter_map(|(allow, path)| {
+                let anchored = path.ends_with('$');
+                let body = path.trim_end_matches('$');
+                let regex = body.split('*').map(regex::escape).collect::<Vec<_>>().join(".*");
+                let pattern = Regex::new(&format!("^{}{}", regex, if anchored { "$" } else { "" })).ok()?;
+                Some(Rule { allow, len: path.len(), pattern })
+            }));
+        }
+        let limiter = crawl_delay.and_then(|secs| {
+            let delay_ms = (secs * 1000.0) as u64;
+            RateLimiter::new(delay_ms, delay_ms)
+        });
+        HostRobots { rules, limiter }
+    }
+
+    /// 禁止抓取所有地址，robots.txt 因服务器错误无法读取时使用
+    fn disallow_all() -> Self {
+        HostRobots {
+            rules: vec![Rule { allow: false, len: 1, pattern: Regex::new("^/").unwrap() }],
+            limiter: None,
+        }
+    }
+
+    fn allows(&self, path: &str) -> bool {
+        self.rules.iter()
+            .filter(|rule| rule.pattern.is_match(path))
+            .max_by_key(|rule| (rule.len, rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+}
+
+/// 已读取的各站点 robots.txt，按 协议://域名:端口 区分
+#[derive(Default)]
+pub(crate) struct RobotsPolicy {
+    hosts: Mutex<HashMap<String, Arc<HostRobots>>>,
+}
+
+impl RobotsPolicy {
+    fn host(&self, url: &reqwest::Url) -> Option<Arc<HostRobots>> {
+        self.hosts.lock().unwrap().get(&url.origin().ascii_serialization()).cloned()
+    }
+
+    /// 读取地址所在站点的 robots.txt，同一站点只读取一次。
+    /// 404 等客户端错误视为没有限制；服务器错误或无法连接时按 RFC 9309 视为全部禁止
+    pub(crate) async fn load(&self, identity: &Identity, url: &reqwest::Url) {
+        let origin = url.origin().ascii_serialization();
+        if self.hosts.lock().unwrap().contains_key(&origin) {
+            return;
+        }
+        let robots_url = format!("{}/robots.txt", origin);
+        identity.throttle(&robots_url).await;
+        let robots = match identity.get_with_accept(&robots_url, "text/plain").send().await {
+            Ok(resp) if resp.status().is_success() => match read_html(resp).await {
+                Ok(text) => HostRobots::parse(&text),
+                Err(e) => {
+                    warn!("{} 读取失败，视为禁止抓取: {}", robots_url, e);
+                    HostRobots::disallow_all()
+                }
+            },
+            Ok(resp) if resp.status().is_client_error() => HostRobots::parse(""),
+            Ok(resp) => {
+                warn!("{} 返回 HTTP {}，视为禁止抓取", robots_url, resp.status());
+                HostRobots::disallow_all()
+            }
+            Err(e) => {
+                warn!("{} 请求失败，视为禁止抓取: {}", robots_url, e);
+                HostRobots::disallow_all()
+            }
+        };
+        let delay = robots.limiter.as_ref().map(|limiter| format!("，Crawl-delay {}ms", limiter.max_delay_ms)).unwrap_or_default();
+        info!("已读取 {}: {} 条规则{}", robots_url, robots.rules.len(), delay);
+        self.hosts.lock().unwrap().insert(origin, Arc::new(robots));
+    }
+
+    /// 尚未读取 robots.txt 的站点视为允许
+    pub(crate) fn allows(&self, url: &str) -> bool {
+        let Ok(url) = reqwest::Url::parse(url) else {
+            return true;
+        };
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        self.host(&url).is_none_or(|robots| robots.allows(&path))
+    }
+
+    /// 按站点的 Crawl-delay 等待，与全局和 [hosts] 中的限速叠加
+    pub(crate) async fn throttle(&self, url: &str) {
+        let robots = reqwest::Url::parse(url).ok().and_then(|url| self.host(&url));
+        if let Some(limiter) = robots.as_ref().and_then(|robots| robots.limiter.as_ref()) {
+            limiter.wait().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specific_group_replaces_wildcard_group() {
+        let robots = HostRobots::parse("\
+User-agent: *
+Disallow: /
+
+User-agent: Googlebot
+User-agent: Rust_Crawler
+Disallow: /vip/
+Crawl-delay: 1.5
+");
+        assert!(robots.allows("/book/1.html"));
+        assert!(!robots.allows("/vip/1.html"));
+        assert_eq!(robots.limiter.as_ref().map(|limiter| limiter.max_delay_ms), Some(1500));
+
+        let robots = HostRobots::parse("User-agent: Googlebot\nDisallow: /\n\nUser-agent: *\nDisallow: /search\n");
+        assert!(robots.allows("/book/1.html"));
+        assert!(!robots.allows("/search?q=1"));
+        assert!(robots.limiter.is_none());
+    }
+
+    #[test]
+    fn rules_after_group_start_a_new_group() {
+        let robots = HostRobots::parse("User-agent: other\nDisallow: /a\nUser-agent: *\nDisallow: /b\n");
+        assert!(robots.allows("/a"));
+        assert!(!robots.allows("/b"));
+    }
+
+    #[test]
+    fn longest_match_wins_and_allow_breaks_ties() {
+        let robots = HostRobots::parse("User-agent: *\nDisallow: /book/\nAllow: /book/free/\nAllow: /page\nDisallow: /page\n");
+        assert!(!robots.allows("/book/vip.html"));
+        assert!(robots.allows("/book/free/1.html"));
+        assert!(robots.allows("/page1.html"));
+        assert!(robots.allows("/"));
+    }
+
+    #[test]
+    fn wildcards_and_end_anchor() {
+        let robots = HostRobots::parse("User-agent: *\nDisallow: /*.php$\nDisallow: /tmp*/cache\nDisallow: /a+b\n");
+        assert!(!robots.allows("/index.php"));
+        assert!(robots.allows("/index.php?id=1"));
+        assert!(!robots.allows("/tmp123/cache/x"));
+        assert!(robots.allows("/tmp/other"));
+        assert!(!robots.allows("/a+b/1"));
+        assert!(robots.allows("/aab/1"));
+    }
+
+    #[test]
+    fn empty_disallow_allows_everything() {
+        let robots = HostRobots::parse("User-agent: *\nDisallow:\n# Disallow: /\n");
+        assert!(robots.rules.is_empty());
+        assert!(robots.allows("/anything"));
+        assert!(HostRobots::parse("").allows("/"));
+        assert!(!HostRobots::disallow_all().allows("/robots-less"));
+    }
+}
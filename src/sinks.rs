@@ -0,0 +1,196 @@
+//! 逐章追加写入的 txt 和 Markdown 输出，以及各种输出格式共用的失败、付费章节占位说明
+
+use crate::pipeline::{Sink, StageError};
+use crate::text_index::ChapterIndex;
+use crate::{ChapterResult, FsyncPolicy, PARTIAL_MARKER};
+use std::fs::File;
+use std::io::Write;
+use tracing::debug;
+
+/// 失败或付费章节在输出中的占位说明，成功的章节返回 None
+pub(crate) fn placeholder_text(result: &ChapterResult) -> Option<String> {
+    if result.success {
+        None
+    } else if result.paywalled {
+        Some(format!("【本章为付费章节，未抓取: {}】", result.url))
+    } else {
+        Some(format!("【本章抓取失败: {}】", result.url))
+    }
+}
+
+/// 占位章节在目录中的标题，有目录页中的章节名时使用章节名
+pub(crate) fn placeholder_title(result: &ChapterResult) -> String {
+    let reason = if result.paywalled { "付费章节" } else { "抓取失败" };
+    match &result.catalog_title {
+        Some(title) => format!("{}（{}）", title, reason),
+        None => format!("第{}章（{}）", result.index + 1, reason),
+    }
+}
+
+/// 逐章追加写入的输出文件，txt 和 markdown 共用：按 fsync 策略落盘并维护章节索引
+pub(crate) struct FileOutput {
+    pub(crate) file: File,
+    pub(crate) fsync: FsyncPolicy,
+    pub(crate) index: Option<ChapterIndex>,
+}
+
+impl FileOutput {
+    fn chapter(&mut self, result: &ChapterResult, text: &str) -> std::io::Result<()> {
+        self.file.write_all(text.as_bytes())?;
+        if let Some(index) = &mut self.index {
+            index.chapter(result, text.len());
+        }
+        if self.fsync == FsyncPolicy::PerChapter {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// 不属于任何章节的内容（书名、占位说明），只推进索引中的偏移
+    fn other(&mut self, text: &str) -> std::io::Result<()> {
+        self.file.write_all(text.as_bytes())?;
+        if let Some(index) = &mut self.index {
+            index.skip(text.len());
+        }
+        Ok(())
+    }
+
+    /// 写入结束后按 fsync 策略把输出文件落盘并关闭，保存章节索引
+    fn finish(mut self) -> Result<(), StageError> {
+        self.file.flush()?;
+        if self.fsync != FsyncPolicy::None {
+            self.file.sync_all()?;
+        }
+        if let Some(index) = &self.index {
+            index.save()?;
+        }
+        Ok(())
+    }
+}
+
+/// 纯文本输出：每章标题一行，随后每段一行
+pub(crate) struct TextSink(pub(crate) FileOutput);
+
+impl Sink for TextSink {
+    fn write(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        debug!("第{}章: {}", result.index + 1, result.title);
+        let mut output = String::new();
+        output.push_str(&result.title);
+        output.push('\n');
+        if result.partial {
+            output.push_str(PARTIAL_MARKER);
+            output.push('\n');
+        }
+        for para in &result.content {
+            output.push_str(para);
+            output.push('\n');
+        }
+        Ok(self.0.chapter(result, &output)?)
+    }
+
+    fn write_placeholder(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        if let Some(notice) = placeholder_text(result) {
+            self.0.other(&format!("{}\n", notice))?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), StageError> {
+        self.0.finish()
+    }
+}
+
+/// Markdown 输出：书名为一级标题，每章标题为二级标题，段落之间空一行
+pub(crate) struct MarkdownSink(FileOutput);
+
+impl MarkdownSink {
+    pub(crate) fn create(output: FileOutput, title: &str) -> std::io::Result<Self> {
+        let mut sink = MarkdownSink(output);
+        if !title.is_empty() {
+            sink.0.other(&format!("# {}\n\n", markdown_escape(title)))?;
+        }
+        Ok(sink)
+    }
+}
+
+/// 转义会被当成 Markdown 语法的字符，行首的 #、>、- 等也不会被解析成标题、引用或列表
+fn markdown_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#' | '|') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    // 行首的 "1. "、"- "、"+ " 会变成列表
+    let digits = out.chars().take_while(char::is_ascii_digit).count();
+    if out.starts_with(['-', '+', '=']) || (digits > 0 && out[digits..].starts_with(['.', ')'])) {
+        out.insert(digits, '\\');
+    }
+    out
+}
+
+impl Sink for MarkdownSink {
+    fn write(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        debug!("第{}章: {}", result.index + 1, result.title);
+        let mut output = format!("## {}\n\n", markdown_escape(&result.title));
+        if result.partial {
+            output.push_str(&format!("> {}\n\n", PARTIAL_MARKER));
+        }
+        // 空段落在 Markdown 中本来就是段落分隔，不再单独输出
+        for para in result.content.iter().filter(|p| !p.trim().is_empty()) {
+            output.push_str(&markdown_escape(para));
+            output.push_str("\n\n");
+        }
+        Ok(self.0.chapter(result, &output)?)
+    }
+
+    fn write_placeholder(&mut self, result: &ChapterResult) -> Result<(), StageError> {
+        if let Some(notice) = placeholder_text(result) {
+            self.0.other(&format!("> {}\n\n", markdown_escape(&notice)))?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), StageError> {
+        self.0.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_escape_neutralizes_syntax() {
+        assert_eq!(markdown_escape("普通的一段话"), "普通的一段话");
+        assert_eq!(markdown_escape("*重点* 和 [链接](x)"), "\\*重点\\* 和 \\[链接\\](x)");
+        assert_eq!(markdown_escape("# 不是标题"), "\\# 不是标题");
+        assert_eq!(markdown_escape("> 不是引用"), "\\> 不是引用");
+        assert_eq!(markdown_escape("- 不是列表"), "\\- 不是列表");
+        assert_eq!(markdown_escape("12. 不是列表"), "12\\. 不是列表");
+        assert_eq!(markdown_escape("2024年"), "2024年");
+        assert_eq!(markdown_escape(""), "");
+    }
+
+    #[test]
+    fn markdown_sink_writes_chapters_and_placeholders() {
+        let path = std::env::temp_dir().join(format!("rust_crawler_markdown_{}.md", std::process::id()));
+        let output = FileOutput { file: File::create(&path).unwrap(), fsync: FsyncPolicy::None, index: None };
+        let mut sink: Box<dyn Sink> = Box::new(MarkdownSink::create(output, "书名").unwrap());
+        let now = chrono::Utc::now();
+        let content = vec!["第一段".to_string(), String::new(), "- 第二段".to_string()];
+        sink.write(&ChapterResult::success(0, "第一章".to_string(), "https://example.com/0.html".to_string(), content, 0, now)).unwrap();
+        sink.write_placeholder(&ChapterResult::paywalled(1, "https://example.com/1.html".to_string(), "VIP", 0, now)).unwrap();
+        sink.write_placeholder(&ChapterResult::failure(2, "https://example.com/2.html".to_string(), "HTTP 500".to_string(), 0, now)).unwrap();
+        sink.finish().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# 书名\n\n## 第一章\n\n第一段\n\n\\- 第二段\n\n\
+             > 【本章为付费章节，未抓取: https://example.com/1.html】\n\n\
+             > 【本章抓取失败: https://example.com/2.html】\n\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
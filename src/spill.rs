@@ -0,0 +1,206 @@
+//! 章节正文转存：抓到一章就把正文写入临时目录，内存中只保留标题、地址等元数据，
+//! 写出阶段再逐章读回，几千章的长篇也不会把全部正文堆在内存里
+//!
+//! 目录在断点续抓时保留，配合断点文件只记录元数据即可恢复已抓到的章节。
+//!
+//! 配置了正文仓库（output.store_dir）时，转存目录中每章只记一个内容哈希，正文按哈希压缩保存在仓库里：
+//! 重抓同一本书、从镜像站抓到相同内容时不会重复占用磁盘，写出时按哈希取回。
+
+use crate::ChapterResult;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// 按内容寻址的正文仓库：以正文的 md5 命名、gzip 压缩保存，多次运行、多本书之间共享，不会被自动清理
+pub(crate) struct BlobStore {
+    dir: PathBuf,
+    written: AtomicUsize,
+    reused: AtomicUsize,
+}
+
+impl BlobStore {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        BlobStore { dir, written: AtomicUsize::new(0), reused: AtomicUsize::new(0) }
+    }
+
+    /// 按哈希前两位分子目录，避免单个目录下文件过多。hash 须是 put 生成或经 [`is_valid_hash`] 校验过的
+    fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(&hash[..2]).join(format!("{}.json.gz", hash))
+    }
+
+    /// 保存正文并返回其哈希，仓库中已有相同内容时不再写入
+    fn put(&self, content: &[String]) -> io::Result<String> {
+        let json = serde_json::to_vec(content)?;
+        let hash = format!("{:x}", md5::compute(&json));
+        let path = self.path(&hash);
+        if path.is_file() {
+            self.reused.fetch_add(1, Ordering::Relaxed);
+            return Ok(hash);
+        }
+        std::fs::create_dir_all(path.parent().unwrap_or(&self.dir))?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&json)?;
+        write_atomic(&path, &encoder.finish()?)?;
+        self.written.fetch_add(1, Ordering::Relaxed);
+        Ok(hash)
+    }
+
+    fn get(&self, hash: &str) -> io::Result<Vec<String>> {
+        let mut json = Vec::new();
+        GzDecoder::new(std::fs::File::open(self.path(hash))?).read_to_end(&mut json)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+/// 先写临时文件再改名，并发写入同一文件或中途退出都不会留下半个文件
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let part = path.with_extension("part");
+    std::fs::write(&part, bytes)?;
+    std::fs::rename(&part, path)
+}
+
+/// 正文仓库的哈希是 32 位十六进制的 md5，引用文件损坏或被改动时不符合
+fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 32 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[derive(Clone)]
+pub(crate) struct SpillStore {
+    dir: PathBuf,
+    blobs: Option<Arc<BlobStore>>,
+}
+
+impl SpillStore {
+    pub(crate) fn new(dir: PathBuf, blobs: Option<BlobStore>) -> Self {
+        SpillStore { dir, blobs: blobs.map(Arc::new) }
+    }
+
+    /// 本次运行写入仓库的新正文数和复用的已有正文数
+    pub(crate) fn blob_stats(&self) -> Option<(usize, usize)> {
+        let blobs = self.blobs.as_ref()?;
+        Some((blobs.written.load(Ordering::Relaxed), blobs.reused.load(Ordering::Relaxed)))
+    }
+
+    /// 抓取开始前调用：keep 为 false 时清掉上次运行留下的文件
+    pub(crate) fn prepare(&self, keep: bool) -> io::Result<()> {
+        if !keep && self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir)?;
+        }
+        std::fs::create_dir_all(&self.dir)
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("{:05}.json", index + 1))
+    }
+
+    /// 使用正文仓库时每章只记录哈希
+    fn ref_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("{:05}.ref", index + 1))
+    }
+
+    /// 读取一章记录的哈希，内容不是合法哈希时按读取失败处理
+    fn read_ref(&self, index: usize) -> io::Result<String> {
+        let hash = std::fs::read_to_string(self.ref_path(index))?;
+        let hash = hash.trim();
+        if !is_valid_hash(hash) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("第{}章的正文引用已损坏", index + 1)));
+        }
+        Ok(hash.to_string())
+    }
+
+    /// 引用文件损坏时视为没有转存，续抓时重新抓取这一章
+    pub(crate) fn contains(&self, index: usize) -> bool {
+        match &self.blobs {
+            Some(blobs) => self.read_ref(index).is_ok_and(|hash| blobs.path(&hash).is_file()),
+            None => self.path(index).is_file(),
+        }
+    }
+
+    /// 把抓取成功的章节正文写入磁盘并从内存中释放
+    pub(crate) fn stash(&self, result: &mut ChapterResult) -> io::Result<()> {
+        if !result.success || result.spilled {
+            return Ok(());
+        }
+        match &self.blobs {
+            Some(blobs) => write_atomic(&self.ref_path(result.index), blobs.put(&result.content)?.as_bytes())?,
+            None => write_atomic(&self.path(result.index), &serde_json::to_vec(&result.content)?)?,
+        }
+        result.content = Vec::new();
+        result.spilled = true;
+        Ok(())
+    }
+
+    pub(crate) fn read(&self, index: usize) -> io::Result<Vec<String>> {
+        match &self.blobs {
+            Some(blobs) => blobs.get(&self.read_ref(index)?),
+            None => Ok(serde_json::from_slice(&std::fs::read(self.path(index))?)?),
+        }
+    }
+
+    /// 读回已转存的正文，之后可以像普通章节一样处理
+    pub(crate) fn load(&self, result: &mut ChapterResult) -> io::Result<()> {
+        if result.spilled {
+            result.content = self.read(result.index)?;
+            result.spilled = false;
+        }
+        Ok(())
+    }
+
+    /// 写出后再次释放正文，磁盘上的文件保留到整个运行结束
+    pub(crate) fn unload(&self, result: &mut ChapterResult) {
+        if result.success && self.contains(result.index) {
+            result.content = Vec::new();
+            result.spilled = true;
+        }
+    }
+
+    /// 只删除本次运行的转存目录，正文仓库保留
+    pub(crate) fn remove(&self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> (SpillStore, PathBuf) {
+        let root = std::env::temp_dir().join(format!("rust_crawler_spill_{}_{}", name, std::process::id()));
+        let store = SpillStore::new(root.join("chapters"), Some(BlobStore::new(root.join("store"))));
+        store.prepare(false).unwrap();
+        (store, root)
+    }
+
+    #[test]
+    fn stashed_content_round_trips_through_blob_store() {
+        let (store, root) = store("round_trip");
+        let content = vec!["第一段".to_string(), "第二段".to_string()];
+        let mut result = ChapterResult::success(0, "第1章".into(), "https://example.com/1.html".into(), content.clone(), 0, chrono::Utc::now());
+        store.stash(&mut result).unwrap();
+        assert!(result.spilled && result.content.is_empty());
+        assert!(store.contains(0));
+        store.load(&mut result).unwrap();
+        assert_eq!(result.content, content);
+        let leftovers = std::fs::read_dir(root.join("chapters")).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "part"))
+            .count();
+        assert_eq!(leftovers, 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn damaged_ref_is_a_miss() {
+        let (store, root) = store("damaged");
+        // 截断、非十六进制、多字节字符开头（按字节切片会 panic）
+        for (index, hash) in ["", "a", "zz0123456789abcdef0123456789abcd", "aé0123456789abcdef0123456789abc"].iter().enumerate() {
+            std::fs::write(store.ref_path(index), hash).unwrap();
+            assert!(!store.contains(index));
+            assert_eq!(store.read(index).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,52 @@
+//! 文本输出的章节索引：记录每章在输出文件中的字节偏移和长度，阅读脚本可以直接 seek 到某一章，不必从头扫描整个文件
+//!
+//! 索引为 JSON，偏移按最终输出文件（UTF-8）的字节计：
+//!
+//! ```json
+//! {"file": "book.txt", "chapters": [{"index": 1, "title": "第一章", "offset": 0, "length": 5123}]}
+//! ```
+
+use crate::{ChapterResult, output_path};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct IndexEntry {
+    /// 章节序号，从1开始
+    index: usize,
+    title: String,
+    offset: u64,
+    length: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ChapterIndex {
+    #[serde(skip)]
+    path: String,
+    /// 索引对应的输出文件
+    file: String,
+    chapters: Vec<IndexEntry>,
+    #[serde(skip)]
+    offset: u64,
+}
+
+impl ChapterIndex {
+    /// 索引写到 path，对应输出文件 file；path 为空时返回 None
+    pub(crate) fn new(path: &str, file: &str) -> Option<Self> {
+        (!path.is_empty()).then(|| ChapterIndex { path: path.to_string(), file: file.to_string(), chapters: Vec::new(), offset: 0 })
+    }
+
+    /// 记录一章写出的字节数
+    pub(crate) fn chapter(&mut self, result: &ChapterResult, length: usize) {
+        self.chapters.push(IndexEntry { index: result.index + 1, title: result.title.clone(), offset: self.offset, length: length as u64 });
+        self.offset += length as u64;
+    }
+
+    /// 跳过不属于任何章节的内容（书名、占位行等）
+    pub(crate) fn skip(&mut self, length: usize) {
+        self.offset += length as u64;
+    }
+
+    pub(crate) fn save(&self) -> std::io::Result<()> {
+        std::fs::write(output_path(&self.path), serde_json::to_string_pretty(self)?)
+    }
+}
@@ -0,0 +1,130 @@
+//! 汇总中的时长、大小和计数按输出语言（output.language）格式化：中文、日文用本地时间单位，
+//! 德语、法语等使用小数逗号和各自的千位分隔符，其余语言按英文习惯
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UnitFormat {
+    decimal: char,
+    group: char,
+    /// 小时、分、秒、毫秒的写法
+    units: [&'static str; 4],
+}
+
+impl UnitFormat {
+    pub(crate) fn for_language(language: &str) -> Self {
+        let primary = language.split(['-', '_']).next().unwrap_or_default().to_ascii_lowercase();
+        let (decimal, group) = match primary.as_str() {
+            "de" | "es" | "it" | "pt" | "nl" | "id" | "tr" | "da" | "vi" => (',', '.'),
+            "fr" | "ru" | "uk" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "no" => (',', '\u{202f}'),
+            _ => ('.', ','),
+        };
+        let units = match primary.as_str() {
+            "zh" => ["小时", "分", "秒", "毫秒"],
+            "ja" => ["時間", "分", "秒", "ミリ秒"],
+            _ => ["h", "m", "s", "ms"],
+        };
+        UnitFormat { decimal, group, units }
+    }
+
+    /// 保留一位小数
+    fn decimal1(&self, value: f64) -> String {
+        format!("{:.1}", value).replace('.', &self.decimal.to_string())
+    }
+
+    /// 带千位分隔符的整数，如 12,345
+    pub(crate) fn count(&self, value: usize) -> String {
+        let digits = value.to_string();
+        let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                out.push(self.group);
+            }
+            out.push(digit);
+        }
+        out
+    }
+
+    /// 按量级取两级单位：1h02m、3m05s、42s、4.2s、350ms
+    pub(crate) fn duration(&self, duration: Duration) -> String {
+        let [h, m, s, ms] = self.units;
+        let secs = duration.as_secs();
+        if secs >= 3600 {
+            format!("{}{}{:02}{}", secs / 3600, h, secs % 3600 / 60, m)
+        } else if secs >= 60 {
+            format!("{}{}{:02}{}", secs / 60, m, secs % 60, s)
+        } else if secs >= 10 || duration.as_secs_f64() >= 9.95 {
+            // 9.95s 以上保留一位小数会进位成 "10.0"，与整数秒的写法不一致
+            format!("{}{}", secs.max(10), s)
+        } else if duration.as_millis() >= 1000 {
+            format!("{}{}", self.decimal1(duration.as_secs_f64()), s)
+        } else {
+            format!("{}{}", duration.as_millis(), ms)
+        }
+    }
+
+    pub(crate) fn duration_ms(&self, ms: u64) -> String {
+        self.duration(Duration::from_millis(ms))
+    }
+
+    /// 字节数按 1024 进位：512 B、13.4 MB
+    pub(crate) fn bytes(&self, bytes: u64) -> String {
+        const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+        if bytes < 1024 {
+            return format!("{} B", bytes);
+        }
+        let mut value = bytes as f64 / 1024.0;
+        let mut unit = 0;
+        // 按一位小数进位后到 1024 的也换用下一级单位，不会出现 "1024.0 KB"
+        while value >= 1023.95 && unit + 1 < UNITS.len() {
+            value /= 1024.0;
+            unit += 1;
+        }
+        format!("{} {}", self.decimal1(value), UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_group_thousands() {
+        let en = UnitFormat::for_language("en");
+        assert_eq!(en.count(0), "0");
+        assert_eq!(en.count(999), "999");
+        assert_eq!(en.count(1000), "1,000");
+        assert_eq!(en.count(1234567), "1,234,567");
+        assert_eq!(UnitFormat::for_language("de-DE").count(12345), "12.345");
+        assert_eq!(UnitFormat::for_language("fr").count(12345), "12\u{202f}345");
+    }
+
+    #[test]
+    fn durations_pick_two_units() {
+        let zh = UnitFormat::for_language("zh-CN");
+        assert_eq!(zh.duration_ms(0), "0毫秒");
+        assert_eq!(zh.duration_ms(999), "999毫秒");
+        assert_eq!(zh.duration_ms(1000), "1.0秒");
+        assert_eq!(zh.duration_ms(9949), "9.9秒");
+        assert_eq!(zh.duration_ms(9999), "10秒");
+        assert_eq!(zh.duration_ms(59_999), "59秒");
+        assert_eq!(zh.duration_ms(60_000), "1分00秒");
+        assert_eq!(zh.duration_ms(3_599_000), "59分59秒");
+        assert_eq!(zh.duration_ms(3_600_000), "1小时00分");
+        assert_eq!(zh.duration(Duration::from_secs(25 * 3600 + 61)), "25小时01分");
+        assert_eq!(UnitFormat::for_language("de").duration_ms(4200), "4,2s");
+    }
+
+    #[test]
+    fn bytes_step_at_1024() {
+        let en = UnitFormat::for_language("en");
+        assert_eq!(en.bytes(0), "0 B");
+        assert_eq!(en.bytes(1023), "1023 B");
+        assert_eq!(en.bytes(1024), "1.0 KB");
+        assert_eq!(en.bytes(1024 * 1024 - 1), "1.0 MB");
+        assert_eq!(en.bytes(14_050_000), "13.4 MB");
+        assert_eq!(en.bytes(5 << 40), "5.0 TB");
+        assert_eq!(en.bytes(2048 << 40), "2048.0 TB");
+        assert_eq!(UnitFormat::for_language("de").bytes(14_050_000), "13,4 MB");
+    }
+}
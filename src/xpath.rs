@@ -0,0 +1,250 @@
+//! XPath 选择器：配置中以 `xpath:` 开头的选择器按 XPath 书写，解析前转换为等价的 CSS 选择器，
+//! 因此只支持能用 CSS 表达的那部分 XPath：
+//!
+//! - 路径：`//`（后代）、`/`（子元素）、`.//`、`|`（并集），每一步为标签名或 `*`
+//! - 谓词：`[@attr]`、`[@attr='值']`、`[contains(@attr,'值')]`、`[starts-with(@attr,'值')]`、`[not(...)]`、
+//!   `[n]`、`[last()]`，同一谓词中的多个条件可用 `and` 连接。`[n]` 和 `[last()]` 只能出现在一步的第一个谓词中：
+//!   `div[@class='x'][2]` 在 XPath 中是带该类名的第 2 个 div，CSS 的 `:nth-of-type` 只能按全部同名元素计数
+//! - 末尾的 `/text()` 和 `/@href` 会被忽略：标题和正文本来就取元素文本，章节链接本来就取 href
+//!
+//! 轴（`following-sibling::` 等）、`..`、按文本匹配（`[text()='...']`）等无法转换为 CSS 的写法会报错
+
+/// 选择器前缀
+pub(crate) const XPATH_PREFIX: &str = "xpath:";
+
+/// 把 XPath 表达式转换为 CSS 选择器
+pub(crate) fn to_css(xpath: &str) -> Result<String, String> {
+    let paths = split_outside(xpath, "|")
+        .into_iter()
+        .map(|path| path_to_css(path.trim()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("无法转换 XPath {}: {}", xpath, e))?;
+    Ok(paths.join(", "))
+}
+
+fn path_to_css(path: &str) -> Result<String, String> {
+    let mut path = path;
+    for suffix in ["/text()", "/@href"] {
+        if let Some(rest) = path.strip_suffix(suffix) {
+            path = rest;
+            break;
+        }
+    }
+    let path = path.strip_prefix('.').unwrap_or(path);
+    // 相对路径从文档任意位置开始匹配
+    let path = if path.starts_with('/') { path.to_string() } else { format!("//{}", path) };
+    let mut css = String::new();
+    let mut descendant = false;
+    let mut first = true;
+    for step in split_outside(&path, "/").into_iter().skip(1) {
+        if step.is_empty() {
+            if descendant {
+                return Err("路径中不能出现 ///".to_string());
+            }
+            descendant = true;
+            continue;
+        }
+        let step = step_to_css(step.trim())?;
+        if first {
+            css.push_str(&step);
+            // 以单个 / 开头的绝对路径，第一步只能是根元素
+            if !descendant {
+                css.push_str(":root");
+            }
+        } else {
+            css.push_str(if descendant { " " } else { " > " });
+            css.push_str(&step);
+        }
+        first = false;
+        descendant = false;
+    }
+    if first || descendant {
+        return Err("路径不完整".to_string());
+    }
+    Ok(css)
+}
+
+fn step_to_css(step: &str) -> Result<String, String> {
+    let (name, mut predicates) = match step.find('[') {
+        Some(i) => (step[..i].trim(), &step[i..]),
+        None => (step, ""),
+    };
+    let any = name == "*";
+    if name.is_empty() || !(any || name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')) {
+        return Err(format!("不支持的步骤 {}（只支持标签名或 *，不支持轴和函数）", step));
+    }
+    let mut css = name.to_string();
+    let mut first = true;
+    while !predicates.is_empty() {
+        let end = closing_bracket(predicates).ok_or_else(|| format!("谓词缺少 ]: {}", step))?;
+        let predicate = predicates[1..end].trim();
+        if !first && is_positional(predicate) {
+            return Err(format!(
+                "位置谓词 [{}] 写在其他谓词之后时按筛选后的结果计数，无法转换为 CSS，请移到 {} 的第一个谓词: {}",
+                predicate, name, step
+            ));
+        }
+        css.push_str(&predicate_to_css(predicate, any)?);
+        first = false;
+        predicates = predicates[end + 1..].trim_start();
+        if !predicates.is_empty() && !predicates.starts_with('[') {
+            return Err(format!("无法识别的步骤 {}", step));
+        }
+    }
+    Ok(css)
+}
+
+/// 谓词中含有 [n] 或 last() 这样按位置筛选的条件
+fn is_positional(predicate: &str) -> bool {
+    split_outside(predicate, " and ").into_iter().map(str::trim).any(|condition| {
+        condition.parse::<usize>().is_ok() || condition == "last()" || call_args(condition, "not").is_some_and(is_positional)
+    })
+}
+
+fn predicate_to_css(predicate: &str, any: bool) -> Result<String, String> {
+    let conditions = split_outside(predicate, " and ");
+    if conditions.len() > 1 {
+        return conditions.into_iter().map(|condition| predicate_to_css(condition.trim(), any)).collect();
+    }
+    if let Ok(position) = predicate.parse::<usize>() {
+        return Ok(if any { format!(":nth-child({})", position) } else { format!(":nth-of-type({})", position) });
+    }
+    if predicate == "last()" {
+        return Ok(if any { ":last-child" } else { ":last-of-type" }.to_string());
+    }
+    if let Some(inner) = call_args(predicate, "not") {
+        return Ok(format!(":not({})", predicate_to_css(inner.trim(), any)?));
+    }
+    for (function, operator) in [("contains", "*="), ("starts-with", "^=")] {
+        if let Some(args) = call_args(predicate, function) {
+            let args = split_outside(args, ",");
+            let [attr, value] = args.as_slice() else {
+                return Err(format!("{}() 需要两个参数: {}", function, predicate));
+            };
+            return Ok(format!("[{}{}{}]", attribute(attr)?, operator, quote(&literal(value)?)));
+        }
+    }
+    match split_outside(predicate, "=").as_slice() {
+        [attr] => Ok(format!("[{}]", attribute(attr)?)),
+        [attr, value] => Ok(format!("[{}={}]", attribute(attr)?, quote(&literal(value)?))),
+        _ => Err(format!("不支持的谓词 [{}]", predicate)),
+    }
+}
+
+/// `name(args)` 形式时返回 args
+fn call_args<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    text.strip_prefix(name)?.trim_start().strip_prefix('(')?.strip_suffix(')')
+}
+
+/// `@name` 形式的属性名
+fn attribute(text: &str) -> Result<&str, String> {
+    let name = text.trim().strip_prefix('@').ok_or_else(|| format!("只支持按属性匹配（@name），不支持 {}", text.trim()))?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("无效的属性名 {}", text.trim()));
+    }
+    Ok(name)
+}
+
+/// 单引号或双引号括起的字符串
+fn literal(text: &str) -> Result<String, String> {
+    let text = text.trim();
+    for quote in ['\'', '"'] {
+        if text.len() >= 2 && text.starts_with(quote) && text.ends_with(quote) {
+            return Ok(text[1..text.len() - 1].to_string());
+        }
+    }
+    Err(format!("属性值应写在引号中: {}", text))
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// text 以 [ 开头时，返回与之配对的 ] 的位置
+fn closing_bracket(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in text.bytes().enumerate() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, b'\'' | b'"') => quote = Some(c),
+            (None, b'[') => depth += 1,
+            (None, b']') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// 在引号、方括号和圆括号之外按 sep 切分
+fn split_outside<'a>(text: &'a str, sep: &str) -> Vec<&'a str> {
+    let bytes = text.as_bytes();
+    let mut parts = Vec::new();
+    let (mut depth, mut quote, mut start, mut i) = (0i32, None, 0, 0);
+    while i < bytes.len() {
+        let c = bytes[i];
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                b'\'' | b'"' => quote = Some(c),
+                b'[' | b'(' => depth += 1,
+                b']' | b')' => depth -= 1,
+                _ if depth == 0 && bytes[i..].starts_with(sep.as_bytes()) => {
+                    parts.push(&text[start..i]);
+                    i += sep.len();
+                    start = i;
+                    continue;
+                }
+                _ => {}
+            },
+        }
+        i += 1;
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_paths_and_predicates() {
+        assert_eq!(to_css("//div[@id='content']/p").unwrap(), r#"div[id="content"] > p"#);
+        assert_eq!(to_css(".//ul[contains(@class,'list')]//a/@href").unwrap(), r#"ul[class*="list"] a"#);
+        assert_eq!(to_css("/html/body//h1/text()").unwrap(), "html:root > body h1");
+        assert_eq!(to_css("//h1 | //h2[starts-with(@id, \"t\")]").unwrap(), r#"h1, h2[id^="t"]"#);
+        assert_eq!(to_css("//div[@data-x and not(@hidden)]").unwrap(), "div[data-x]:not([hidden])");
+    }
+
+    #[test]
+    fn positional_predicate_first_is_converted() {
+        assert_eq!(to_css("//div[2]/p").unwrap(), "div:nth-of-type(2) > p");
+        assert_eq!(to_css("//*[3]").unwrap(), "*:nth-child(3)");
+        assert_eq!(to_css("//li[last()][@class='x']").unwrap(), r#"li:last-of-type[class="x"]"#);
+        assert_eq!(to_css("//li[@class='x' and last()]").unwrap(), r#"li[class="x"]:last-of-type"#);
+        assert_eq!(to_css("//li[not(last())]").unwrap(), "li:not(:last-of-type)");
+    }
+
+    #[test]
+    fn positional_predicate_after_filter_is_rejected() {
+        for xpath in ["//div[@class='x'][2]", "//li[@id][last()]", "//li[@id][not(1)]", "//p[1][2]"] {
+            let error = to_css(xpath).unwrap_err();
+            assert!(error.contains("第一个谓词"), "{}: {}", xpath, error);
+        }
+    }
+
+    #[test]
+    fn unsupported_xpath_is_rejected() {
+        for xpath in ["//div/following-sibling::p", "//div/..", "//p[text()='x']", "//div[@class=x]", "//div[", "///p"] {
+            assert!(to_css(xpath).is_err(), "{}", xpath);
+        }
+    }
+}